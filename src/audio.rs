@@ -9,6 +9,10 @@ use phf::phf_map;
 use serde::{Deserialize, Serialize};
 use strum::{EnumIter, EnumString, IntoStaticStr};
 
+mod join;
+
+pub use join::{audio_duration, join_audio, AudioJoinError, AudioJoinErrorKind};
+
 pub type QualityMap = phf::Map<i8, AudioFormat>;
 
 static WAV_QUALITY_MAP: QualityMap = phf_map! {
@@ -46,7 +50,11 @@ mod internal {
     use std::error::Error;
     use std::fmt::{self, Display, Formatter};
 
-    use rodio::{decoder::DecoderError, PlayError, StreamError};
+    use rodio::{
+        cpal::{self, traits::HostTrait},
+        decoder::DecoderError,
+        DeviceTrait, DevicesError, OutputStreamHandle, PlayError, StreamError,
+    };
     use rodio::{Decoder, OutputStream, Sink};
     #[allow(unused)]
     pub fn play_borrowed_audio_blocking(buffer: &[u8]) -> Result<(), AudioError> {
@@ -54,8 +62,16 @@ mod internal {
     }
 
     pub fn play_owned_audio_blocking(buffer: Vec<u8>) -> Result<(), AudioError> {
+        play_owned_audio_blocking_on(buffer, None)
+    }
+
+    /// Play the audio on the output device with the given name, or on the default device if `device` is `None`.
+    pub fn play_owned_audio_blocking_on(
+        buffer: Vec<u8>,
+        device: Option<&str>,
+    ) -> Result<(), AudioError> {
         log::info!("Playing audio... ({} bytes)", buffer.len());
-        let (_stream, stream_handle) = OutputStream::try_default()?;
+        let (_stream, stream_handle) = open_output_stream(device)?;
         let sink = Sink::try_new(&stream_handle).unwrap();
         let cursor = std::io::Cursor::new(buffer);
         let source = Decoder::new(cursor)?;
@@ -65,6 +81,35 @@ mod internal {
         Ok(())
     }
 
+    /// The names of the available audio output devices.
+    pub fn output_device_names() -> Result<Vec<String>, AudioError> {
+        Ok(cpal::default_host()
+            .output_devices()?
+            // Devices whose names can not be determined can not be selected anyway
+            .filter_map(|device| device.name().ok())
+            .collect())
+    }
+
+    /// Open an output stream on the output device with the given name,
+    /// or on the default device if `device` is `None`.
+    ///
+    /// The names of the devices can be obtained by [`output_device_names`].
+    pub fn open_output_stream(
+        device: Option<&str>,
+    ) -> Result<(OutputStream, OutputStreamHandle), AudioError> {
+        let Some(name) = device else {
+            return Ok(OutputStream::try_default()?);
+        };
+        let device = cpal::default_host()
+            .output_devices()?
+            .find(|device| device.name().is_ok_and(|n| n == name))
+            .ok_or_else(|| AudioError {
+                kind: AudioErrorKind::Device,
+                source: Some(anyhow::anyhow!("no output device named {name:?}")),
+            })?;
+        Ok(OutputStream::try_from_device(&device)?)
+    }
+
     #[derive(Debug)]
     #[non_exhaustive]
     /// An error that can occur when trying to play audio
@@ -74,6 +119,7 @@ mod internal {
     ///     - Bad audio data (e.g. not a valid audio file)
     ///     - Unsupported audio format
     /// - Audio stream error
+    /// - The requested output device is not available
     pub struct AudioError {
         pub kind: AudioErrorKind,
         source: Option<anyhow::Error>,
@@ -96,6 +142,8 @@ mod internal {
     pub enum AudioErrorKind {
         Decoder,
         Stream,
+        /// The output device is not found or the devices can not be enumerated
+        Device,
         #[allow(unused)]
         Play,
     }
@@ -116,6 +164,7 @@ mod internal {
     impl_from_for_audio_error!(StreamError, Stream);
     impl_from_for_audio_error!(DecoderError, Decoder);
     impl_from_for_audio_error!(PlayError, Decoder);
+    impl_from_for_audio_error!(DevicesError, Device);
 
     #[cfg(feature = "python")]
     impl From<AudioError> for pyo3::PyErr {
@@ -147,7 +196,18 @@ pub static QUALITY_RANGE_MAP: phf::Map<&'static str, (i8, i8)> = phf_map! {
 /// Some endpoints only support a subset of these formats.
 #[cfg_attr(feature = "python", pyo3::pyclass)]
 #[derive(
-    Debug, Clone, Copy, Default, IntoStaticStr, EnumString, EnumIter, Deserialize, Serialize,
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Hash,
+    IntoStaticStr,
+    EnumString,
+    EnumIter,
+    Deserialize,
+    Serialize,
 )]
 #[non_exhaustive]
 pub enum AudioFormat {
@@ -294,6 +354,171 @@ impl AudioFormat {
             })
         }
     }
+
+    /// The parts of the name of the format, e.g. `riff`, `24khz`, `16bit`, `mono` and `pcm`
+    fn name_parts(self) -> std::str::Split<'static, char> {
+        Into::<&'static str>::into(self).split('-')
+    }
+
+    /// The sample rate in Hz.
+    ///
+    /// ```
+    /// use aspeak::AudioFormat;
+    ///
+    /// assert_eq!(AudioFormat::Riff24Khz16BitMonoPcm.sample_rate(), 24000);
+    /// assert_eq!(AudioFormat::Raw22050Hz16BitMonoPcm.sample_rate(), 22050);
+    /// ```
+    pub fn sample_rate(self) -> u32 {
+        self.name_parts()
+            .find_map(|part| match part.strip_suffix("khz") {
+                Some(khz) => khz.parse::<u32>().ok().map(|khz| khz * 1000),
+                None => part.strip_suffix("hz")?.parse().ok(),
+            })
+            .expect("every audio format has a sample rate")
+    }
+
+    /// The number of bits per sample, or `None` if the format doesn't specify it, e.g. MP3.
+    pub fn bit_depth(self) -> Option<u16> {
+        self.name_parts()
+            .find_map(|part| part.strip_suffix("bit")?.parse().ok())
+    }
+
+    /// The number of channels.
+    pub fn channels(self) -> u16 {
+        if self.name_parts().any(|part| part == "stereo") {
+            2
+        } else {
+            1
+        }
+    }
+
+    /// The codec of the audio.
+    pub fn codec(self) -> AudioCodec {
+        let name: &str = self.into();
+        if name.starts_with("amr-wb") {
+            return AudioCodec::AmrWb;
+        }
+        match self.name_parts().next_back() {
+            Some("pcm") => AudioCodec::Pcm,
+            Some("alaw") => AudioCodec::ALaw,
+            Some("mulaw") => AudioCodec::MuLaw,
+            Some("mp3") => AudioCodec::Mp3,
+            Some("opus") => AudioCodec::Opus,
+            Some("truesilk") => AudioCodec::TrueSilk,
+            _ => unreachable!("unknown codec of audio format {name}"),
+        }
+    }
+
+    /// The container of the audio.
+    ///
+    /// ```
+    /// use aspeak::{AudioCodec, AudioContainer, AudioFormat};
+    ///
+    /// let format = AudioFormat::Webm24Khz16BitMonoOpus;
+    /// assert_eq!(format.codec(), AudioCodec::Opus);
+    /// assert_eq!(format.container(), AudioContainer::Webm);
+    /// ```
+    pub fn container(self) -> AudioContainer {
+        match self.name_parts().next() {
+            Some("riff") => AudioContainer::Riff,
+            Some("ogg") => AudioContainer::Ogg,
+            Some("webm") => AudioContainer::Webm,
+            Some("amr") => AudioContainer::Amr,
+            Some("raw") => AudioContainer::Raw,
+            _ if self.codec() == AudioCodec::Mp3 => AudioContainer::Mp3,
+            // The opus formats without a container name are Ogg Opus
+            _ => AudioContainer::Ogg,
+        }
+    }
+
+    /// The bitrate in bits per second, or `None` if it is variable or unknown.
+    ///
+    /// ```
+    /// use aspeak::AudioFormat;
+    ///
+    /// assert_eq!(AudioFormat::Audio24Khz48KBitRateMonoMp3.bitrate(), Some(48000));
+    /// assert_eq!(AudioFormat::Riff24Khz16BitMonoPcm.bitrate(), Some(384000));
+    /// assert_eq!(AudioFormat::Ogg24Khz16BitMonoOpus.bitrate(), None);
+    /// ```
+    pub fn bitrate(self) -> Option<u32> {
+        let kbps = self.name_parts().find_map(|part| {
+            part.strip_suffix("kbitrate")
+                .or_else(|| part.strip_suffix("kbps"))?
+                .parse::<u32>()
+                .ok()
+        });
+        match kbps {
+            Some(kbps) => Some(kbps * 1000),
+            None if matches!(
+                self.codec(),
+                AudioCodec::Pcm | AudioCodec::ALaw | AudioCodec::MuLaw
+            ) =>
+            {
+                Some(self.sample_rate() * self.bit_depth()? as u32 * self.channels() as u32)
+            }
+            None => None,
+        }
+    }
+
+    /// The MIME type of the audio, e.g. for the `Content-Type` header.
+    ///
+    /// Audio without a container is `application/octet-stream`.
+    pub fn mime_type(self) -> &'static str {
+        match self.container() {
+            AudioContainer::Riff => "audio/wav",
+            AudioContainer::Mp3 => "audio/mpeg",
+            AudioContainer::Ogg => "audio/ogg",
+            AudioContainer::Webm => "audio/webm",
+            AudioContainer::Amr => "audio/amr-wb",
+            AudioContainer::Raw => "application/octet-stream",
+        }
+    }
+
+    /// The recommended file extension without the leading dot.
+    pub fn file_extension(self) -> &'static str {
+        match self.container() {
+            AudioContainer::Riff => "wav",
+            AudioContainer::Mp3 => "mp3",
+            AudioContainer::Ogg => "ogg",
+            AudioContainer::Webm => "webm",
+            AudioContainer::Amr => "amr",
+            AudioContainer::Raw if self.codec() == AudioCodec::Pcm => "pcm",
+            AudioContainer::Raw => "raw",
+        }
+    }
+}
+
+/// The codec of an [`AudioFormat`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum AudioCodec {
+    /// Linear PCM with signed little endian samples
+    Pcm,
+    /// G.711 A-law
+    ALaw,
+    /// G.711 μ-law
+    MuLaw,
+    Mp3,
+    Opus,
+    /// Adaptive Multi-Rate Wideband
+    AmrWb,
+    TrueSilk,
+}
+
+/// The container of an [`AudioFormat`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum AudioContainer {
+    /// RIFF WAVE, i.e. a `.wav` file
+    Riff,
+    /// A plain MP3 stream
+    Mp3,
+    Ogg,
+    Webm,
+    /// An AMR-WB file
+    Amr,
+    /// No container, just the encoded samples
+    Raw,
 }
 
 #[cfg(feature = "python")]
@@ -0,0 +1,485 @@
+//! Joining audio clips of the same [`AudioFormat`] into a single valid file.
+
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+    time::Duration,
+};
+
+use super::{AudioCodec, AudioContainer, AudioFormat};
+
+mod ogg;
+mod webm;
+
+/// Join multiple audio clips of the same [`AudioFormat`] into a single valid file.
+///
+/// This is useful when long text is synthesized by several requests.
+/// Naively appending the clips produces broken files for most formats, so this function
+///
+/// - rewrites the header of RIFF files,
+/// - concatenates the frames of raw formats,
+/// - drops repeated ID3 tags of MP3 files and repeated magic of AMR-WB files,
+/// - rewrites the serial numbers, sequence numbers and granule positions of Ogg pages,
+/// - rewrites the cluster timecodes of WebM files.
+///
+/// A single clip is returned unchanged.
+///
+/// Compressed clips are not re-encoded, so the encoder delay of every clip after the first one is kept:
+/// Ogg Opus clips contribute their pre-skip (typically 6.5ms) and MP3 clips their encoder padding.
+pub fn join_audio<T: AsRef<[u8]>>(
+    format: AudioFormat,
+    clips: &[T],
+) -> Result<Vec<u8>, AudioJoinError> {
+    use AudioFormat::*;
+    match clips {
+        [] => return Ok(Vec::new()),
+        [clip] => return Ok(clip.as_ref().to_vec()),
+        _ => {}
+    }
+    match format {
+        Riff8Khz8BitMonoALaw
+        | Riff8Khz8BitMonoMULaw
+        | Riff8Khz16BitMonoPcm
+        | Riff16Khz16BitMonoPcm
+        | Riff22050Hz16BitMonoPcm
+        | Riff24Khz16BitMonoPcm
+        | Riff44100Hz16BitMonoPcm
+        | Riff48Khz16BitMonoPcm => join_riff(clips),
+        Raw8Khz8BitMonoALaw
+        | Raw8Khz8BitMonoMULaw
+        | Raw8Khz16BitMonoPcm
+        | Raw16Khz16BitMonoPcm
+        | Raw22050Hz16BitMonoPcm
+        | Raw24Khz16BitMonoPcm
+        | Raw44100Hz16BitMonoPcm
+        | Raw48Khz16BitMonoPcm
+        | Raw16Khz16BitMonoTrueSilk
+        | Raw24Khz16BitMonoTrueSilk => Ok(clips
+            .iter()
+            .flat_map(|clip| clip.as_ref())
+            .copied()
+            .collect()),
+        Audio16Khz32KBitRateMonoMp3
+        | Audio16Khz64KBitRateMonoMp3
+        | Audio16Khz128KBitRateMonoMp3
+        | Audio24Khz48KBitRateMonoMp3
+        | Audio24Khz96KBitRateMonoMp3
+        | Audio24Khz160KBitRateMonoMp3
+        | Audio48Khz96KBitRateMonoMp3
+        | Audio48Khz192KBitRateMonoMp3 => Ok(join_mp3(clips)),
+        AmrWb16000Hz => join_with_magic(clips, AMR_WB_MAGIC),
+        // The opus formats without a container name are Ogg Opus as well.
+        Ogg16Khz16BitMonoOpus
+        | Ogg24Khz16BitMonoOpus
+        | Ogg48Khz16BitMonoOpus
+        | Audio16Khz16Bit32KbpsMonoOpus
+        | Audio24Khz16Bit24KbpsMonoOpus
+        | Audio24Khz16Bit48KbpsMonoOpus => ogg::join_ogg(clips),
+        Webm16Khz16BitMonoOpus | Webm24Khz16Bit24KbpsMonoOpus | Webm24Khz16BitMonoOpus => {
+            webm::join_webm(clips)
+        }
+    }
+}
+
+/// The duration of an audio clip of the given format, or `None` if it can not be determined.
+///
+/// The duration is computed from the size of the audio for uncompressed formats and MP3, which has a constant bitrate,
+/// and from the container for Ogg, WebM and AMR-WB.
+///
+/// ```
+/// use std::time::Duration;
+/// use aspeak::{audio_duration, AudioFormat};
+///
+/// // One second of 16 bit samples at 16kHz
+/// let audio = vec![0; 32000];
+/// assert_eq!(
+///     audio_duration(AudioFormat::Raw16Khz16BitMonoPcm, &audio),
+///     Some(Duration::from_secs(1))
+/// );
+/// ```
+pub fn audio_duration(format: AudioFormat, audio: &[u8]) -> Option<Duration> {
+    let from_bitrate = |len: usize| {
+        let bitrate = format.bitrate()? as u128;
+        Some(Duration::from_nanos(
+            (len as u128 * 8 * 1_000_000_000 / bitrate) as u64,
+        ))
+    };
+    match format.container() {
+        AudioContainer::Riff => from_bitrate(riff_data(audio, 0).ok()?.1.len()),
+        AudioContainer::Raw if format.codec() == AudioCodec::TrueSilk => None,
+        AudioContainer::Raw => from_bitrate(audio.len()),
+        AudioContainer::Mp3 => from_bitrate(
+            audio
+                .len()
+                .saturating_sub(id3v2_len(audio) + id3v1_len(audio)),
+        ),
+        AudioContainer::Ogg => ogg::duration(audio),
+        AudioContainer::Webm => webm::duration(audio),
+        AudioContainer::Amr => amr_wb_duration(audio),
+    }
+}
+
+fn invalid_data(index: usize, reason: impl Display) -> AudioJoinError {
+    AudioJoinError {
+        kind: AudioJoinErrorKind::InvalidData(format!("clip {index}: {reason}")),
+    }
+}
+
+const AMR_WB_MAGIC: &[u8] = b"#!AMR-WB\n";
+
+/// Keep the magic of the first clip and strip it from the rest.
+fn join_with_magic<T: AsRef<[u8]>>(clips: &[T], magic: &[u8]) -> Result<Vec<u8>, AudioJoinError> {
+    let mut buffer = Vec::new();
+    for (i, clip) in clips.iter().enumerate() {
+        let body = clip
+            .as_ref()
+            .strip_prefix(magic)
+            .ok_or_else(|| invalid_data(i, "missing magic"))?;
+        if i == 0 {
+            buffer.extend_from_slice(magic);
+        }
+        buffer.extend_from_slice(body);
+    }
+    Ok(buffer)
+}
+
+/// The size of the speech data of AMR-WB frames by frame type, `None` for reserved types.
+const AMR_WB_FRAME_SIZES: [Option<usize>; 16] = [
+    Some(17),
+    Some(23),
+    Some(32),
+    Some(36),
+    Some(40),
+    Some(46),
+    Some(50),
+    Some(58),
+    Some(60),
+    // SID
+    Some(5),
+    None,
+    None,
+    None,
+    None,
+    // Speech lost and no data
+    Some(0),
+    Some(0),
+];
+/// Every AMR-WB frame is 20ms long.
+const AMR_WB_FRAME_DURATION: Duration = Duration::from_millis(20);
+
+/// Count the frames of an AMR-WB file.
+fn amr_wb_duration(audio: &[u8]) -> Option<Duration> {
+    let mut frames = audio.strip_prefix(AMR_WB_MAGIC)?;
+    let mut count = 0;
+    while let Some((&header, rest)) = frames.split_first() {
+        let size = AMR_WB_FRAME_SIZES[(header >> 3 & 0x0F) as usize]?;
+        frames = rest.get(size..)?;
+        count += 1;
+    }
+    Some(AMR_WB_FRAME_DURATION * count)
+}
+
+/// The length of the ID3v2 tag at the start of an MP3 file, if any.
+fn id3v2_len(clip: &[u8]) -> usize {
+    match clip {
+        [b'I', b'D', b'3', _, _, flags, size @ ..] if size.len() >= 4 => {
+            // The size is a 28 bit synchsafe integer, excluding the 10 byte header and the footer.
+            let size = size[..4]
+                .iter()
+                .fold(0, |acc, &byte| (acc << 7) | (byte & 0x7F) as usize);
+            let footer = if flags & 0x10 != 0 { 10 } else { 0 };
+            (10 + size + footer).min(clip.len())
+        }
+        _ => 0,
+    }
+}
+
+/// The length of the ID3v1 tag at the end of an MP3 file, if any.
+fn id3v1_len(clip: &[u8]) -> usize {
+    if clip.len() >= 128 && clip[clip.len() - 128..].starts_with(b"TAG") {
+        128
+    } else {
+        0
+    }
+}
+
+/// Keep the leading ID3v2 tag of the first clip and the trailing ID3v1 tag of the last clip,
+/// and concatenate the frames.
+fn join_mp3<T: AsRef<[u8]>>(clips: &[T]) -> Vec<u8> {
+    let last = clips.len() - 1;
+    let mut buffer = Vec::with_capacity(clips.iter().map(|c| c.as_ref().len()).sum());
+    for (i, clip) in clips.iter().enumerate() {
+        let clip = clip.as_ref();
+        let start = if i == 0 { 0 } else { id3v2_len(clip) };
+        let end = if i == last {
+            clip.len()
+        } else {
+            clip.len() - id3v1_len(clip)
+        };
+        buffer.extend_from_slice(&clip[start..end.max(start)]);
+    }
+    buffer
+}
+
+/// Find the `data` chunk of a RIFF file, returning the header length and the data.
+fn riff_data(clip: &[u8], index: usize) -> Result<(usize, &[u8]), AudioJoinError> {
+    if clip.len() < 12 || &clip[..4] != b"RIFF" || &clip[8..12] != b"WAVE" {
+        return Err(invalid_data(index, "not a RIFF WAVE file"));
+    }
+    let mut pos = 12;
+    while pos + 8 <= clip.len() {
+        let id = &clip[pos..pos + 4];
+        let size = u32::from_le_bytes(clip[pos + 4..pos + 8].try_into().unwrap());
+        let start = pos + 8;
+        if id == b"data" {
+            // Streaming responses might not know the size of the data in advance.
+            let end = match size {
+                0 | u32::MAX => clip.len(),
+                size => start.saturating_add(size as usize).min(clip.len()),
+            };
+            return Ok((start, &clip[start..end]));
+        }
+        // Chunks are padded to an even size
+        pos = start
+            .saturating_add(size as usize)
+            .saturating_add(size as usize & 1);
+    }
+    Err(invalid_data(index, "missing data chunk"))
+}
+
+fn join_riff<T: AsRef<[u8]>>(clips: &[T]) -> Result<Vec<u8>, AudioJoinError> {
+    let first = clips[0].as_ref();
+    let (header_len, _) = riff_data(first, 0)?;
+    let mut buffer = first[..header_len].to_vec();
+    for (i, clip) in clips.iter().enumerate() {
+        buffer.extend_from_slice(riff_data(clip.as_ref(), i)?.1);
+    }
+    let data_len = buffer.len() - header_len;
+    let (riff_size, data_size) = match (u32::try_from(buffer.len() - 8), u32::try_from(data_len)) {
+        (Ok(riff_size), Ok(data_size)) => (riff_size, data_size),
+        _ => {
+            return Err(AudioJoinError {
+                kind: AudioJoinErrorKind::TooLarge,
+            })
+        }
+    };
+    buffer[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    buffer[header_len - 4..header_len].copy_from_slice(&data_size.to_le_bytes());
+    Ok(buffer)
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+/// An error that can occur in [`join_audio`].
+pub struct AudioJoinError {
+    pub kind: AudioJoinErrorKind,
+}
+
+impl Display for AudioJoinError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "audio join error: ")?;
+        match &self.kind {
+            AudioJoinErrorKind::UnsupportedFormat(format) => {
+                write!(
+                    f,
+                    "joining {} audio is not supported",
+                    Into::<&str>::into(format)
+                )
+            }
+            AudioJoinErrorKind::InvalidData(reason) => write!(f, "invalid audio data: {reason}"),
+            AudioJoinErrorKind::TooLarge => {
+                write!(f, "the joined audio is too large for its container")
+            }
+        }
+    }
+}
+
+impl Error for AudioJoinError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        None
+    }
+}
+
+#[cfg(feature = "python")]
+impl From<AudioJoinError> for pyo3::PyErr {
+    fn from(value: AudioJoinError) -> Self {
+        pyo3::exceptions::PyOSError::new_err(format!("{:?}", color_eyre::Report::from(value)))
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+#[non_exhaustive]
+pub enum AudioJoinErrorKind {
+    /// Joining audio of this format is not supported.
+    UnsupportedFormat(AudioFormat),
+    /// The audio data is not valid for its format.
+    InvalidData(String),
+    /// The joined audio exceeds the size limit of its container.
+    TooLarge,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A RIFF WAVE file with 16 bit mono samples at 16kHz
+    fn riff(data: &[u8]) -> Vec<u8> {
+        let mut clip = b"RIFF".to_vec();
+        clip.extend_from_slice(&(36 + data.len() as u32).to_le_bytes());
+        clip.extend_from_slice(b"WAVEfmt ");
+        clip.extend_from_slice(&16u32.to_le_bytes());
+        clip.extend_from_slice(&[1, 0, 1, 0]);
+        clip.extend_from_slice(&16000u32.to_le_bytes());
+        clip.extend_from_slice(&32000u32.to_le_bytes());
+        clip.extend_from_slice(&[2, 0, 16, 0]);
+        clip.extend_from_slice(b"data");
+        clip.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        clip.extend_from_slice(data);
+        clip
+    }
+
+    /// An ID3v2 tag with `size` bytes of content
+    fn id3v2(size: u8) -> Vec<u8> {
+        let mut tag = b"ID3\x04\x00\x00\x00\x00\x00".to_vec();
+        tag.push(size);
+        tag.resize(tag.len() + size as usize, 0);
+        tag
+    }
+
+    /// An ID3v1 tag
+    fn id3v1() -> Vec<u8> {
+        let mut tag = b"TAG".to_vec();
+        tag.resize(128, b' ');
+        tag
+    }
+
+    #[test]
+    fn single_clip_is_unchanged() {
+        let clip = b"not even audio".to_vec();
+        assert_eq!(
+            join_audio(AudioFormat::Riff16Khz16BitMonoPcm, &[&clip]).unwrap(),
+            clip
+        );
+    }
+
+    #[test]
+    fn riff_headers_are_rewritten() {
+        let joined = join_audio(
+            AudioFormat::Riff16Khz16BitMonoPcm,
+            &[riff(&[1, 2, 3, 4]), riff(&[5, 6])],
+        )
+        .unwrap();
+        assert_eq!(joined, riff(&[1, 2, 3, 4, 5, 6]));
+    }
+
+    #[test]
+    fn riff_with_unknown_data_size() {
+        let mut streamed = riff(&[5, 6]);
+        streamed[40..44].copy_from_slice(&u32::MAX.to_le_bytes());
+        let joined = join_audio(
+            AudioFormat::Riff16Khz16BitMonoPcm,
+            &[riff(&[1, 2]), streamed],
+        )
+        .unwrap();
+        assert_eq!(joined, riff(&[1, 2, 5, 6]));
+    }
+
+    #[test]
+    fn invalid_riff_is_rejected() {
+        let err = join_audio(
+            AudioFormat::Riff16Khz16BitMonoPcm,
+            &[riff(&[1, 2]), b"RIFF....WAVE".to_vec()],
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.kind,
+            AudioJoinErrorKind::InvalidData("clip 1: missing data chunk".to_string())
+        );
+    }
+
+    #[test]
+    fn mp3_tags_are_dropped_between_clips() {
+        let clip = |tag_size, frames: &[u8]| {
+            let mut clip = id3v2(tag_size);
+            clip.extend_from_slice(frames);
+            clip.extend_from_slice(&id3v1());
+            clip
+        };
+        let joined = join_audio(
+            AudioFormat::Audio24Khz48KBitRateMonoMp3,
+            &[clip(3, &[0xFF, 0xF3, 1]), clip(5, &[0xFF, 0xF3, 2])],
+        )
+        .unwrap();
+        let mut expected = id3v2(3);
+        expected.extend_from_slice(&[0xFF, 0xF3, 1, 0xFF, 0xF3, 2]);
+        expected.extend_from_slice(&id3v1());
+        assert_eq!(joined, expected);
+    }
+
+    #[test]
+    fn id3v2_size_is_synchsafe() {
+        // 0x01 0x00 is 128 in a synchsafe integer
+        let mut clip = b"ID3\x04\x00\x00\x00\x00\x01\x00".to_vec();
+        clip.resize(10 + 128 + 2, 0);
+        assert_eq!(id3v2_len(&clip), 138);
+        // With a footer
+        clip[5] = 0x10;
+        assert_eq!(id3v2_len(&clip), 140);
+    }
+
+    #[test]
+    fn amr_wb_magic_is_kept_once() {
+        let clip = |frame: u8| {
+            let mut clip = AMR_WB_MAGIC.to_vec();
+            clip.push(frame);
+            clip
+        };
+        let joined = join_audio(AudioFormat::AmrWb16000Hz, &[clip(1), clip(2)]).unwrap();
+        assert_eq!(joined, [AMR_WB_MAGIC, &[1, 2]].concat());
+        assert!(join_audio(AudioFormat::AmrWb16000Hz, &[clip(1), vec![2]]).is_err());
+    }
+
+    #[test]
+    fn raw_clips_are_concatenated() {
+        let joined = join_audio(AudioFormat::Raw16Khz16BitMonoPcm, &[[1, 2], [3, 4]]).unwrap();
+        assert_eq!(joined, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn riff_duration() {
+        let clip = riff(&[0; 16000]);
+        assert_eq!(
+            audio_duration(AudioFormat::Riff16Khz16BitMonoPcm, &clip),
+            Some(Duration::from_millis(500))
+        );
+    }
+
+    #[test]
+    fn mp3_duration_excludes_id3_tags() {
+        // One second at 32 kbit/s
+        let mut clip = id3v2(20);
+        clip.extend_from_slice(&[0xFF; 4000]);
+        assert_eq!(
+            audio_duration(AudioFormat::Audio16Khz32KBitRateMonoMp3, &clip),
+            Some(Duration::from_secs(1))
+        );
+    }
+
+    #[test]
+    fn amr_wb_duration_counts_frames() {
+        let mut clip = AMR_WB_MAGIC.to_vec();
+        for _ in 0..3 {
+            // Frame type 8, 23.85 kbit/s
+            clip.push(8 << 3 | 0x04);
+            clip.extend_from_slice(&[0; 60]);
+        }
+        // No data
+        clip.push(15 << 3);
+        assert_eq!(
+            audio_duration(AudioFormat::AmrWb16000Hz, &clip),
+            Some(Duration::from_millis(80))
+        );
+        clip.push(8 << 3);
+        assert_eq!(audio_duration(AudioFormat::AmrWb16000Hz, &clip), None);
+    }
+}
@@ -0,0 +1,345 @@
+//! Joining Ogg Opus streams.
+//!
+//! Every clip is a complete Ogg Opus stream with its own serial number,
+//! starting with an `OpusHead` and an `OpusTags` packet.
+//! The joined stream keeps the headers of the first clip, drops the headers of the others
+//! and rewrites the serial numbers, sequence numbers, granule positions and flags of all pages.
+//!
+//! The pre-skip of the later clips is not trimmed: only the `OpusHead` of the first clip is kept,
+//! so decoders discard its pre-skip only once, at the start of the joined stream.
+//! Trimming the pre-skip of later clips would require re-encoding, so every later clip
+//! contributes its encoder delay (typically 312 samples, i.e. 6.5ms) to the joined stream,
+//! which is inaudible at the sentence boundaries where clips are split.
+
+use std::time::Duration;
+
+use super::{invalid_data, AudioJoinError};
+
+const CAPTURE_PATTERN: &[u8] = b"OggS";
+const HEADER_LEN: usize = 27;
+const FLAG_BOS: u8 = 0x02;
+const FLAG_EOS: u8 = 0x04;
+/// Number of header packets at the start of an Ogg Opus stream(`OpusHead` and `OpusTags`).
+const OPUS_HEADER_PACKETS: usize = 2;
+/// Granule positions of Ogg Opus streams are in samples at 48kHz, regardless of the input sample rate.
+const OPUS_GRANULE_RATE: u64 = 48000;
+
+struct Page<'a> {
+    header_type: u8,
+    granule: i64,
+    segments: &'a [u8],
+    payload: &'a [u8],
+}
+
+impl Page<'_> {
+    /// Number of packets that end on this page.
+    fn completed_packets(&self) -> usize {
+        self.segments.iter().filter(|&&lacing| lacing < 255).count()
+    }
+
+    fn write(&self, buffer: &mut Vec<u8>, header_type: u8, granule: i64, serial: u32, seq: u32) {
+        let start = buffer.len();
+        buffer.extend_from_slice(CAPTURE_PATTERN);
+        buffer.push(0);
+        buffer.push(header_type);
+        buffer.extend_from_slice(&granule.to_le_bytes());
+        buffer.extend_from_slice(&serial.to_le_bytes());
+        buffer.extend_from_slice(&seq.to_le_bytes());
+        buffer.extend_from_slice(&[0; 4]);
+        buffer.push(self.segments.len() as u8);
+        buffer.extend_from_slice(self.segments);
+        buffer.extend_from_slice(self.payload);
+        let crc = crc32(&buffer[start..]);
+        buffer[start + 22..start + 26].copy_from_slice(&crc.to_le_bytes());
+    }
+}
+
+fn parse_pages(clip: &[u8], index: usize) -> Result<Vec<Page<'_>>, AudioJoinError> {
+    let mut pages = Vec::new();
+    let mut pos = 0;
+    while pos < clip.len() {
+        let header = clip
+            .get(pos..pos + HEADER_LEN)
+            .filter(|header| header.starts_with(CAPTURE_PATTERN))
+            .ok_or_else(|| invalid_data(index, format!("invalid ogg page at byte {pos}")))?;
+        let segment_count = header[26] as usize;
+        let segments = clip
+            .get(pos + HEADER_LEN..pos + HEADER_LEN + segment_count)
+            .ok_or_else(|| invalid_data(index, "truncated ogg page"))?;
+        let payload_start = pos + HEADER_LEN + segment_count;
+        let payload_len = segments
+            .iter()
+            .map(|&lacing| lacing as usize)
+            .sum::<usize>();
+        let payload = clip
+            .get(payload_start..payload_start + payload_len)
+            .ok_or_else(|| invalid_data(index, "truncated ogg page"))?;
+        pages.push(Page {
+            header_type: header[5],
+            granule: i64::from_le_bytes(header[6..14].try_into().unwrap()),
+            segments,
+            payload,
+        });
+        pos = payload_start + payload_len;
+    }
+    Ok(pages)
+}
+
+/// The duration of an Ogg Opus stream, i.e. the last granule position minus the pre-skip.
+pub(super) fn duration(clip: &[u8]) -> Option<Duration> {
+    let pages = parse_pages(clip, 0).ok()?;
+    let head = pages.first()?.payload;
+    if !head.starts_with(b"OpusHead") {
+        return None;
+    }
+    let pre_skip = u16::from_le_bytes(head.get(10..12)?.try_into().unwrap()) as u64;
+    let granule = pages.iter().rev().find(|page| page.granule != -1)?.granule;
+    let samples = u64::try_from(granule).ok()?.saturating_sub(pre_skip);
+    Some(Duration::from_nanos(
+        samples * 1_000_000_000 / OPUS_GRANULE_RATE,
+    ))
+}
+
+pub(super) fn join_ogg<T: AsRef<[u8]>>(clips: &[T]) -> Result<Vec<u8>, AudioJoinError> {
+    let first = clips[0].as_ref();
+    let serial = first
+        .get(14..18)
+        .map(|serial| u32::from_le_bytes(serial.try_into().unwrap()))
+        .ok_or_else(|| invalid_data(0, "truncated ogg page"))?;
+    let mut pages = Vec::new();
+    for (i, clip) in clips.iter().enumerate() {
+        let clip_pages = parse_pages(clip.as_ref(), i)?;
+        // Skip the header packets of all clips except the first one.
+        // Audio data always starts on a fresh page.
+        let mut skip = 0;
+        if i > 0 {
+            let mut packets = 0;
+            while packets < OPUS_HEADER_PACKETS {
+                packets += clip_pages
+                    .get(skip)
+                    .ok_or_else(|| invalid_data(i, "missing opus header pages"))?
+                    .completed_packets();
+                skip += 1;
+            }
+        }
+        pages.push(clip_pages.into_iter().skip(skip).collect::<Vec<_>>());
+    }
+    let total = pages.iter().map(Vec::len).sum::<usize>();
+    let mut buffer = Vec::with_capacity(clips.iter().map(|c| c.as_ref().len()).sum());
+    let mut seq = 0;
+    // Granule positions continue from the end of the previous clip.
+    let mut granule_offset = 0;
+    for clip_pages in pages {
+        let mut clip_end = granule_offset;
+        for page in clip_pages {
+            let mut header_type = page.header_type & !(FLAG_BOS | FLAG_EOS);
+            if seq == 0 {
+                header_type |= FLAG_BOS;
+            }
+            if seq as usize == total - 1 {
+                header_type |= FLAG_EOS;
+            }
+            // A granule position of -1 means that no packet ends on this page.
+            let granule = if page.granule == -1 {
+                -1
+            } else {
+                clip_end = granule_offset + page.granule;
+                clip_end
+            };
+            page.write(&mut buffer, header_type, granule, serial, seq);
+            seq += 1;
+        }
+        granule_offset = clip_end;
+    }
+    Ok(buffer)
+}
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// The CRC used by Ogg pages(unreflected, polynomial `0x04c11db7`, zero initial value).
+fn crc32(data: &[u8]) -> u32 {
+    data.iter().fold(0, |crc, &byte| {
+        (crc << 8) ^ CRC_TABLE[((crc >> 24) as u8 ^ byte) as usize]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRE_SKIP: u16 = 312;
+
+    /// An Ogg page with the given packets, the last of which may be continued on the next page
+    fn page(
+        header_type: u8,
+        granule: i64,
+        serial: u32,
+        seq: u32,
+        lacing: &[u8],
+        payload: &[u8],
+    ) -> Vec<u8> {
+        let mut page = CAPTURE_PATTERN.to_vec();
+        page.push(0);
+        page.push(header_type);
+        page.extend_from_slice(&granule.to_le_bytes());
+        page.extend_from_slice(&serial.to_le_bytes());
+        page.extend_from_slice(&seq.to_le_bytes());
+        page.extend_from_slice(&[0; 4]);
+        page.push(lacing.len() as u8);
+        page.extend_from_slice(lacing);
+        page.extend_from_slice(payload);
+        let crc = crc32(&page);
+        page[22..26].copy_from_slice(&crc.to_le_bytes());
+        page
+    }
+
+    fn opus_head() -> Vec<u8> {
+        let mut head = b"OpusHead\x01\x01".to_vec();
+        head.extend_from_slice(&PRE_SKIP.to_le_bytes());
+        head.extend_from_slice(&24000u32.to_le_bytes());
+        head.extend_from_slice(&[0, 0, 0]);
+        head
+    }
+
+    /// An Ogg Opus stream with two audio pages, which end at `granule / 2` and `granule`.
+    /// If `long_tags` is set, the `OpusTags` packet spans two pages.
+    fn clip(serial: u32, granule: i64, long_tags: bool) -> Vec<u8> {
+        let head = opus_head();
+        let mut clip = page(FLAG_BOS, 0, serial, 0, &[head.len() as u8], &head);
+        let mut seq = 1;
+        if long_tags {
+            let tags = [b"OpusTags".as_slice(), &[0; 292]].concat();
+            clip.extend(page(0, -1, serial, seq, &[255], &tags[..255]));
+            clip.extend(page(0, 0, serial, seq + 1, &[45], &tags[255..]));
+            seq += 2;
+        } else {
+            clip.extend(page(0, 0, serial, seq, &[8], b"OpusTags"));
+            seq += 1;
+        }
+        clip.extend(page(
+            0,
+            granule / 2,
+            serial,
+            seq,
+            &[2, 2],
+            &[serial as u8; 4],
+        ));
+        clip.extend(page(
+            FLAG_EOS,
+            granule,
+            serial,
+            seq + 1,
+            &[2],
+            &[serial as u8; 2],
+        ));
+        clip
+    }
+
+    /// `(header_type, granule, serial, seq, crc)` of every page
+    fn headers(stream: &[u8]) -> Vec<(u8, i64, u32, u32, u32)> {
+        let mut headers = Vec::new();
+        let mut pos = 0;
+        for page in parse_pages(stream, 0).unwrap() {
+            let header = &stream[pos..pos + HEADER_LEN];
+            headers.push((
+                page.header_type,
+                page.granule,
+                u32::from_le_bytes(header[14..18].try_into().unwrap()),
+                u32::from_le_bytes(header[18..22].try_into().unwrap()),
+                u32::from_le_bytes(header[22..26].try_into().unwrap()),
+            ));
+            pos += HEADER_LEN + page.segments.len() + page.payload.len();
+        }
+        headers
+    }
+
+    #[test]
+    fn crc_matches_the_ogg_specification() {
+        // The CRC-32 with polynomial 0x04c11db7, zero initial value and no final xor
+        assert_eq!(crc32(b"123456789"), 0x89A1_897F);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn pages_are_renumbered() {
+        let first = clip(1111, 960 * 4, false);
+        let joined = join_ogg(&[first.clone(), clip(2222, 960 * 2, true)]).unwrap();
+        // The first clip is kept as is
+        assert!(joined.starts_with(&first[..first.len() - 30]));
+        let headers = headers(&joined);
+        assert_eq!(
+            headers
+                .iter()
+                .map(|&(header_type, granule, serial, seq, _)| (header_type, granule, serial, seq))
+                .collect::<Vec<_>>(),
+            [
+                (FLAG_BOS, 0, 1111, 0),
+                (0, 0, 1111, 1),
+                (0, 960 * 2, 1111, 2),
+                (0, 960 * 4, 1111, 3),
+                // The header pages of the second clip are dropped
+                (0, 960 * 5, 1111, 4),
+                (FLAG_EOS, 960 * 6, 1111, 5),
+            ]
+        );
+    }
+
+    #[test]
+    fn crc_is_updated_after_rewrite() {
+        let joined = join_ogg(&[clip(1, 960, false), clip(2, 960, false)]).unwrap();
+        let mut pos = 0;
+        let pages = parse_pages(&joined, 0).unwrap();
+        let crcs = headers(&joined).into_iter().map(|header| header.4);
+        for (page, crc) in pages.iter().zip(crcs) {
+            let len = HEADER_LEN + page.segments.len() + page.payload.len();
+            let mut raw = joined[pos..pos + len].to_vec();
+            raw[22..26].fill(0);
+            assert_eq!(crc32(&raw), crc);
+            pos += len;
+        }
+        assert_eq!(pos, joined.len());
+    }
+
+    #[test]
+    fn continued_pages_keep_their_granule() {
+        let first = clip(1, 960, true);
+        let joined = join_ogg(&[first.clone(), first]).unwrap();
+        let granules = headers(&joined)
+            .into_iter()
+            .map(|header| header.1)
+            .collect::<Vec<_>>();
+        assert_eq!(granules, [0, -1, 0, 480, 960, 1440, 1920]);
+    }
+
+    #[test]
+    fn missing_headers_are_rejected() {
+        let head = opus_head();
+        let truncated = page(FLAG_BOS, 0, 1, 0, &[head.len() as u8], &head);
+        assert!(join_ogg(&[clip(1, 960, false), truncated]).is_err());
+        assert!(join_ogg(&[clip(1, 960, false), b"OggS".to_vec()]).is_err());
+    }
+
+    #[test]
+    fn duration_excludes_pre_skip() {
+        let stream = clip(1, 48000 + PRE_SKIP as i64, false);
+        assert_eq!(duration(&stream), Some(Duration::from_secs(1)));
+    }
+}
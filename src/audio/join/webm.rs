@@ -0,0 +1,408 @@
+//! Joining WebM files.
+//!
+//! The joined file keeps the EBML header, `Info` and `Tracks` of the first clip
+//! and the clusters of all clips, with their timecodes shifted so that every clip
+//! starts where the previous one ends.
+//! `SeekHead` and `Cues` are dropped because the positions in them are no longer valid.
+//! The duration in `Info` is dropped as well and the segment is written with an unknown size,
+//! just like a live stream.
+
+use std::time::Duration;
+
+use super::{invalid_data, AudioJoinError};
+
+const EBML_HEADER: u32 = 0x1A45_DFA3;
+const SEGMENT: u32 = 0x1853_8067;
+const INFO: u32 = 0x1549_A966;
+const DURATION: u32 = 0x4489;
+const TRACKS: u32 = 0x1654_AE6B;
+const CLUSTER: u32 = 0x1F43_B675;
+const TIMECODE: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+const BLOCK_GROUP: u32 = 0xA0;
+const BLOCK: u32 = 0xA1;
+/// Top level elements of a segment, which terminate clusters of unknown size.
+const SEGMENT_CHILDREN: &[u32] = &[
+    0x114D_9B74, // SeekHead
+    INFO,
+    TRACKS,
+    CLUSTER,
+    0x1C53_BB6B, // Cues
+    0x1941_A469, // Attachments
+    0x1043_A770, // Chapters
+    0x1254_C367, // Tags
+];
+/// The size of an element whose size is unknown, encoded as an 8 byte vint.
+const UNKNOWN_SIZE: [u8; 8] = [0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+/// Opus frames produced by the service are 20ms long.
+const DEFAULT_FRAME_DURATION: i64 = 20;
+
+struct Element<'a> {
+    id: u32,
+    /// The raw bytes of the element, including its header.
+    raw: &'a [u8],
+    /// The data of the element.
+    data: &'a [u8],
+}
+
+fn read_vint(data: &[u8], keep_marker: bool) -> Option<(u64, usize)> {
+    let first = *data.first()?;
+    let len = first.leading_zeros() as usize + 1;
+    if len > 8 || data.len() < len {
+        return None;
+    }
+    let mut value = if keep_marker {
+        first as u64
+    } else {
+        (first as u64) & (0xFF >> len)
+    };
+    for &byte in &data[1..len] {
+        value = (value << 8) | byte as u64;
+    }
+    Some((value, len))
+}
+
+/// Parse the elements in `data`.
+///
+/// Elements of unknown size extend to the next element whose id is in `terminators`
+/// or to the end of the data.
+fn parse_elements<'a>(
+    data: &'a [u8],
+    terminators: &[u32],
+    index: usize,
+) -> Result<Vec<Element<'a>>, AudioJoinError> {
+    let mut elements = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let (id, id_len) = read_vint(&data[pos..], true)
+            .filter(|(_, len)| *len <= 4)
+            .ok_or_else(|| invalid_data(index, format!("invalid EBML id at byte {pos}")))?;
+        let (size, size_len) = read_vint(&data[pos + id_len..], false)
+            .ok_or_else(|| invalid_data(index, format!("invalid EBML size at byte {pos}")))?;
+        let start = pos + id_len + size_len;
+        let end = if size == (1 << (7 * size_len)) - 1 {
+            find_terminator(data, start, terminators, index)?
+        } else {
+            usize::try_from(size)
+                .ok()
+                .and_then(|size| start.checked_add(size))
+                .filter(|end| *end <= data.len())
+                .ok_or_else(|| invalid_data(index, "truncated EBML element"))?
+        };
+        elements.push(Element {
+            id: id as u32,
+            raw: &data[pos..end],
+            data: &data[start..end],
+        });
+        pos = end;
+    }
+    Ok(elements)
+}
+
+/// Find the end of an element of unknown size that starts at `start`.
+fn find_terminator(
+    data: &[u8],
+    start: usize,
+    terminators: &[u32],
+    index: usize,
+) -> Result<usize, AudioJoinError> {
+    let mut pos = start;
+    while pos < data.len() {
+        let (id, id_len) = read_vint(&data[pos..], true)
+            .ok_or_else(|| invalid_data(index, format!("invalid EBML id at byte {pos}")))?;
+        if terminators.contains(&(id as u32)) {
+            return Ok(pos);
+        }
+        let (size, size_len) = read_vint(&data[pos + id_len..], false)
+            .ok_or_else(|| invalid_data(index, format!("invalid EBML size at byte {pos}")))?;
+        if size == (1 << (7 * size_len)) - 1 {
+            // A nested element of unknown size, e.g. a cluster of a live stream.
+            // Its data consists of elements as well, so continue with them.
+            pos += id_len + size_len;
+            continue;
+        }
+        pos = usize::try_from(size)
+            .ok()
+            .and_then(|size| (pos + id_len + size_len).checked_add(size))
+            .filter(|end| *end <= data.len())
+            .ok_or_else(|| invalid_data(index, "truncated EBML element"))?;
+    }
+    Ok(data.len())
+}
+
+fn read_uint(data: &[u8]) -> u64 {
+    data.iter().fold(0, |acc, &byte| (acc << 8) | byte as u64)
+}
+
+fn write_id(buffer: &mut Vec<u8>, id: u32) {
+    let bytes = id.to_be_bytes();
+    let skip = bytes.iter().take_while(|&&byte| byte == 0).count();
+    buffer.extend_from_slice(&bytes[skip..]);
+}
+
+fn write_size(buffer: &mut Vec<u8>, size: usize) {
+    // Always use 8 bytes for simplicity
+    buffer.push(0x01);
+    buffer.extend_from_slice(&(size as u64).to_be_bytes()[1..]);
+}
+
+fn write_element(buffer: &mut Vec<u8>, id: u32, data: &[u8]) {
+    write_id(buffer, id);
+    write_size(buffer, data.len());
+    buffer.extend_from_slice(data);
+}
+
+/// The relative timecode of a `SimpleBlock` or `Block`.
+fn block_timecode(block: &[u8]) -> Option<i64> {
+    let (_, track_len) = read_vint(block, false)?;
+    let timecode = block.get(track_len..track_len + 2)?;
+    Some(i16::from_be_bytes(timecode.try_into().unwrap()) as i64)
+}
+
+struct Clip<'a> {
+    segment: Vec<Element<'a>>,
+    /// `(timecode, children)` of every cluster
+    clusters: Vec<(i64, Vec<Element<'a>>)>,
+}
+
+impl<'a> Clip<'a> {
+    fn parse(data: &'a [u8], index: usize) -> Result<Self, AudioJoinError> {
+        let top = parse_elements(data, &[SEGMENT], index)?;
+        let segment = top
+            .iter()
+            .find(|e| e.id == SEGMENT)
+            .ok_or_else(|| invalid_data(index, "missing segment"))?;
+        let segment = parse_elements(segment.data, SEGMENT_CHILDREN, index)?;
+        let clusters = segment
+            .iter()
+            .filter(|e| e.id == CLUSTER)
+            .map(|cluster| {
+                let children = parse_elements(cluster.data, SEGMENT_CHILDREN, index)?;
+                let timecode = children
+                    .iter()
+                    .find(|e| e.id == TIMECODE)
+                    .map(|e| read_uint(e.data) as i64)
+                    .ok_or_else(|| invalid_data(index, "missing cluster timecode"))?;
+                Ok((timecode, children))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { segment, clusters })
+    }
+
+    /// The absolute timecodes of all blocks in this clip.
+    fn block_timecodes(&self) -> Vec<i64> {
+        self.clusters
+            .iter()
+            .flat_map(|(cluster_timecode, children)| {
+                children.iter().filter_map(move |e| {
+                    let block = match e.id {
+                        SIMPLE_BLOCK => e.data,
+                        BLOCK_GROUP => {
+                            parse_elements(e.data, &[], 0)
+                                .ok()?
+                                .into_iter()
+                                .find(|e| e.id == BLOCK)?
+                                .data
+                        }
+                        _ => return None,
+                    };
+                    Some(cluster_timecode + block_timecode(block)?)
+                })
+            })
+            .collect()
+    }
+
+    /// The end of the last block in this clip, estimated from the interval between blocks.
+    fn end(&self) -> i64 {
+        let timecodes = self.block_timecodes();
+        match timecodes.as_slice() {
+            [] => 0,
+            [last] => last + DEFAULT_FRAME_DURATION,
+            [.., previous, last] => last + (last - previous).max(1),
+        }
+    }
+}
+
+/// The duration of a WebM file, estimated from the timecodes of its blocks.
+pub(super) fn duration(clip: &[u8]) -> Option<Duration> {
+    let end = Clip::parse(clip, 0).ok()?.end();
+    // Timecodes are in milliseconds with the default timecode scale, which the service uses
+    Some(Duration::from_millis(u64::try_from(end).ok()?))
+}
+
+pub(super) fn join_webm<T: AsRef<[u8]>>(clips: &[T]) -> Result<Vec<u8>, AudioJoinError> {
+    let first = clips[0].as_ref();
+    let ebml_header = parse_elements(first, &[SEGMENT], 0)?
+        .into_iter()
+        .find(|e| e.id == EBML_HEADER)
+        .ok_or_else(|| invalid_data(0, "missing EBML header"))?
+        .raw;
+    let clips = clips
+        .iter()
+        .enumerate()
+        .map(|(i, clip)| Clip::parse(clip.as_ref(), i))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut buffer = Vec::with_capacity(first.len() * clips.len());
+    buffer.extend_from_slice(ebml_header);
+    write_id(&mut buffer, SEGMENT);
+    buffer.extend_from_slice(&UNKNOWN_SIZE);
+    for element in &clips[0].segment {
+        match element.id {
+            INFO => {
+                let mut info = Vec::with_capacity(element.data.len());
+                for child in parse_elements(element.data, &[], 0)? {
+                    if child.id != DURATION {
+                        info.extend_from_slice(child.raw);
+                    }
+                }
+                write_element(&mut buffer, INFO, &info);
+            }
+            TRACKS => buffer.extend_from_slice(element.raw),
+            _ => {}
+        }
+    }
+    let mut offset = 0;
+    for clip in &clips {
+        for (timecode, children) in &clip.clusters {
+            let mut cluster = Vec::new();
+            write_element(
+                &mut cluster,
+                TIMECODE,
+                &((timecode + offset) as u64).to_be_bytes(),
+            );
+            for child in children.iter().filter(|e| e.id != TIMECODE) {
+                cluster.extend_from_slice(child.raw);
+            }
+            write_element(&mut buffer, CLUSTER, &cluster);
+        }
+        offset += clip.end();
+    }
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMECODE_SCALE: u32 = 0x2A_D7B1;
+
+    fn element(id: u32, data: &[u8]) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        write_element(&mut buffer, id, data);
+        buffer
+    }
+
+    /// An element whose size is unknown, like the ones of live streams
+    fn unknown_size_element(id: u32, data: &[u8]) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        write_id(&mut buffer, id);
+        buffer.extend_from_slice(&UNKNOWN_SIZE);
+        buffer.extend_from_slice(data);
+        buffer
+    }
+
+    fn simple_block(timecode: i16, frame: u8) -> Vec<u8> {
+        let mut block = vec![0x81];
+        block.extend_from_slice(&timecode.to_be_bytes());
+        block.extend_from_slice(&[0x80, frame]);
+        element(SIMPLE_BLOCK, &block)
+    }
+
+    fn cluster(timecode: u8, blocks: &[(i16, u8)]) -> Vec<u8> {
+        let mut data = element(TIMECODE, &[timecode]);
+        for &(timecode, frame) in blocks {
+            data.extend(simple_block(timecode, frame));
+        }
+        data
+    }
+
+    /// A WebM file with one cluster for every item of `clusters`.
+    /// If `live` is set, the segment and the clusters have unknown sizes.
+    fn clip(clusters: &[(u8, &[(i16, u8)])], live: bool) -> Vec<u8> {
+        let mut segment = element(0x114D_9B74, b"seek");
+        let mut info = element(TIMECODE_SCALE, &1_000_000u32.to_be_bytes());
+        info.extend(element(DURATION, &1234f32.to_be_bytes()));
+        segment.extend(element(INFO, &info));
+        segment.extend(element(TRACKS, b"opus track"));
+        for &(timecode, blocks) in clusters {
+            let data = cluster(timecode, blocks);
+            segment.extend(if live {
+                unknown_size_element(CLUSTER, &data)
+            } else {
+                element(CLUSTER, &data)
+            });
+        }
+        segment.extend(element(0x1C53_BB6B, b"cues"));
+        let mut clip = element(EBML_HEADER, b"webm");
+        clip.extend(if live {
+            unknown_size_element(SEGMENT, &segment)
+        } else {
+            element(SEGMENT, &segment)
+        });
+        clip
+    }
+
+    #[test]
+    fn clusters_are_shifted() {
+        let joined = join_webm(&[
+            clip(&[(0, &[(0, 1), (20, 2)]), (40, &[(0, 3)])], false),
+            clip(&[(0, &[(0, 4), (20, 5)])], true),
+        ])
+        .unwrap();
+        let top = parse_elements(&joined, &[SEGMENT], 0).unwrap();
+        assert_eq!(top[0].raw, element(EBML_HEADER, b"webm"));
+        assert_eq!(top[1].id, SEGMENT);
+        // The segment of the joined file has an unknown size
+        assert_eq!(top[1].raw[4..12], UNKNOWN_SIZE);
+        let segment = parse_elements(top[1].data, SEGMENT_CHILDREN, 0).unwrap();
+        assert_eq!(
+            segment.iter().map(|e| e.id).collect::<Vec<_>>(),
+            [INFO, TRACKS, CLUSTER, CLUSTER, CLUSTER]
+        );
+        // The duration is dropped, the rest of the info is kept
+        assert_eq!(
+            segment[0].data,
+            element(TIMECODE_SCALE, &1_000_000u32.to_be_bytes())
+        );
+        let joined = Clip::parse(&joined, 0).unwrap();
+        assert_eq!(
+            joined
+                .clusters
+                .iter()
+                .map(|(timecode, _)| *timecode)
+                .collect::<Vec<_>>(),
+            // The first clip ends 20ms after its last block at 40ms
+            [0, 40, 60]
+        );
+        assert_eq!(joined.block_timecodes(), [0, 20, 40, 60, 80]);
+    }
+
+    #[test]
+    fn unknown_size_clusters_end_at_the_next_cluster() {
+        let live = clip(&[(0, &[(0, 1)]), (20, &[(0, 2), (20, 3)])], true);
+        let clip = Clip::parse(&live, 0).unwrap();
+        assert_eq!(clip.clusters.len(), 2);
+        assert_eq!(clip.block_timecodes(), [0, 20, 40]);
+        assert_eq!(duration(&live), Some(Duration::from_millis(60)));
+    }
+
+    #[test]
+    fn vint() {
+        assert_eq!(read_vint(&[0x81], false), Some((1, 1)));
+        assert_eq!(read_vint(&[0x40, 0x02], false), Some((2, 2)));
+        assert_eq!(
+            read_vint(&[0x1A, 0x45, 0xDF, 0xA3], true),
+            Some((0x1A45_DFA3, 4))
+        );
+        assert_eq!(read_vint(&UNKNOWN_SIZE, false), Some(((1 << 56) - 1, 8)));
+        assert_eq!(read_vint(&[0x00], false), None);
+        assert_eq!(read_vint(&[0x40], false), None);
+    }
+
+    #[test]
+    fn truncated_clips_are_rejected() {
+        let mut truncated = clip(&[(0, &[(0, 1)])], false);
+        truncated.truncate(truncated.len() - 3);
+        assert!(join_webm(&[clip(&[(0, &[(0, 1)])], false), truncated]).is_err());
+    }
+}
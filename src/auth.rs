@@ -1,7 +1,18 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, sync::Arc};
 
 use hyper::{header::HeaderName, http::HeaderValue};
 
+mod provider;
+mod token;
+
+#[cfg(feature = "external-credentials")]
+pub use provider::{CommandCredential, FileCredential};
+pub use provider::{
+    CredentialError, CredentialErrorKind, CredentialFuture, CredentialKind, CredentialProvider,
+    EnvCredential, StaticCredential,
+};
+pub use token::TokenIssuer;
+
 /// Options for authentication
 #[derive(Debug, Clone)]
 pub struct AuthOptions<'a> {
@@ -17,6 +28,8 @@ pub struct AuthOptions<'a> {
     pub(crate) headers: Cow<'a, [(HeaderName, HeaderValue)]>,
     /// Proxy server to use. Only http and socks5 proxy are supported by now.
     pub(crate) proxy: Option<Cow<'a, str>>,
+    /// Provider of the credentials that are used instead of `token` and `key`
+    pub(crate) credential_provider: Option<Arc<dyn CredentialProvider>>,
 }
 
 impl<'a> AuthOptions<'a> {
@@ -74,10 +87,46 @@ impl<'a> AuthOptions<'a> {
         &mut self.proxy
     }
 
+    /// Provider of the credentials that are used instead of `token` and `key`
+    pub fn credential_provider(&self) -> Option<&Arc<dyn CredentialProvider>> {
+        self.credential_provider.as_ref()
+    }
+
+    /// Provider of the credentials that are used instead of `token` and `key`
+    pub fn credential_provider_mut(&mut self) -> &mut Option<Arc<dyn CredentialProvider>> {
+        &mut self.credential_provider
+    }
+
+    /// The headers given by the credential provider, if any
+    #[cfg(feature = "websocket-synthesizer")]
+    pub(crate) async fn provided_headers(
+        &self,
+    ) -> Result<Vec<(HeaderName, HeaderValue)>, CredentialError> {
+        match &self.credential_provider {
+            Some(provider) => provider.headers().await,
+            None => Ok(Vec::new()),
+        }
+    }
+
     /// Create a builder for `AuthOptions`
     pub fn builder(endpoint: impl Into<Cow<'a, str>>) -> AuthOptionsBuilder<'a> {
         AuthOptionsBuilder::new(endpoint)
     }
+
+    /// Convert into an `AuthOptions` that owns all its data
+    pub fn into_owned(self) -> AuthOptions<'static> {
+        fn owned(cow: Cow<'_, str>) -> Cow<'static, str> {
+            Cow::Owned(cow.into_owned())
+        }
+        AuthOptions {
+            endpoint: owned(self.endpoint),
+            token: self.token.map(owned),
+            key: self.key.map(owned),
+            headers: Cow::Owned(self.headers.into_owned()),
+            proxy: self.proxy.map(owned),
+            credential_provider: self.credential_provider,
+        }
+    }
 }
 
 /// Builder for `AuthOptions`
@@ -87,6 +136,8 @@ pub struct AuthOptionsBuilder<'a> {
     key: Option<Cow<'a, str>>,
     headers: Cow<'a, [(HeaderName, HeaderValue)]>,
     proxy: Option<Cow<'a, str>>,
+    credential_provider: Option<Arc<dyn CredentialProvider>>,
+    token_issuer: Option<TokenIssuer>,
 }
 
 impl<'a> AuthOptionsBuilder<'a> {
@@ -95,8 +146,8 @@ impl<'a> AuthOptionsBuilder<'a> {
     /// # Arguments
     ///
     /// * `endpoint` - Endpoint of the service.
-    /// It is usually a https url if you are using [`crate::synthesizer::RestSynthesizer`] or
-    /// a wss url if you are using [`crate::synthesizer::WebsocketSynthesizer`].
+    ///   It is usually a https url if you are using [`crate::synthesizer::RestSynthesizer`] or
+    ///   a wss url if you are using [`crate::synthesizer::WebsocketSynthesizer`].
     pub fn new(endpoint: impl Into<Cow<'a, str>>) -> Self {
         Self {
             endpoint: endpoint.into(),
@@ -104,6 +155,8 @@ impl<'a> AuthOptionsBuilder<'a> {
             key: Default::default(),
             headers: Default::default(),
             proxy: Default::default(),
+            credential_provider: Default::default(),
+            token_issuer: Default::default(),
         }
     }
 
@@ -149,6 +202,35 @@ impl<'a> AuthOptionsBuilder<'a> {
         self
     }
 
+    /// Provider of the credentials that are used instead of `token` and `key`.
+    ///
+    /// It is asked for the request headers before each request.
+    pub fn credential_provider(mut self, provider: impl CredentialProvider + 'static) -> Self {
+        self.credential_provider = Some(Arc::new(provider));
+        self.token_issuer = None;
+        self
+    }
+
+    /// Provider of the credentials that are used instead of `token` and `key`.
+    pub fn optional_credential_provider(
+        mut self,
+        provider: Option<Arc<dyn CredentialProvider>>,
+    ) -> Self {
+        self.credential_provider = provider;
+        self.token_issuer = None;
+        self
+    }
+
+    /// Issuer of short-lived tokens that are used instead of `token` and `key`.
+    ///
+    /// The token is refreshed automatically before it expires.
+    /// Tokens are issued through the proxy of the `AuthOptions`.
+    pub fn token_issuer(mut self, token_issuer: TokenIssuer) -> Self {
+        self.token_issuer = Some(token_issuer);
+        self.credential_provider = None;
+        self
+    }
+
     /// Build `AuthOptions`
     pub fn build(self) -> AuthOptions<'a> {
         AuthOptions {
@@ -156,6 +238,10 @@ impl<'a> AuthOptionsBuilder<'a> {
             token: self.token,
             key: self.key,
             headers: self.headers,
+            credential_provider: match self.token_issuer {
+                Some(issuer) => Some(Arc::new(issuer.with_proxy(self.proxy.as_deref()))),
+                None => self.credential_provider,
+            },
             proxy: self.proxy,
         }
     }
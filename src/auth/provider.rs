@@ -0,0 +1,443 @@
+use std::{
+    error::Error,
+    fmt::{self, Debug, Display, Formatter},
+    future::{self, Future},
+    pin::Pin,
+};
+#[cfg(feature = "external-credentials")]
+use std::{
+    ffi::OsString,
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use hyper::{
+    header::{HeaderName, AUTHORIZATION},
+    http::HeaderValue,
+};
+#[cfg(feature = "external-credentials")]
+use log::debug;
+use strum::AsRefStr;
+#[cfg(feature = "external-credentials")]
+use tokio::process::Command;
+
+use super::TokenIssuer;
+
+/// The future returned by [`CredentialProvider::headers`]
+pub type CredentialFuture<'a> = Pin<
+    Box<dyn Future<Output = Result<Vec<(HeaderName, HeaderValue)>, CredentialError>> + Send + 'a>,
+>;
+
+/// A source of credentials that is asked for request headers before each request.
+///
+/// It is used by [`crate::synthesizer::SynthesizerConfig::rest_synthesizer`],
+/// [`crate::synthesizer::SynthesizerConfig::connect_websocket`]
+/// and [`crate::Voice::request_available_voices`].
+/// Implement it to get credentials from your own source, e.g. an Azure AD token:
+///
+/// ```
+/// use aspeak::{CredentialFuture, CredentialKind, CredentialProvider, StaticCredential};
+///
+/// #[derive(Debug)]
+/// struct AzureAdCredential;
+///
+/// impl CredentialProvider for AzureAdCredential {
+///     fn headers(&self) -> CredentialFuture<'_> {
+///         Box::pin(async move {
+///             let token = "token from Azure AD"; // Get the token asynchronously
+///             StaticCredential::from_credential(CredentialKind::BearerToken, token)?
+///                 .headers()
+///                 .await
+///         })
+///     }
+/// }
+/// ```
+pub trait CredentialProvider: Debug + Send + Sync {
+    /// The headers that authenticate the next request.
+    fn headers(&self) -> CredentialFuture<'_>;
+
+    /// Called when the server rejects the credentials, so that cached credentials can be discarded.
+    fn invalidate(&self) {}
+}
+
+/// The kind of a credential, which determines the header it is sent in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CredentialKind {
+    /// An Azure subscription key, sent in the `Ocp-Apim-Subscription-Key` header
+    SubscriptionKey,
+    /// A bearer token, sent in the `Authorization` header
+    BearerToken,
+}
+
+impl CredentialKind {
+    fn header(self, credential: &str) -> Result<(HeaderName, HeaderValue), CredentialError> {
+        let credential = credential.trim();
+        let (name, value) = match self {
+            CredentialKind::SubscriptionKey => (
+                HeaderName::from_static("ocp-apim-subscription-key"),
+                HeaderValue::from_str(credential),
+            ),
+            CredentialKind::BearerToken => (
+                AUTHORIZATION,
+                HeaderValue::from_str(&format!("Bearer {credential}")),
+            ),
+        };
+        let mut value = value.map_err(|e| CredentialError {
+            kind: CredentialErrorKind::InvalidCredential,
+            source: Some(e.into()),
+        })?;
+        value.set_sensitive(true);
+        Ok((name, value))
+    }
+}
+
+/// Credentials that never change
+#[derive(Clone)]
+pub struct StaticCredential {
+    headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl Debug for StaticCredential {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        // Don't leak the credentials into logs
+        f.debug_struct("StaticCredential").finish_non_exhaustive()
+    }
+}
+
+impl StaticCredential {
+    /// Send the given headers with every request
+    pub fn new(headers: Vec<(HeaderName, HeaderValue)>) -> Self {
+        Self { headers }
+    }
+
+    /// Send the credential of the given kind with every request
+    pub fn from_credential(
+        kind: CredentialKind,
+        credential: &str,
+    ) -> Result<Self, CredentialError> {
+        Ok(Self::new(vec![kind.header(credential)?]))
+    }
+}
+
+impl CredentialProvider for StaticCredential {
+    fn headers(&self) -> CredentialFuture<'_> {
+        Box::pin(future::ready(Ok(self.headers.clone())))
+    }
+}
+
+/// Reads the credential from an environment variable before each request
+#[derive(Debug, Clone)]
+pub struct EnvCredential {
+    var: std::ffi::OsString,
+    kind: CredentialKind,
+}
+
+impl EnvCredential {
+    /// Read the credential of the given kind from the environment variable `var`
+    pub fn new(var: impl Into<std::ffi::OsString>, kind: CredentialKind) -> Self {
+        Self {
+            var: var.into(),
+            kind,
+        }
+    }
+}
+
+impl CredentialProvider for EnvCredential {
+    fn headers(&self) -> CredentialFuture<'_> {
+        let headers = std::env::var(&self.var)
+            .map_err(|e| CredentialError {
+                kind: CredentialErrorKind::Env,
+                source: Some(e.into()),
+            })
+            .and_then(|credential| Ok(vec![self.kind.header(&credential)?]));
+        Box::pin(future::ready(headers))
+    }
+}
+
+/// Reads the credential from a file before each request
+///
+/// This is useful when another process rotates the credential in the file.
+/// Leading and trailing whitespace is ignored.
+#[cfg(feature = "external-credentials")]
+#[derive(Debug, Clone)]
+pub struct FileCredential {
+    path: PathBuf,
+    kind: CredentialKind,
+}
+
+#[cfg(feature = "external-credentials")]
+impl FileCredential {
+    /// Read the credential of the given kind from the file at `path`
+    pub fn new(path: impl Into<PathBuf>, kind: CredentialKind) -> Self {
+        Self {
+            path: path.into(),
+            kind,
+        }
+    }
+}
+
+#[cfg(feature = "external-credentials")]
+impl CredentialProvider for FileCredential {
+    fn headers(&self) -> CredentialFuture<'_> {
+        Box::pin(async move {
+            let credential =
+                tokio::fs::read_to_string(&self.path)
+                    .await
+                    .map_err(|e| CredentialError {
+                        kind: CredentialErrorKind::Io,
+                        source: Some(e.into()),
+                    })?;
+            Ok(vec![self.kind.header(&credential)?])
+        })
+    }
+}
+
+/// Gets the credential from the standard output of a command, e.g. a secrets manager CLI
+///
+/// The output is cached for [`CommandCredential::ttl`], which defaults to 5 minutes.
+/// Leading and trailing whitespace is ignored.
+#[cfg(feature = "external-credentials")]
+#[derive(Debug)]
+pub struct CommandCredential {
+    program: OsString,
+    args: Vec<OsString>,
+    kind: CredentialKind,
+    ttl: Duration,
+    cache: Mutex<Option<(String, Instant)>>,
+}
+
+#[cfg(feature = "external-credentials")]
+impl CommandCredential {
+    /// Run `program` with `args` to get the credential of the given kind
+    pub fn new(
+        program: impl Into<OsString>,
+        args: impl IntoIterator<Item = impl Into<OsString>>,
+        kind: CredentialKind,
+    ) -> Self {
+        Self {
+            program: program.into(),
+            args: args.into_iter().map(Into::into).collect(),
+            kind,
+            ttl: Duration::from_secs(5 * 60),
+            cache: Mutex::new(None),
+        }
+    }
+
+    /// How long the output of the command is cached
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Set how long the output of the command is cached. Use [`Duration::ZERO`] to disable caching.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    async fn run(&self) -> Result<String, CredentialError> {
+        debug!("Running credential command {:?}", self.program);
+        let output = Command::new(&self.program)
+            .args(&self.args)
+            .output()
+            .await
+            .map_err(|e| CredentialError {
+                kind: CredentialErrorKind::Command,
+                source: Some(e.into()),
+            })?;
+        if !output.status.success() {
+            return Err(CredentialError {
+                kind: CredentialErrorKind::Command,
+                source: Some(anyhow::anyhow!(
+                    "{:?} exited with {}: {}",
+                    self.program,
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )),
+            });
+        }
+        String::from_utf8(output.stdout).map_err(|e| CredentialError {
+            kind: CredentialErrorKind::InvalidCredential,
+            source: Some(e.into()),
+        })
+    }
+}
+
+#[cfg(feature = "external-credentials")]
+impl CredentialProvider for CommandCredential {
+    fn headers(&self) -> CredentialFuture<'_> {
+        Box::pin(async move {
+            if let Some((credential, fetched_at)) = self.cache.lock().unwrap().as_ref() {
+                if fetched_at.elapsed() < self.ttl {
+                    return Ok(vec![self.kind.header(credential)?]);
+                }
+            }
+            let credential = self.run().await?;
+            let header = self.kind.header(&credential)?;
+            *self.cache.lock().unwrap() = Some((credential, Instant::now()));
+            Ok(vec![header])
+        })
+    }
+
+    fn invalidate(&self) {
+        *self.cache.lock().unwrap() = None;
+    }
+}
+
+impl CredentialProvider for TokenIssuer {
+    fn headers(&self) -> CredentialFuture<'_> {
+        Box::pin(async move {
+            Ok(vec![
+                CredentialKind::BearerToken.header(&self.token().await?)?
+            ])
+        })
+    }
+
+    fn invalidate(&self) {
+        TokenIssuer::invalidate(self)
+    }
+}
+
+/// Errors that can occur while obtaining credentials.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct CredentialError {
+    pub kind: CredentialErrorKind,
+    pub(crate) source: Option<anyhow::Error>,
+}
+
+impl Display for CredentialError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        use CredentialErrorKind::*;
+        write!(f, "credential error: ")?;
+        match &self.kind {
+            Connect => write!(f, "error while connecting to the token endpoint"),
+            Command => write!(f, "the command that provides the credential failed"),
+            Unauthorized => write!(f, "the subscription key is rejected by the token endpoint"),
+            _ => write!(f, "{} error", self.kind.as_ref()),
+        }
+    }
+}
+
+impl Error for CredentialError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_ref().map(|e| e.as_ref() as _)
+    }
+}
+
+#[cfg(feature = "python")]
+impl From<CredentialError> for pyo3::PyErr {
+    fn from(value: CredentialError) -> Self {
+        pyo3::exceptions::PyOSError::new_err(format!("{:?}", color_eyre::Report::from(value)))
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, AsRefStr)]
+#[non_exhaustive]
+#[strum(serialize_all = "title_case")]
+pub enum CredentialErrorKind {
+    /// Failed to connect to the token endpoint.
+    Connect,
+    /// The token endpoint rejected the subscription key.
+    Unauthorized,
+    /// Other HTTP errors.
+    Http,
+    /// The credential can't be used in a request header.
+    InvalidCredential,
+    /// Failed to read the credential from an environment variable.
+    Env,
+    /// Failed to read the credential from a file.
+    Io,
+    /// The command that provides the credential failed.
+    Command,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(headers: &[(HeaderName, HeaderValue)]) -> &str {
+        headers[0].1.to_str().unwrap()
+    }
+
+    #[tokio::test]
+    async fn env_credential() {
+        let var = "ASPEAK_TEST_ENV_CREDENTIAL";
+        let provider = EnvCredential::new(var, CredentialKind::SubscriptionKey);
+        std::env::remove_var(var);
+        assert_eq!(
+            provider.headers().await.unwrap_err().kind,
+            CredentialErrorKind::Env
+        );
+        std::env::set_var(var, " key\n");
+        let headers = provider.headers().await.unwrap();
+        assert_eq!(headers[0].0, "ocp-apim-subscription-key");
+        assert_eq!(value(&headers), "key");
+        // The variable is read before each request
+        std::env::set_var(var, "rotated");
+        assert_eq!(value(&provider.headers().await.unwrap()), "rotated");
+        std::env::remove_var(var);
+    }
+
+    #[cfg(feature = "external-credentials")]
+    #[tokio::test]
+    async fn file_credential() {
+        let path = std::env::temp_dir().join(format!(
+            "aspeak-test-file-credential-{}",
+            std::process::id()
+        ));
+        let provider = FileCredential::new(&path, CredentialKind::BearerToken);
+        assert_eq!(
+            provider.headers().await.unwrap_err().kind,
+            CredentialErrorKind::Io
+        );
+        std::fs::write(&path, "token\n").unwrap();
+        let headers = provider.headers().await.unwrap();
+        assert_eq!(headers[0].0, AUTHORIZATION);
+        assert_eq!(value(&headers), "Bearer token");
+        // The file is read before each request
+        std::fs::write(&path, "rotated").unwrap();
+        assert_eq!(value(&provider.headers().await.unwrap()), "Bearer rotated");
+        std::fs::write(&path, "line\nbreak").unwrap();
+        assert_eq!(
+            provider.headers().await.unwrap_err().kind,
+            CredentialErrorKind::InvalidCredential
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(all(unix, feature = "external-credentials"))]
+    #[tokio::test]
+    async fn command_credential() {
+        let counter = std::env::temp_dir().join(format!(
+            "aspeak-test-command-credential-{}",
+            std::process::id()
+        ));
+        let script = format!(
+            r#"echo >> "{}"; wc -l < "{}""#,
+            counter.display(),
+            counter.display()
+        );
+        let provider =
+            CommandCredential::new("sh", ["-c", &script], CredentialKind::SubscriptionKey);
+        let credential = |headers: Vec<(HeaderName, HeaderValue)>| value(&headers).to_string();
+        // The output is cached for the ttl
+        assert_eq!(credential(provider.headers().await.unwrap()), "1");
+        assert_eq!(credential(provider.headers().await.unwrap()), "1");
+        provider.invalidate();
+        assert_eq!(credential(provider.headers().await.unwrap()), "2");
+
+        let provider = provider.with_ttl(Duration::ZERO);
+        assert_eq!(credential(provider.headers().await.unwrap()), "3");
+        assert_eq!(credential(provider.headers().await.unwrap()), "4");
+        std::fs::remove_file(&counter).unwrap();
+
+        let provider =
+            CommandCredential::new("sh", ["-c", "exit 1"], CredentialKind::SubscriptionKey);
+        assert_eq!(
+            provider.headers().await.unwrap_err().kind,
+            CredentialErrorKind::Command
+        );
+    }
+}
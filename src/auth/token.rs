@@ -0,0 +1,156 @@
+use std::{
+    fmt::{self, Debug, Formatter},
+    time::{Duration, Instant},
+};
+
+use super::{CredentialError, CredentialErrorKind};
+use crate::utils::ClientBuilderExt;
+use log::{debug, info};
+use reqwest::{Client, Proxy, StatusCode};
+use tokio::sync::Mutex;
+
+/// Tokens issued by the service are valid for 10 minutes.
+/// We refresh them a minute earlier to be safe.
+const TOKEN_REFRESH_INTERVAL: Duration = Duration::from_secs(9 * 60);
+
+/// Exchanges an Azure subscription key for short-lived bearer tokens at the `issueToken` endpoint.
+///
+/// The token is cached and refreshed automatically before it expires.
+/// It is a [`crate::CredentialProvider`].
+/// Use it with [`crate::AuthOptionsBuilder::token_issuer`] so that the synthesizers
+/// authenticate with tokens instead of the key,
+/// or call [`TokenIssuer::token`] to hand out tokens to your clients
+/// without revealing the key to them.
+///
+/// Used with [`crate::AuthOptionsBuilder::token_issuer`], it connects through the proxy of the [`crate::AuthOptions`].
+pub struct TokenIssuer {
+    endpoint: String,
+    key: String,
+    proxy: Option<String>,
+    /// A custom client that is used instead of a client built with `proxy`
+    client: Option<Client>,
+    cache: Mutex<Option<(String, Instant)>>,
+}
+
+impl Debug for TokenIssuer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        // Don't leak the key into logs
+        f.debug_struct("TokenIssuer")
+            .field("endpoint", &self.endpoint)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TokenIssuer {
+    /// Create a new [`TokenIssuer`] that exchanges the `key` for tokens at the `endpoint`.
+    ///
+    /// See [`crate::get_issue_token_endpoint_by_region`] for the official endpoints.
+    pub fn new(endpoint: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            key: key.into(),
+            proxy: None,
+            client: None,
+            cache: Mutex::new(None),
+        }
+    }
+
+    /// Create a new [`TokenIssuer`] that uses the official endpoint of the region(e.g. `eastus`).
+    pub fn from_region(region: &str, key: impl Into<String>) -> Self {
+        Self::new(crate::get_issue_token_endpoint_by_region(region), key)
+    }
+
+    /// Use a custom [`reqwest::Client`] instead of a client that connects through the proxy.
+    pub fn with_client(mut self, client: Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Connect through the proxy unless a custom client is used.
+    pub(super) fn with_proxy(mut self, proxy: Option<&str>) -> Self {
+        self.proxy = proxy.map(Into::into);
+        self
+    }
+
+    /// The endpoint that issues tokens
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    /// Get a valid token, issuing a new one if the cached token is about to expire.
+    ///
+    /// Concurrent callers wait for a single refresh instead of issuing tokens of their own.
+    pub async fn token(&self) -> Result<String, CredentialError> {
+        let mut cache = self.cache.lock().await;
+        if let Some((token, issued_at)) = cache.as_ref() {
+            if issued_at.elapsed() < TOKEN_REFRESH_INTERVAL {
+                return Ok(token.clone());
+            }
+        }
+        let token = self.issue_token().await?;
+        *cache = Some((token.clone(), Instant::now()));
+        Ok(token)
+    }
+
+    /// Discard the cached token so that a new token is issued on the next request.
+    pub fn invalidate(&self) {
+        // If the cache is locked, a new token is being issued anyway
+        if let Ok(mut cache) = self.cache.try_lock() {
+            *cache = None;
+        }
+    }
+
+    fn client(&self) -> Result<Client, CredentialError> {
+        if let Some(client) = &self.client {
+            return Ok(client.clone());
+        }
+        let connect_error = |e: reqwest::Error| CredentialError {
+            kind: CredentialErrorKind::Connect,
+            source: Some(e.into()),
+        };
+        Client::builder()
+            .no_proxy() // Disable default system proxy detection.
+            .optional_proxy(
+                self.proxy
+                    .as_deref()
+                    .map(Proxy::all)
+                    .transpose()
+                    .map_err(connect_error)?,
+            )
+            .build()
+            .map_err(connect_error)
+    }
+
+    async fn issue_token(&self) -> Result<String, CredentialError> {
+        debug!("Issuing a new token from {}", self.endpoint);
+        let token = self
+            .client()?
+            .post(&self.endpoint)
+            .header("Ocp-Apim-Subscription-Key", &self.key)
+            .header(reqwest::header::CONTENT_LENGTH, 0)
+            .send()
+            .await
+            .map_err(|e| CredentialError {
+                kind: CredentialErrorKind::Connect,
+                source: Some(e.into()),
+            })?
+            .error_for_status()
+            .map_err(|e| CredentialError {
+                kind: match e.status() {
+                    Some(StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) => {
+                        CredentialErrorKind::Unauthorized
+                    }
+                    _ => CredentialErrorKind::Http,
+                },
+                source: Some(e.into()),
+            })?
+            .text()
+            .await
+            .map_err(|e| CredentialError {
+                kind: CredentialErrorKind::Connect,
+                source: Some(e.into()),
+            })?;
+        info!("Successfully issued a new token");
+        Ok(token)
+    }
+}
@@ -0,0 +1,176 @@
+/// The default size budget of a chunk, in characters.
+///
+/// It is well below the limits of the Azure TTS API and
+/// typically yields a few minutes of audio per chunk.
+pub const DEFAULT_CHUNK_SIZE: usize = 3000;
+
+/// Characters that terminate a sentence when followed by whitespace.
+const SENTENCE_TERMINATORS: &[char] = &['.', '!', '?', ';', '…'];
+
+/// Characters that terminate a sentence on their own, e.g. full-width CJK punctuation.
+const FULL_WIDTH_SENTENCE_TERMINATORS: &[char] = &['。', '！', '？', '；'];
+
+/// Splits long text into chunks that can be synthesized by separate requests.
+///
+/// Each chunk contains at most [`TextSplitter::max_chars`] characters.
+/// The splitter prefers to break at paragraph boundaries, then at sentence boundaries
+/// and then at whitespace. Text without any of these is split at the size limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextSplitter {
+    max_chars: usize,
+}
+
+impl Default for TextSplitter {
+    fn default() -> Self {
+        Self::new(DEFAULT_CHUNK_SIZE)
+    }
+}
+
+impl TextSplitter {
+    /// Create a new [`TextSplitter`] that produces chunks of at most `max_chars` characters.
+    ///
+    /// `max_chars` is clamped to at least 1.
+    pub fn new(max_chars: usize) -> Self {
+        Self {
+            max_chars: max_chars.max(1),
+        }
+    }
+
+    /// The size budget of a chunk, in characters.
+    pub fn max_chars(&self) -> usize {
+        self.max_chars
+    }
+
+    /// Split the text into chunks.
+    ///
+    /// Leading and trailing whitespace of every chunk is trimmed and empty chunks are skipped.
+    pub fn split<'a>(&self, text: &'a str) -> Vec<&'a str> {
+        let mut chunks = Vec::new();
+        let mut rest = text.trim();
+        while !rest.is_empty() {
+            let end = match rest.char_indices().nth(self.max_chars) {
+                None => rest.len(),
+                Some((limit, _)) => find_break(rest, limit).unwrap_or(limit),
+            };
+            let (chunk, remaining) = rest.split_at(end);
+            let chunk = chunk.trim_end();
+            if !chunk.is_empty() {
+                chunks.push(chunk);
+            }
+            rest = remaining.trim_start();
+        }
+        chunks
+    }
+}
+
+/// Find the best position in `text[..limit]` to break the text at.
+///
+/// The returned position is always greater than zero so that the splitter makes progress.
+fn find_break(text: &str, limit: usize) -> Option<usize> {
+    let window = &text[..limit];
+    paragraph_break(window)
+        .or_else(|| sentence_break(text, limit))
+        .or_else(|| {
+            window
+                .char_indices()
+                .rev()
+                .find(|(_, c)| c.is_whitespace())
+                .map(|(pos, _)| pos)
+        })
+        .filter(|pos| *pos > 0)
+}
+
+/// The position of the last blank line in the window.
+fn paragraph_break(window: &str) -> Option<usize> {
+    let mut newlines = window.match_indices('\n').map(|(pos, _)| pos).rev();
+    let mut next = newlines.next()?;
+    for pos in newlines {
+        if window[pos + 1..next].trim().is_empty() && pos > 0 {
+            return Some(pos);
+        }
+        next = pos;
+    }
+    None
+}
+
+/// The position right after the last sentence terminator that ends before `limit`.
+fn sentence_break(text: &str, limit: usize) -> Option<usize> {
+    text[..limit].char_indices().rev().find_map(|(pos, c)| {
+        let end = pos + c.len_utf8();
+        let followed_by_whitespace = text[end..].chars().next().map_or(true, char::is_whitespace);
+        (FULL_WIDTH_SENTENCE_TERMINATORS.contains(&c)
+            || (SENTENCE_TERMINATORS.contains(&c) && followed_by_whitespace))
+            .then_some(end)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_text_is_a_single_chunk() {
+        let splitter = TextSplitter::default();
+        assert_eq!(splitter.split("  Hello, world!\n"), ["Hello, world!"]);
+        assert!(splitter.split(" \n\t ").is_empty());
+        assert_eq!(TextSplitter::new(0).max_chars(), 1);
+    }
+
+    #[test]
+    fn paragraphs_are_preferred() {
+        let text = "First paragraph. Still first.\n\nSecond paragraph.";
+        assert_eq!(
+            TextSplitter::new(40).split(text),
+            ["First paragraph. Still first.", "Second paragraph."]
+        );
+    }
+
+    #[test]
+    fn sentence_boundaries() {
+        assert_eq!(
+            TextSplitter::new(10).split("One. Two. Three."),
+            ["One. Two.", "Three."]
+        );
+        // A period inside a number does not end a sentence
+        assert_eq!(
+            TextSplitter::new(12).split("Pi is 3.14 and e is 2.72"),
+            ["Pi is 3.14", "and e is", "2.72"]
+        );
+    }
+
+    #[test]
+    fn cjk_punctuation() {
+        assert_eq!(
+            TextSplitter::new(4).split("你好。世界！再见。"),
+            ["你好。", "世界！", "再见。"]
+        );
+    }
+
+    #[test]
+    fn long_sentences() {
+        assert_eq!(
+            TextSplitter::new(12).split("a long sentence without any terminator"),
+            ["a long", "sentence", "without any", "terminator"]
+        );
+        // Text without whitespace is split at the size limit
+        assert_eq!(
+            TextSplitter::new(10).split(&"a".repeat(25)),
+            ["a".repeat(10), "a".repeat(10), "a".repeat(5)]
+        );
+    }
+
+    #[test]
+    fn chunk_size_is_counted_in_characters() {
+        assert_eq!(TextSplitter::new(2).split("ééééé"), ["éé", "éé", "é"]);
+        let text =
+            "Lorem ipsum dolor sit amet, consectetur adipiscing elit. 你好，世界。\n\n".repeat(20);
+        for max_chars in [1, 7, 30, 100] {
+            let chunks = TextSplitter::new(max_chars).split(&text);
+            assert!(chunks.iter().all(|c| c.chars().count() <= max_chars));
+            assert_eq!(
+                chunks.concat().split_whitespace().collect::<String>(),
+                text.split_whitespace().collect::<String>()
+            );
+        }
+    }
+}
@@ -1,18 +1,25 @@
 use clap::{ArgAction, Parser};
 use log::{debug, info, warn};
-use rodio::{Decoder, OutputStream, Sink};
+use rodio::{Decoder, Sink};
 
 use self::{
-    args::{AuthArgs, Color, InputArgs, ProfileArgs, SynthesizerMode, TextArgs},
+    args::{
+        AuthArgs, CacheArgs, Color, InputArgs, OutputArgs, ProfileArgs, SynthesizerMode,
+        TextOptionsArgs,
+    },
     commands::Command,
     config::{Config, TextConfig},
+    subtitles::SubtitleFormat,
+};
+use aspeak::{
+    get_default_voice_by_locale, open_output_stream, synthesizer::SynthesisEvent, RichSsmlOptions,
+    TextOptions,
 };
-use aspeak::{get_default_voice_by_locale, RichSsmlOptions, TextOptions};
 use std::{
     borrow::Cow,
     fs::{File, OpenOptions},
     io::{self, Cursor, Read, Write},
-    path::Path,
+    path::{Path, PathBuf},
 };
 
 use color_eyre::{
@@ -22,9 +29,14 @@ use color_eyre::{
 use encoding_rs_io::{DecodeReaderBytes, DecodeReaderBytesBuilder};
 
 pub(crate) mod args;
+pub(crate) mod batch;
+pub(crate) mod book;
 pub(crate) mod commands;
 pub(crate) mod config;
 mod parse;
+pub(crate) mod playback;
+pub(crate) mod serve;
+pub(crate) mod subtitles;
 
 #[derive(Parser, Debug)]
 #[command(author, version,
@@ -44,9 +56,13 @@ pub(crate) struct Cli {
     pub profile: ProfileArgs,
     #[command(flatten)]
     pub auth: AuthArgs,
+    #[command(flatten)]
+    pub cache: CacheArgs,
 }
 
 type OutputProcessor = Box<dyn FnOnce(Vec<u8>) -> color_eyre::Result<()> + Send>;
+pub(crate) type SubtitlesProcessor =
+    Box<dyn FnOnce(&[SynthesisEvent]) -> color_eyre::Result<()> + Send>;
 
 impl Cli {
     fn log_level_by_verbosity(verbosity: u8) -> log::LevelFilter {
@@ -60,9 +76,20 @@ impl Cli {
 
     pub(crate) fn get_synthesizer_mode(
         input_args: &InputArgs,
+        output_args: &OutputArgs,
         config: &Option<Config>,
-    ) -> SynthesizerMode {
-        input_args
+    ) -> color_eyre::Result<SynthesizerMode> {
+        if output_args.subtitles.is_some() {
+            // Subtitles are generated from the metadata that only the websocket API provides
+            return match input_args.mode {
+                Some(SynthesizerMode::Rest) => {
+                    Err(eyre!("Subtitles can not be generated in rest mode")
+                        .suggestion("Use --mode websocket or remove the --mode option."))
+                }
+                _ => Ok(SynthesizerMode::Websocket),
+            };
+        }
+        Ok(input_args
             .mode
             .or_else(|| {
                 config
@@ -70,7 +97,7 @@ impl Cli {
                     .and_then(|c| c.auth.as_ref())
                     .and_then(|a| a.mode)
             })
-            .unwrap_or(SynthesizerMode::Rest)
+            .unwrap_or(SynthesizerMode::Rest))
     }
     pub(crate) fn get_log_level(&self, verbosity_config: Option<u8>) -> log::LevelFilter {
         match self.verbose {
@@ -82,13 +109,19 @@ impl Cli {
     }
 
     pub(crate) fn process_input_text(args: &InputArgs) -> color_eyre::Result<String> {
-        let mut s = String::new();
-
         let file: Box<dyn io::Read> = match args.file.as_deref() {
             Some(file) if file != "-" => Box::new(File::open(file)?),
             _ => Box::new(io::stdin()),
         };
-        let mut decoder = if let Some(encoding) = args.encoding.as_deref() {
+        Self::decode_text(file, args.encoding.as_deref())
+    }
+
+    pub(crate) fn decode_text(
+        file: impl io::Read,
+        encoding: Option<&str>,
+    ) -> color_eyre::Result<String> {
+        let mut s = String::new();
+        let mut decoder = if let Some(encoding) = encoding {
             let encoding = encoding_rs::Encoding::for_label(encoding.as_bytes())
                 .ok_or(eyre!("Unsupported encoding: {encoding}"))?;
             DecodeReaderBytesBuilder::new()
@@ -104,30 +137,65 @@ impl Cli {
         Ok(s)
     }
 
+    pub(crate) fn create_output_file(file: &Path, overwrite: bool) -> color_eyre::Result<File> {
+        Ok(match (file.exists(), overwrite) {
+            (_, true) => File::create(file)?,
+            (false, false) => OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(file)?,
+            (true, false) => {
+                return Err(anyhow!("File {} already exists!", file.display())
+                    .suggestion("You can use --overwrite to overwrite this file."))
+            }
+        })
+    }
+
+    pub(crate) fn process_subtitles_output(
+        subtitles: Option<String>,
+        overwrite: bool,
+    ) -> color_eyre::Result<Option<SubtitlesProcessor>> {
+        subtitles
+            .map(|path| {
+                let path = PathBuf::from(path);
+                let format = SubtitleFormat::from_path(&path)?;
+                if path.exists() && !overwrite {
+                    return Err(anyhow!("File {} already exists!", path.display())
+                        .suggestion("You can use --overwrite to overwrite this file."));
+                }
+                // The file is only created after a successful synthesis
+                Ok::<SubtitlesProcessor, color_eyre::eyre::Report>(Box::new(
+                    move |events: &[SynthesisEvent]| {
+                        Self::create_output_file(&path, overwrite)?
+                            .write_all(format.render(events).as_bytes())?;
+                        Ok(())
+                    },
+                ))
+            })
+            .transpose()
+    }
+
     pub(crate) fn process_output(
         output: Option<String>,
         overwrite: bool,
+        device: Option<String>,
     ) -> color_eyre::Result<OutputProcessor> {
-        Ok(if let Some(file) = output.as_deref() {
-            let file = Path::new(file);
-            let mut file = match (file.exists(), overwrite) {
-                (_, true) => File::create(file)?,
-                (false, false) => OpenOptions::new()
-                    .read(true)
-                    .write(true)
-                    .create_new(true)
-                    .open(file)?,
-                (true, false) => {
-                    return Err(anyhow!("File {} already exists!", file.display())
-                        .suggestion("You can use --overwrite to overwrite this file."))
-                }
-            };
+        Ok(if output.as_deref() == Some("-") {
+            Box::new(|buffer| {
+                let mut stdout = io::stdout().lock();
+                stdout.write_all(&buffer)?;
+                stdout.flush()?;
+                Ok(())
+            })
+        } else if let Some(file) = output.as_deref() {
+            let mut file = Self::create_output_file(Path::new(file), overwrite)?;
             Box::new(move |buffer| {
                 file.write_all(&buffer)?;
                 Ok(())
             })
         } else {
-            Box::new(|buffer| {
+            Box::new(move |buffer| {
                 info!("Playing audio... ({} bytes)", buffer.len());
                 if buffer.is_empty()
                     || (
@@ -142,7 +210,7 @@ impl Cli {
                     warn!("Got empty audio buffer, nothing to play");
                     return Ok(());
                 }
-                let (_stream, stream_handle) = OutputStream::try_default()?;
+                let (_stream, stream_handle) = open_output_stream(device.as_deref())?;
                 let sink = Sink::try_new(&stream_handle).unwrap();
                 let cursor = Cursor::new(buffer);
                 let source = Decoder::new(cursor)?;
@@ -155,7 +223,7 @@ impl Cli {
     }
 
     pub(crate) fn process_text_options<'a>(
-        args: &'a TextArgs,
+        args: &'a TextOptionsArgs,
         config: Option<&'a TextConfig>,
     ) -> color_eyre::Result<TextOptions<'a>> {
         Ok(TextOptions::builder()
@@ -1,16 +1,20 @@
 use std::borrow::Cow;
 use std::env;
+use std::path::Path;
 
-use super::config::{AuthConfig, Config, OutputConfig};
+use super::config::{AuthConfig, Config, EndpointConfig, OutputConfig};
 use super::parse;
 use aspeak::{
-    get_rest_endpoint_by_region, get_websocket_endpoint_by_region, AudioFormat, AuthOptions, Role,
+    get_rest_endpoint_by_region, get_websocket_endpoint_by_region,
+    synthesizer::{SynthesisCache, DEFAULT_CACHE_SIZE},
+    AudioCodec, AudioContainer, AudioFormat, AuthOptions, Role,
 };
 use clap::{ArgAction, Args, ValueEnum};
-use color_eyre::Help;
+use color_eyre::{eyre::WrapErr, Help};
+use log::{debug, warn};
 use reqwest::header::{HeaderName, HeaderValue};
 use serde::{Deserialize, Serialize};
-use strum::{AsRefStr, Display};
+use strum::{AsRefStr, Display, IntoEnumIterator};
 
 #[derive(Debug, Clone, Copy, ValueEnum, PartialEq, Display)]
 #[strum(serialize_all = "kebab-case")]
@@ -31,6 +35,13 @@ pub(crate) enum ContainerFormat {
     Wav,
 }
 
+#[derive(Debug, Clone, Copy, Default, ValueEnum, PartialEq)]
+pub(crate) enum InputFormat {
+    #[default]
+    Plain,
+    Markdown,
+}
+
 #[derive(Args, Debug)]
 pub struct ProfileArgs {
     #[arg(long, action = ArgAction::SetTrue, help = "Do not use profile")]
@@ -49,6 +60,35 @@ impl ProfileArgs {
     }
 }
 
+#[derive(Args, Debug)]
+pub(crate) struct CacheArgs {
+    #[arg(
+        long,
+        global = true,
+        help = "Cache synthesized audio in this directory and reuse it for identical requests"
+    )]
+    cache_dir: Option<String>,
+    #[arg(
+        long,
+        global = true,
+        default_value_t = DEFAULT_CACHE_SIZE / 1024 / 1024,
+        help = "Maximum size of the cache in MiB. The least recently used audio is evicted beyond it"
+    )]
+    cache_size: u64,
+}
+
+impl CacheArgs {
+    pub(crate) fn open_cache(&self) -> color_eyre::Result<Option<SynthesisCache>> {
+        self.cache_dir
+            .as_deref()
+            .map(|dir| {
+                SynthesisCache::open(dir, self.cache_size.saturating_mul(1024 * 1024))
+                    .wrap_err_with(|| format!("Failed to open cache directory {dir}"))
+            })
+            .transpose()
+    }
+}
+
 #[derive(Args, Debug, Clone)]
 pub struct AuthArgs {
     #[arg(short, long, help = "Endpoint of TTS API")]
@@ -79,6 +119,45 @@ pub struct AuthArgs {
 }
 
 impl AuthArgs {
+    /// Look for the given url first,
+    /// then look for auth.voice_list_api in profile,
+    /// then try to determine the url by region
+    pub(crate) fn voice_list_url<'a>(
+        &'a self,
+        url: Option<&'a str>,
+        auth_config: Option<&'a AuthConfig>,
+    ) -> color_eyre::Result<Cow<'a, str>> {
+        url.map(Cow::Borrowed)
+            .or_else(|| {
+                auth_config.and_then(|a| a.voice_list_api.as_deref().map(Cow::Borrowed))
+            })
+            .or_else(|| {
+                self.region
+                    .as_deref()
+                    .or_else(|| {
+                        auth_config.and_then(|a| {
+                            a.endpoint_config.as_ref().and_then(|e| {
+                                if let EndpointConfig::Region { ref region } = e {
+                                    Some(region.as_str())
+                                } else {
+                                    None
+                                }
+                            })
+                        })
+                    })
+                    .map(|r| {
+                        Cow::Owned(format!(
+                            "https://{r}.tts.speech.microsoft.com/cognitiveservices/voices/list"
+                        ))
+                    })
+            })
+            .ok_or_else(
+                || color_eyre::eyre::eyre!("No voice list API url specified!")
+                    .with_note(|| "The default voice list API that is used in aspeak v4 has been shutdown and is no longer available.")
+                    .with_suggestion(|| "You can still use the list-voices command by specifying a region(authentication needed) or a custom voice list API url.")
+            )
+    }
+
     pub(crate) fn to_auth_options<'a>(
         &'a self,
         auth_config: Option<&'a AuthConfig>,
@@ -179,8 +258,123 @@ pub(crate) struct InputArgs {
 
 #[derive(Args, Debug, Default)]
 pub(crate) struct OutputArgs {
-    #[arg(short, long, help = "Output file path")]
+    #[arg(
+        short,
+        long,
+        help = "Output file path, or `-` to write the audio to stdout. \
+                If it is not specified, the audio is played. \
+                Unless a format or container format is specified, the container is inferred from the file extension"
+    )]
     pub output: Option<String>,
+    #[command(flatten)]
+    pub format_args: FormatArgs,
+    #[arg(long, action = ArgAction::SetTrue, help="Overwrite existing file")]
+    pub overwrite: bool,
+    #[arg(
+        long,
+        help = "Write subtitles to the given file. The format (SRT or WebVTT) is determined by the file extension (.srt or .vtt). \
+                This option requires the websocket mode."
+    )]
+    pub subtitles: Option<String>,
+    #[arg(
+        long,
+        help = "The audio output device to play the audio on. Run `aspeak list-devices` to list available devices"
+    )]
+    pub device: Option<String>,
+}
+
+impl OutputArgs {
+    /// The audio format, which is inferred from the extension of the output file
+    /// if neither a format nor a container format is specified on the command line.
+    pub(crate) fn get_audio_format(
+        &self,
+        config: Option<&OutputConfig>,
+    ) -> color_eyre::Result<AudioFormat> {
+        let format_args = &self.format_args;
+        let extension = self
+            .output
+            .as_deref()
+            .filter(|output| *output != "-")
+            .and_then(|output| Path::new(output).extension()?.to_str())
+            .map(str::to_ascii_lowercase)
+            .filter(|extension| AUDIO_EXTENSIONS.contains(&extension.as_str()));
+        if format_args.format.is_some() || format_args.container_format.is_some() {
+            let format = format_args.get_audio_format(config)?;
+            match extension {
+                Some(extension) if !matches_extension(format, &extension) => warn!(
+                    "The audio format {} does not match the extension .{extension} of the output file",
+                    Into::<&str>::into(format)
+                ),
+                _ => {}
+            }
+            return Ok(format);
+        }
+        let Some(extension) = extension else {
+            return format_args.get_audio_format(config);
+        };
+        // The format from the config is kept if it suits the extension
+        if let Some(format) = config.and_then(|c| c.format).filter(|format| {
+            format_args.quality.is_none() && matches_extension(*format, &extension)
+        }) {
+            return Ok(format);
+        }
+        let (quality, use_closest) = match (format_args.quality, config.and_then(|c| c.quality)) {
+            (Some(quality), _) => (quality, false),
+            (None, quality) => (quality.unwrap_or_default(), true),
+        };
+        let format = format_for_extension(&extension, quality as i8, use_closest)?;
+        debug!(
+            "Inferred audio format {} from the extension .{extension}",
+            Into::<&str>::into(format)
+        );
+        Ok(format)
+    }
+
+    /// The audio output device, the default device is used if it is `None`
+    pub(crate) fn device(&self, config: Option<&OutputConfig>) -> Option<String> {
+        self.device
+            .clone()
+            .or_else(|| config.and_then(|c| c.device.clone()))
+    }
+}
+
+/// The extensions of the output files from which the audio format can be inferred
+const AUDIO_EXTENSIONS: [&str; 7] = ["mp3", "ogg", "opus", "webm", "wav", "pcm", "amr"];
+
+/// Whether the format suits a file with the given extension
+fn matches_extension(format: AudioFormat, extension: &str) -> bool {
+    match extension {
+        "opus" => format.container() == AudioContainer::Ogg && format.codec() == AudioCodec::Opus,
+        extension => format.file_extension() == extension,
+    }
+}
+
+/// The audio format of the given quality for a file with the given audio file extension
+fn format_for_extension(
+    extension: &str,
+    quality: i8,
+    use_closest: bool,
+) -> color_eyre::Result<AudioFormat> {
+    Ok(match extension {
+        "amr" => AudioFormat::AmrWb16000Hz,
+        // Raw PCM has the same quality levels as WAV
+        "pcm" => {
+            let wav = AudioFormat::from_container_and_quality("wav", quality, use_closest)?;
+            AudioFormat::iter()
+                .find(|format| {
+                    format.container() == AudioContainer::Raw
+                        && format.codec() == AudioCodec::Pcm
+                        && format.sample_rate() == wav.sample_rate()
+                })
+                .expect("every WAV quality level has a raw PCM counterpart")
+        }
+        "opus" => AudioFormat::from_container_and_quality("ogg", quality, use_closest)?,
+        container => AudioFormat::from_container_and_quality(container, quality, use_closest)?,
+    })
+}
+
+#[derive(Args, Debug, Default)]
+pub(crate) struct FormatArgs {
     #[arg(
         short,
         long,
@@ -199,11 +393,9 @@ pub(crate) struct OutputArgs {
         help = "Set output audio format (experts only). Run `aspeak list-formats` to list available formats"
     )]
     pub format: Option<AudioFormat>,
-    #[arg(long, action = ArgAction::SetTrue, help="Overwrite existing file")]
-    pub overwrite: bool,
 }
 
-impl OutputArgs {
+impl FormatArgs {
     pub(crate) fn get_audio_format(
         &self,
         config: Option<&OutputConfig>,
@@ -271,6 +463,26 @@ pub(crate) struct TextArgs {
     #[clap(help = "The text to speak. \
                 If neither text nor input file is specified, the text will be read from stdin.")]
     pub text: Option<String>,
+    #[command(flatten)]
+    pub options: TextOptionsArgs,
+    #[arg(
+        long,
+        help = "Split long text into chunks of at most this many characters, \
+                synthesize them in separate requests and join the audio. Default to 3000"
+    )]
+    pub chunk_size: Option<usize>,
+    #[arg(
+        long,
+        default_value_t = InputFormat::Plain,
+        value_enum,
+        help = "Format of the input text. Markdown is spoken without code blocks and link urls, \
+                with emphasized headings and pauses between list items"
+    )]
+    pub input_format: InputFormat,
+}
+
+#[derive(Args, Debug, Default)]
+pub(crate) struct TextOptionsArgs {
     #[arg(short, long, value_parser = parse_pitch,
         help="Set pitch, default to 0. \
               Valid values include floats(will be converted to percentages), \
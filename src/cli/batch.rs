@@ -0,0 +1,536 @@
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::{hash_map::Entry, HashMap, HashSet},
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use aspeak::{
+    synthesizer::{SynthesisCache, SynthesizerConfig, UnifiedSynthesizer},
+    AudioFormat, AuthOptions, RichSsmlOptions, TextOptions, TextSplitter,
+};
+use color_eyre::eyre::{eyre, WrapErr};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{args::SynthesizerMode, parse};
+use crate::synthesizer_by_mode;
+
+#[derive(Debug)]
+pub(crate) enum BatchInput {
+    Text(String),
+    Ssml(String),
+}
+
+/// An item of the manifest
+#[derive(Debug)]
+pub(crate) struct BatchItem {
+    /// The item as it is written in the manifest, which is used to detect changes
+    value: Value,
+    input: BatchInput,
+    /// The output path as it is written in the manifest, which identifies the item
+    output: String,
+    path: PathBuf,
+    voice: Option<String>,
+    rate: Option<String>,
+    pitch: Option<String>,
+    style: Option<String>,
+    format: Option<AudioFormat>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManifestLine {
+    text: Option<String>,
+    ssml: Option<String>,
+    output: String,
+    voice: Option<String>,
+    rate: Option<String>,
+    pitch: Option<String>,
+    style: Option<String>,
+    format: Option<AudioFormat>,
+}
+
+impl BatchItem {
+    fn parse(value: Value, base: &Path) -> color_eyre::Result<Self> {
+        let line: ManifestLine = serde_json::from_value(value.clone())?;
+        let input = match (line.text, line.ssml) {
+            (Some(text), None) => BatchInput::Text(text),
+            (None, Some(ssml)) => BatchInput::Ssml(ssml),
+            _ => {
+                return Err(eyre!(
+                    "Exactly one of `text` and `ssml` should be specified"
+                ))
+            }
+        };
+        Ok(Self {
+            value,
+            input,
+            path: base.join(&line.output),
+            output: line.output,
+            voice: line.voice,
+            rate: line
+                .rate
+                .as_deref()
+                .map(parse::parse_rate)
+                .transpose()?
+                .map(Cow::into_owned),
+            pitch: line
+                .pitch
+                .as_deref()
+                .map(parse::parse_pitch)
+                .transpose()?
+                .map(Cow::into_owned),
+            style: line.style,
+            format: line.format,
+        })
+    }
+
+    /// The text options of this item, which override the given defaults
+    fn text_options<'a>(&'a self, defaults: &TextOptions<'a>) -> TextOptions<'a> {
+        let mut options = defaults.clone();
+        if let Some(voice) = self.voice.as_deref() {
+            *options.voice_mut() = Cow::Borrowed(voice);
+        }
+        if let Some(rate) = self.rate.as_deref() {
+            *options.rate_mut() = Some(Cow::Borrowed(rate));
+        }
+        if let Some(pitch) = self.pitch.as_deref() {
+            *options.pitch_mut() = Some(Cow::Borrowed(pitch));
+        }
+        if let Some(style) = self.style.as_deref() {
+            *options
+                .rich_ssml_options_mut()
+                .get_or_insert_with(RichSsmlOptions::default)
+                .style_mut() = Some(Cow::Borrowed(style));
+        }
+        options
+    }
+
+    /// The settings the item is synthesized with in the given context
+    fn settings(&self, context: &BatchContext<'_>) -> ItemSettings {
+        let format = self.format.unwrap_or(context.audio_format);
+        // The text options are not used for SSML
+        let BatchInput::Text(_) = self.input else {
+            return ItemSettings {
+                format,
+                ..Default::default()
+            };
+        };
+        let options = self.text_options(&context.text_options);
+        let rich_ssml_options = options.rich_ssml_options().as_ref();
+        ItemSettings {
+            format,
+            voice: Some(options.voice().to_string()),
+            rate: options.rate().map(ToString::to_string),
+            pitch: options.pitch().map(ToString::to_string),
+            style: rich_ssml_options
+                .and_then(|o| o.style())
+                .map(ToString::to_string),
+            role: rich_ssml_options
+                .and_then(|o| o.role())
+                .map(|role| <&str>::from(role).to_string()),
+            style_degree: rich_ssml_options.and_then(|o| o.style_degree()),
+        }
+    }
+}
+
+/// Read the items of a JSON Lines manifest.
+///
+/// Relative output paths are resolved against the directory of the manifest.
+pub(crate) fn load_manifest(path: &Path) -> color_eyre::Result<Vec<BatchItem>> {
+    let base = path.parent().unwrap_or(Path::new(""));
+    let manifest = fs::read_to_string(path)
+        .wrap_err_with(|| format!("Failed to read manifest {}", path.display()))?;
+    let mut outputs = HashSet::new();
+    manifest
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            let item = serde_json::from_str(line)
+                .map_err(color_eyre::Report::from)
+                .and_then(|value| BatchItem::parse(value, base))
+                .wrap_err_with(|| format!("Invalid item on line {} of the manifest", i + 1))?;
+            if !outputs.insert(item.output.clone()) {
+                return Err(eyre!(
+                    "Output {} on line {} is used by another item",
+                    item.output,
+                    i + 1
+                ));
+            }
+            Ok(item)
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ItemStatus {
+    Done,
+    Failed,
+}
+
+/// The effective settings of an item, i.e. the item merged with the options of the command line
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct ItemSettings {
+    format: AudioFormat,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    voice: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rate: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pitch: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    style: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    role: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    style_degree: Option<f32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StateEntry {
+    output: String,
+    status: ItemStatus,
+    item: Value,
+    /// Missing in the entries written by older versions
+    #[serde(default)]
+    settings: Option<ItemSettings>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// The status of every item, persisted in a JSON Lines file.
+///
+/// Every status change is appended to the file so that it survives interruptions.
+/// The latest entry of an item wins.
+pub(crate) struct BatchState {
+    file: File,
+    entries: HashMap<String, StateEntry>,
+}
+
+impl BatchState {
+    pub fn open(path: &Path) -> color_eyre::Result<Self> {
+        let mut entries = HashMap::new();
+        if path.exists() {
+            for (i, line) in fs::read_to_string(path)?.lines().enumerate() {
+                match serde_json::from_str::<StateEntry>(line) {
+                    Ok(entry) => {
+                        entries.insert(entry.output.clone(), entry);
+                    }
+                    // e.g. a line that was partially written when the last run was interrupted
+                    Err(e) => warn!(
+                        "Ignoring line {} of state file {}: {e}",
+                        i + 1,
+                        path.display()
+                    ),
+                }
+            }
+            // Compact the state file so that it doesn't grow with every run
+            let partial = path.with_extension("part");
+            let mut compacted = String::new();
+            for entry in entries.values() {
+                compacted.push_str(&serde_json::to_string(entry)?);
+                compacted.push('\n');
+            }
+            fs::write(&partial, compacted)?;
+            fs::rename(&partial, path)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { file, entries })
+    }
+
+    /// Whether the item has been synthesized with the same settings
+    /// and neither the item nor its output has changed since.
+    pub fn is_done(&self, item: &BatchItem, context: &BatchContext<'_>) -> bool {
+        self.entries.get(&item.output).is_some_and(|entry| {
+            entry.status == ItemStatus::Done
+                && entry.item == item.value
+                && entry.settings.as_ref() == Some(&item.settings(context))
+                && item.path.exists()
+        })
+    }
+
+    fn record(
+        &mut self,
+        item: &BatchItem,
+        context: &BatchContext<'_>,
+        error: Option<String>,
+    ) -> color_eyre::Result<()> {
+        let entry = StateEntry {
+            output: item.output.clone(),
+            status: if error.is_some() {
+                ItemStatus::Failed
+            } else {
+                ItemStatus::Done
+            },
+            item: item.value.clone(),
+            settings: Some(item.settings(context)),
+            error,
+        };
+        writeln!(self.file, "{}", serde_json::to_string(&entry)?)?;
+        self.file.flush()?;
+        self.entries.insert(entry.output.clone(), entry);
+        Ok(())
+    }
+}
+
+/// Settings shared by every item of a batch
+pub(crate) struct BatchContext<'a> {
+    pub auth_options: AuthOptions<'a>,
+    pub mode: SynthesizerMode,
+    pub cache: Option<SynthesisCache>,
+    pub audio_format: AudioFormat,
+    pub text_options: TextOptions<'a>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct BatchSummary {
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+/// Synthesize the items with `concurrency` workers and record their status.
+///
+/// Every worker has its own synthesizers, i.e. connections in websocket mode.
+pub(crate) async fn run(
+    items: &[&BatchItem],
+    state: &mut BatchState,
+    context: &BatchContext<'_>,
+    concurrency: usize,
+) -> color_eyre::Result<BatchSummary> {
+    let queue = RefCell::new(items.iter().copied().enumerate());
+    let state = RefCell::new(state);
+    let workers = (0..concurrency.max(1)).map(|_| worker(&queue, &state, context, items.len()));
+    let mut summary = BatchSummary::default();
+    for result in futures_util::future::join_all(workers).await {
+        let worker_summary = result?;
+        summary.succeeded += worker_summary.succeeded;
+        summary.failed += worker_summary.failed;
+    }
+    Ok(summary)
+}
+
+async fn worker<'a>(
+    queue: &RefCell<impl Iterator<Item = (usize, &'a BatchItem)>>,
+    state: &RefCell<&mut BatchState>,
+    context: &BatchContext<'_>,
+    total: usize,
+) -> color_eyre::Result<BatchSummary> {
+    let mut synthesizers = HashMap::new();
+    let mut summary = BatchSummary::default();
+    loop {
+        let Some((i, item)) = queue.borrow_mut().next() else {
+            break;
+        };
+        info!("Synthesizing item {}/{}: {}", i + 1, total, item.output);
+        let format = item.format.unwrap_or(context.audio_format);
+        let result = synthesize(&mut synthesizers, item, format, context).await;
+        if result.is_err() {
+            // The connection might be broken, reconnect for the next item
+            synthesizers.remove(&format);
+        }
+        let result = result.and_then(|audio| {
+            if let Some(parent) = item.path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut partial = item.path.clone().into_os_string();
+            partial.push(".part");
+            fs::write(&partial, audio)?;
+            fs::rename(&partial, &item.path)?;
+            Ok(())
+        });
+        let error = result.err().map(|e| {
+            warn!("Failed to synthesize {}: {e:#}", item.output);
+            format!("{e:#}")
+        });
+        if error.is_some() {
+            summary.failed += 1;
+        } else {
+            summary.succeeded += 1;
+        }
+        state.borrow_mut().record(item, context, error)?;
+    }
+    Ok(summary)
+}
+
+async fn synthesize(
+    synthesizers: &mut HashMap<AudioFormat, Box<dyn UnifiedSynthesizer>>,
+    item: &BatchItem,
+    format: AudioFormat,
+    context: &BatchContext<'_>,
+) -> color_eyre::Result<Vec<u8>> {
+    let synthesizer = match synthesizers.entry(format) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => {
+            let conf = SynthesizerConfig::new(context.auth_options.clone(), format);
+            entry.insert(synthesizer_by_mode(conf, context.mode, context.cache.as_ref()).await?)
+        }
+    };
+    Ok(match &item.input {
+        BatchInput::Text(text) => {
+            synthesizer
+                .process_text_chunked(
+                    text,
+                    &item.text_options(&context.text_options),
+                    &TextSplitter::default(),
+                )
+                .await?
+        }
+        BatchInput::Ssml(ssml) => synthesizer.process_ssml(ssml).await?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use aspeak::AuthOptionsBuilder;
+
+    use super::*;
+
+    /// A fresh directory for the files of a test
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("aspeak-batch-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn manifest_is_loaded() {
+        let dir = test_dir("manifest");
+        let manifest = dir.join("manifest.jsonl");
+        fs::write(
+            &manifest,
+            r#"{"text": "Hello", "output": "out/hello.mp3", "voice": "en-US-JennyNeural", "rate": "fast"}
+
+{"ssml": "<speak>Hi</speak>", "output": "hi.wav", "format": "riff-16khz-16bit-mono-pcm"}
+"#,
+        )
+        .unwrap();
+        let items = load_manifest(&manifest).unwrap();
+        assert_eq!(items.len(), 2);
+        assert!(matches!(&items[0].input, BatchInput::Text(text) if text == "Hello"));
+        assert_eq!(items[0].path, dir.join("out/hello.mp3"));
+        assert_eq!(items[0].voice.as_deref(), Some("en-US-JennyNeural"));
+        assert_eq!(items[0].rate.as_deref(), Some("fast"));
+        assert!(matches!(&items[1].input, BatchInput::Ssml(ssml) if ssml == "<speak>Hi</speak>"));
+        assert_eq!(items[1].format, Some(AudioFormat::Riff16Khz16BitMonoPcm));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_manifests_are_rejected() {
+        let dir = test_dir("invalid");
+        let manifest = dir.join("manifest.jsonl");
+        for invalid in [
+            // Neither text nor ssml
+            r#"{"output": "a.wav"}"#,
+            // Both text and ssml
+            r#"{"text": "a", "ssml": "<speak>a</speak>", "output": "a.wav"}"#,
+            r#"{"text": "a", "output": "a.wav", "unknown": 1}"#,
+            r#"{"text": "a", "output": "a.wav", "rate": "very fast"}"#,
+            "{\"text\": \"a\", \"output\": \"a.wav\"}\n{\"text\": \"b\", \"output\": \"a.wav\"}",
+        ] {
+            fs::write(&manifest, invalid).unwrap();
+            assert!(load_manifest(&manifest).is_err(), "{invalid}");
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn context() -> BatchContext<'static> {
+        BatchContext {
+            auth_options: AuthOptionsBuilder::new("wss://localhost").build(),
+            mode: SynthesizerMode::Rest,
+            cache: None,
+            audio_format: AudioFormat::Riff24Khz16BitMonoPcm,
+            text_options: TextOptions::default(),
+        }
+    }
+
+    #[test]
+    fn changed_items_are_not_done() {
+        let dir = test_dir("state");
+        let context = context();
+        let item = |text: &str| {
+            BatchItem::parse(serde_json::json!({"text": text, "output": "a.wav"}), &dir).unwrap()
+        };
+        let state_path = dir.join("state.jsonl");
+        let original = item("Hello");
+        let mut state = BatchState::open(&state_path).unwrap();
+        assert!(!state.is_done(&original, &context));
+        state.record(&original, &context, None).unwrap();
+        // The output doesn't exist yet
+        assert!(!state.is_done(&original, &context));
+        fs::write(&original.path, b"audio").unwrap();
+        assert!(state.is_done(&original, &context));
+        assert!(!state.is_done(&item("Hello, world"), &context));
+        drop(state);
+
+        // The state survives a restart
+        let mut state = BatchState::open(&state_path).unwrap();
+        assert!(state.is_done(&original, &context));
+        assert!(!state.is_done(&item("Hello, world"), &context));
+        // The latest entry wins
+        state
+            .record(&original, &context, Some("synthesis failed".to_string()))
+            .unwrap();
+        drop(state);
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&state_path)
+            .unwrap()
+            .write_all(b"{\"output\": \"a.w")
+            .unwrap();
+        let state = BatchState::open(&state_path).unwrap();
+        assert!(!state.is_done(&original, &context));
+        // The state file is compacted and the partial line is dropped
+        assert_eq!(fs::read_to_string(&state_path).unwrap().lines().count(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn changed_settings_are_not_done() {
+        let dir = test_dir("settings");
+        let default = context();
+        let text =
+            BatchItem::parse(serde_json::json!({"text": "Hi", "output": "a.wav"}), &dir).unwrap();
+        let ssml = BatchItem::parse(
+            serde_json::json!({"ssml": "<speak>Hi</speak>", "output": "b.wav"}),
+            &dir,
+        )
+        .unwrap();
+        let mut state = BatchState::open(&dir.join("state.jsonl")).unwrap();
+        for item in [&text, &ssml] {
+            state.record(item, &default, None).unwrap();
+            fs::write(&item.path, b"audio").unwrap();
+            assert!(state.is_done(item, &default));
+        }
+
+        let other_format = BatchContext {
+            audio_format: AudioFormat::Audio24Khz48KBitRateMonoMp3,
+            ..context()
+        };
+        assert!(!state.is_done(&text, &other_format));
+        assert!(!state.is_done(&ssml, &other_format));
+
+        let mut other_options = context();
+        *other_options.text_options.rate_mut() = Some(Cow::Borrowed("fast"));
+        assert!(!state.is_done(&text, &other_options));
+        // The text options are not used for SSML
+        assert!(state.is_done(&ssml, &other_options));
+
+        // The format of the item overrides the default
+        let text = BatchItem::parse(
+            serde_json::json!({"text": "Hi", "output": "a.wav", "format": "audio-24khz-48kbitrate-mono-mp3"}),
+            &dir,
+        )
+        .unwrap();
+        state.record(&text, &default, None).unwrap();
+        assert!(state.is_done(&text, &other_format));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
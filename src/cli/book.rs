@@ -0,0 +1,505 @@
+use std::{
+    cmp::Ordering,
+    fs::{self, File},
+    io::Read,
+    path::Path,
+};
+
+use aspeak::AudioFormat;
+use color_eyre::{
+    eyre::{eyre, WrapErr},
+    Help,
+};
+use serde::Serialize;
+use xml::{reader::XmlEvent, EventReader, ParserConfig};
+use zip::ZipArchive;
+
+use super::{args::InputFormat, Cli};
+
+mod entities;
+
+use entities::HTML_ENTITIES;
+
+pub(crate) const PLAYLIST_FILE: &str = "playlist.m3u";
+pub(crate) const METADATA_FILE: &str = "chapters.json";
+
+/// Elements whose text is not part of the content
+const SKIPPED_ELEMENTS: &[&str] = &["head", "script", "style", "nav"];
+
+/// Elements that separate paragraphs
+const BLOCK_ELEMENTS: &[&str] = &[
+    "p",
+    "div",
+    "section",
+    "article",
+    "blockquote",
+    "li",
+    "dt",
+    "dd",
+    "tr",
+    "br",
+    "hr",
+    "pre",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+];
+
+#[derive(Debug)]
+pub(crate) struct Chapter {
+    pub title: String,
+    pub text: String,
+    pub format: InputFormat,
+    /// The file in the directory or in the EPUB that the chapter is read from
+    pub source: String,
+}
+
+#[derive(Debug)]
+pub(crate) struct Book {
+    pub title: String,
+    pub chapters: Vec<Chapter>,
+}
+
+#[derive(Serialize)]
+struct BookMetadata<'a> {
+    title: &'a str,
+    format: AudioFormat,
+    chapters: Vec<ChapterMetadata<'a>>,
+}
+
+#[derive(Serialize)]
+struct ChapterMetadata<'a> {
+    number: usize,
+    title: &'a str,
+    file: &'a str,
+    source: &'a str,
+    characters: usize,
+}
+
+impl Book {
+    pub fn load(path: &Path, encoding: Option<&str>) -> color_eyre::Result<Self> {
+        let book = if path.is_dir() {
+            Self::from_directory(path, encoding)?
+        } else {
+            Self::from_epub(path)
+                .wrap_err_with(|| format!("Failed to read EPUB file {}", path.display()))?
+        };
+        if book.chapters.is_empty() {
+            return Err(eyre!("No chapters found in {}", path.display())
+                .suggestion("A directory should contain .txt or .md files, one per chapter"));
+        }
+        Ok(book)
+    }
+
+    fn from_directory(dir: &Path, encoding: Option<&str>) -> color_eyre::Result<Self> {
+        let mut paths = fs::read_dir(dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        paths.retain(|path| path.is_file() && input_format(path).is_some());
+        paths.sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()));
+        let chapters = paths
+            .iter()
+            .map(|path| {
+                Ok(Chapter {
+                    title: file_stem(path),
+                    text: Cli::decode_text(File::open(path)?, encoding)?,
+                    format: input_format(path).unwrap(),
+                    source: path
+                        .file_name()
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_default(),
+                })
+            })
+            .collect::<color_eyre::Result<Vec<_>>>()?;
+        Ok(Self {
+            title: file_stem(dir),
+            chapters,
+        })
+    }
+
+    fn from_epub(path: &Path) -> color_eyre::Result<Self> {
+        let mut archive = ZipArchive::new(File::open(path)?)?;
+        let container = read_entry(&mut archive, "META-INF/container.xml")?;
+        let opf_path = parse_xml(&container)
+            .into_iter()
+            .find_map(|event| match event {
+                Ok(XmlEvent::StartElement {
+                    name, attributes, ..
+                }) if name.local_name == "rootfile" => attributes
+                    .into_iter()
+                    .find(|attr| attr.name.local_name == "full-path")
+                    .map(|attr| Ok(attr.value)),
+                Err(e) => Some(Err(e)),
+                _ => None,
+            })
+            .ok_or_else(|| eyre!("No rootfile in META-INF/container.xml"))??;
+        let package = Package::parse(&read_entry(&mut archive, &opf_path)?)?;
+        let base = opf_path.rsplit_once('/').map_or("", |(base, _)| base);
+        let mut chapters = Vec::new();
+        for href in package.spine {
+            let source = resolve_href(base, &href);
+            let (heading, text) = xhtml_text(&read_entry(&mut archive, &source)?)
+                .wrap_err_with(|| format!("Failed to parse {source}"))?;
+            if text.is_empty() {
+                continue;
+            }
+            chapters.push(Chapter {
+                title: heading.unwrap_or_else(|| format!("Chapter {}", chapters.len() + 1)),
+                text,
+                format: InputFormat::Plain,
+                source,
+            });
+        }
+        Ok(Self {
+            title: package.title.unwrap_or_else(|| file_stem(path)),
+            chapters,
+        })
+    }
+
+    /// File names of the chapters, e.g. `01-introduction.mp3`
+    pub fn file_names(&self, extension: &str) -> Vec<String> {
+        let width = self.chapters.len().to_string().len().max(2);
+        self.chapters
+            .iter()
+            .enumerate()
+            .map(|(i, chapter)| format!("{:0width$}-{}.{extension}", i + 1, slug(&chapter.title)))
+            .collect()
+    }
+
+    pub fn playlist(&self, files: &[String]) -> String {
+        let mut playlist = String::from("#EXTM3U\n");
+        playlist.push_str(&format!("#PLAYLIST:{}\n", self.title));
+        for (chapter, file) in self.chapters.iter().zip(files) {
+            playlist.push_str(&format!("#EXTINF:-1,{}\n{file}\n", chapter.title));
+        }
+        playlist
+    }
+
+    pub fn metadata(&self, files: &[String], format: AudioFormat) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(&BookMetadata {
+            title: &self.title,
+            format,
+            chapters: self
+                .chapters
+                .iter()
+                .zip(files)
+                .enumerate()
+                .map(|(i, (chapter, file))| ChapterMetadata {
+                    number: i + 1,
+                    title: &chapter.title,
+                    file,
+                    source: &chapter.source,
+                    characters: chapter.text.chars().count(),
+                })
+                .collect(),
+        })
+    }
+}
+
+/// The parts of the EPUB package document that we need
+struct Package {
+    title: Option<String>,
+    /// Content documents in reading order
+    spine: Vec<String>,
+}
+
+impl Package {
+    fn parse(opf: &str) -> color_eyre::Result<Self> {
+        let mut title = None;
+        let mut in_title = false;
+        let mut manifest = Vec::new();
+        let mut spine = Vec::new();
+        for event in parse_xml(opf).into_iter() {
+            match event? {
+                XmlEvent::StartElement {
+                    name, attributes, ..
+                } => {
+                    let attr = |key: &str| {
+                        attributes
+                            .iter()
+                            .find(|attr| attr.name.local_name == key)
+                            .map(|attr| attr.value.clone())
+                    };
+                    match name.local_name.as_str() {
+                        "title" => in_title = title.is_none(),
+                        "item" => {
+                            if let (Some(id), Some(href)) = (attr("id"), attr("href")) {
+                                manifest.push((id, href));
+                            }
+                        }
+                        "itemref" if attr("linear").as_deref() != Some("no") => {
+                            spine.extend(attr("idref"));
+                        }
+                        _ => {}
+                    }
+                }
+                XmlEvent::Characters(text) if in_title => {
+                    title = Some(text.trim().to_string());
+                    in_title = false;
+                }
+                XmlEvent::EndElement { .. } => in_title = false,
+                _ => {}
+            }
+        }
+        let spine = spine
+            .into_iter()
+            .map(|idref| {
+                manifest
+                    .iter()
+                    .find(|(id, _)| *id == idref)
+                    .map(|(_, href)| href.clone())
+                    .ok_or_else(|| eyre!("Spine item {idref} is not in the manifest"))
+            })
+            .collect::<color_eyre::Result<_>>()?;
+        Ok(Self { title, spine })
+    }
+}
+
+fn parse_xml(xml: &str) -> EventReader<&[u8]> {
+    let config = HTML_ENTITIES
+        .iter()
+        .fold(ParserConfig::new(), |config, (entity, value)| {
+            config.add_entity(*entity, *value)
+        })
+        .cdata_to_characters(true);
+    EventReader::new_with_config(xml.as_bytes(), config)
+}
+
+fn read_entry(archive: &mut ZipArchive<File>, name: &str) -> color_eyre::Result<String> {
+    let mut content = String::new();
+    archive
+        .by_name(name)
+        .wrap_err_with(|| format!("{name} is missing"))?
+        .read_to_string(&mut content)?;
+    Ok(content)
+}
+
+/// Extract the first heading and the paragraphs of a XHTML content document.
+fn xhtml_text(xhtml: &str) -> color_eyre::Result<(Option<String>, String)> {
+    let mut text = String::new();
+    let mut heading: Option<String> = None;
+    let mut in_heading = false;
+    let mut skip_depth = 0usize;
+    for event in parse_xml(xhtml).into_iter() {
+        match event? {
+            XmlEvent::StartElement { name, .. } => {
+                let name = name.local_name.as_str();
+                if skip_depth > 0 || SKIPPED_ELEMENTS.contains(&name) {
+                    skip_depth += 1;
+                } else if BLOCK_ELEMENTS.contains(&name) {
+                    text.push_str("\n\n");
+                    if heading.is_none() && matches!(name, "h1" | "h2" | "h3") {
+                        heading = Some(String::new());
+                        in_heading = true;
+                    }
+                }
+            }
+            XmlEvent::EndElement { name } => {
+                if skip_depth > 0 {
+                    skip_depth -= 1;
+                } else if BLOCK_ELEMENTS.contains(&name.local_name.as_str()) {
+                    text.push_str("\n\n");
+                    in_heading = false;
+                }
+            }
+            XmlEvent::Characters(s) | XmlEvent::Whitespace(s) if skip_depth == 0 => {
+                text.push_str(&s);
+                if in_heading {
+                    heading.get_or_insert_with(String::new).push_str(&s);
+                }
+            }
+            _ => {}
+        }
+    }
+    let text = text
+        .split("\n\n")
+        .map(|paragraph| paragraph.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|paragraph| !paragraph.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    let heading = heading
+        .map(|heading| heading.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|heading| !heading.is_empty());
+    Ok((heading, text))
+}
+
+/// Resolve a href in the package document to the name of the zip entry.
+fn resolve_href(base: &str, href: &str) -> String {
+    let href = href.split('#').next().unwrap_or_default();
+    let mut segments: Vec<&str> = base.split('/').filter(|s| !s.is_empty()).collect();
+    for segment in href.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    percent_decode(&segments.join("/"))
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match (bytes[i], s.get(i + 1..i + 3)) {
+            (b'%', Some(hex)) if u8::from_str_radix(hex, 16).is_ok() => {
+                decoded.push(u8::from_str_radix(hex, 16).unwrap());
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn input_format(path: &Path) -> Option<InputFormat> {
+    match path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase())
+        .as_deref()
+    {
+        Some("txt") => Some(InputFormat::Plain),
+        Some("md" | "markdown") => Some(InputFormat::Markdown),
+        _ => None,
+    }
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "Untitled".to_string())
+}
+
+/// Compare strings so that numbers are ordered by their values, e.g. `2.txt` comes before `10.txt`.
+///
+/// Strings that only differ in leading zeros are ordered as plain strings.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (full_a, full_b) = (a, b);
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (Some(x), Some(y)) = (a.chars().next(), b.chars().next()) else {
+            return a.len().cmp(&b.len()).then_with(|| full_a.cmp(full_b));
+        };
+        let ordering = if x.is_ascii_digit() && y.is_ascii_digit() {
+            let a_end = a.find(|c: char| !c.is_ascii_digit()).unwrap_or(a.len());
+            let b_end = b.find(|c: char| !c.is_ascii_digit()).unwrap_or(b.len());
+            let (a_num, b_num) = (
+                a[..a_end].trim_start_matches('0'),
+                b[..b_end].trim_start_matches('0'),
+            );
+            let ordering = a_num.len().cmp(&b_num.len()).then_with(|| a_num.cmp(b_num));
+            (a, b) = (&a[a_end..], &b[b_end..]);
+            ordering
+        } else {
+            (a, b) = (&a[x.len_utf8()..], &b[y.len_utf8()..]);
+            x.cmp(&y)
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+/// A file name friendly version of the title
+fn slug(title: &str) -> String {
+    let slug = title
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    match slug.char_indices().nth(48) {
+        Some((end, _)) => slug[..end].trim_end_matches('-').to_string(),
+        None if slug.is_empty() => "chapter".to_string(),
+        None => slug,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_are_compared_by_value() {
+        assert_eq!(natural_cmp("2.txt", "10.txt"), Ordering::Less);
+        assert_eq!(natural_cmp("ch10-b", "ch10-a"), Ordering::Greater);
+        assert_eq!(natural_cmp("ch02", "ch2"), Ordering::Less);
+        let mut files = vec!["10.txt", "1.txt", "b.txt", "2.txt", "a10.txt", "a9.txt"];
+        files.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(
+            files,
+            ["1.txt", "2.txt", "10.txt", "a9.txt", "a10.txt", "b.txt"]
+        );
+    }
+
+    #[test]
+    fn hrefs_are_resolved_against_the_package() {
+        assert_eq!(
+            resolve_href("OEBPS/Content/", "../Text/ch%201.xhtml#x"),
+            "OEBPS/Text/ch 1.xhtml"
+        );
+        assert_eq!(resolve_href("", "./ch1.xhtml"), "ch1.xhtml");
+        assert_eq!(
+            resolve_href("OEBPS", "Text//ch1.xhtml"),
+            "OEBPS/Text/ch1.xhtml"
+        );
+    }
+
+    #[test]
+    fn percent_encoded_bytes_are_decoded() {
+        assert_eq!(percent_decode("%E7%AB%A0%201"), "章 1");
+        // Invalid escapes are kept as is
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%4"), "%zz%4");
+    }
+
+    #[test]
+    fn slugs() {
+        assert_eq!(slug("Chapter 1: The Beginning!"), "chapter-1-the-beginning");
+        assert_eq!(slug("第一章 开始"), "第一章-开始");
+        assert_eq!(slug("?!"), "chapter");
+        let long = slug(&"word ".repeat(20));
+        assert!(long.chars().count() <= 48 && !long.ends_with('-'));
+    }
+
+    #[test]
+    fn xhtml_paragraphs_are_extracted() {
+        let xhtml = r#"<?xml version="1.0" encoding="utf-8"?>
+<html xmlns="http://www.w3.org/1999/xhtml">
+<head><title>Ignored</title><style>p { color: red; }</style></head>
+<body>
+  <h1>Chapter&nbsp;1
+    <span>Loomings</span></h1>
+  <p>Call me   Ishmael.</p>
+  <script>alert("ignored")</script>
+  <p>Some years ago&#8212;never mind
+     how long precisely.</p>
+</body>
+</html>"#;
+        let (heading, text) = xhtml_text(xhtml).unwrap();
+        assert_eq!(heading.as_deref(), Some("Chapter 1 Loomings"));
+        assert_eq!(
+            text,
+            "Chapter 1 Loomings\n\nCall me Ishmael.\n\nSome years ago—never mind how long precisely."
+        );
+    }
+
+    #[test]
+    fn xhtml_entities_are_decoded() {
+        let xhtml = r#"<html xmlns="http://www.w3.org/1999/xhtml"><body>
+  <p>&eacute;t&eacute; &euro;5 &frac12; &rarr; &alpha;&Omega; &laquo;x&hellip;&raquo; &trade;&amp;&apos;</p>
+</body></html>"#;
+        let (_, text) = xhtml_text(xhtml).unwrap();
+        assert_eq!(text, "été €5 ½ → αΩ «x…» ™&'");
+    }
+}
@@ -27,6 +27,8 @@ pub(crate) enum Command {
     ListQualities,
     #[command(about = "List available formats (for experts)")]
     ListFormats,
+    #[command(about = "List available audio output devices")]
+    ListDevices,
     #[command(about = "Speak text")]
     Text {
         #[command(flatten)]
@@ -42,11 +44,150 @@ pub(crate) enum Command {
                     If neither SSML nor input file is specified, the SSML will be read from stdin. \
                     Do not include the document type definition in your SSML.")]
         ssml: Option<String>,
+        #[arg(
+            long,
+            help = "Check the SSML for errors instead of speaking it. \
+                    The issues are reported with line and column numbers."
+        )]
+        check: bool,
         #[command(flatten)]
         input_args: InputArgs,
         #[command(flatten)]
         output_args: OutputArgs,
     },
+    #[command(
+        about = "Speak a multi-speaker dialogue script",
+        long_about = "Speak a multi-speaker dialogue script.\n\n\
+                      The script starts with speaker declarations like \
+                      `@ALICE voice=en-US-JennyNeural style=cheerful rate=fast`, \
+                      followed by lines like `ALICE: Hello`. \
+                      Available options are voice, style, role, styledegree, rate and pitch."
+    )]
+    Dialogue {
+        #[arg(
+            long,
+            help = "Split the dialogue into requests of at most this many characters \
+                    and join the audio. Default to 3000"
+        )]
+        chunk_size: Option<usize>,
+        #[command(flatten)]
+        input_args: InputArgs,
+        #[command(flatten)]
+        output_args: OutputArgs,
+    },
+    #[command(about = "Synthesize an audiobook from an EPUB file or a directory of text files")]
+    Book {
+        #[clap(help = "An EPUB file, or a directory of .txt and .md files. \
+                    The files in the directory are read as chapters in the order of their names.")]
+        input: String,
+        #[arg(
+            short,
+            long,
+            help = "Directory to write the audio of the chapters, an M3U playlist and the chapter metadata to"
+        )]
+        output_dir: String,
+        #[arg(
+            long,
+            action = ArgAction::SetTrue,
+            conflicts_with = "overwrite",
+            help = "Skip the chapters that have already been synthesized, e.g. to resume an interrupted run"
+        )]
+        resume: bool,
+        #[arg(long, action = ArgAction::SetTrue, help = "Overwrite existing files")]
+        overwrite: bool,
+        #[arg(short, long, help = "Encoding of the text files in the directory")]
+        encoding: Option<String>,
+        #[arg(short, long, help = "Mode of synthesizer, default to `rest`")]
+        mode: Option<SynthesizerMode>,
+        #[arg(
+            long,
+            help = "Split long chapters into chunks of at most this many characters, \
+                    synthesize them in separate requests and join the audio. Default to 3000"
+        )]
+        chunk_size: Option<usize>,
+        #[command(flatten)]
+        text_options_args: TextOptionsArgs,
+        #[command(flatten)]
+        format_args: FormatArgs,
+    },
+    #[command(
+        about = "Synthesize the items of a manifest, skipping the items that have been synthesized",
+        long_about = "Synthesize the items of a manifest, skipping the items that have been synthesized.\n\n\
+                      The manifest is a JSON Lines file. Every line is an object with `text` or `ssml`, \
+                      the `output` path and optional `voice`, `rate`, `pitch`, `style` and `format` overrides, e.g.\n\n\
+                      {\"text\": \"Welcome!\", \"output\": \"prompts/welcome.wav\", \"voice\": \"en-US-GuyNeural\"}\n\n\
+                      Relative output paths are resolved against the directory of the manifest. \
+                      The status of every item is recorded in the state file. \
+                      Rerunning the command retries the failed items and skips the completed ones, \
+                      unless their options or format, including the defaults of the command line, have changed."
+    )]
+    Batch {
+        #[clap(help = "The manifest in JSON Lines format")]
+        manifest: String,
+        #[arg(
+            long,
+            help = "The file to record the status of every item in, default to <MANIFEST>.state.jsonl"
+        )]
+        state: Option<String>,
+        #[arg(
+            short = 'j',
+            long,
+            default_value_t = 4,
+            value_parser = clap::value_parser!(u16).range(1..),
+            help = "Number of items to synthesize concurrently. \
+                    In websocket mode, every concurrent item has its own connection"
+        )]
+        concurrency: u16,
+        #[arg(short, long, help = "Mode of synthesizer, default to `rest`")]
+        mode: Option<SynthesizerMode>,
+        #[command(flatten)]
+        text_options_args: TextOptionsArgs,
+        #[command(flatten)]
+        format_args: FormatArgs,
+    },
+    #[command(
+        about = "Serve a local HTTP API for text-to-speech",
+        long_about = "Serve a local HTTP API for text-to-speech.\n\n\
+                      The server authenticates to Azure with the profile and the auth options, \
+                      so that clients never see the credentials. Endpoints:\n\n\
+                      POST /tts: Synthesize the JSON body {\"text\": \"...\"} with optional \
+                      `voice`, `locale`, `rate`, `pitch`, `style`, `role`, `style_degree` and `format` fields\n\
+                      POST /ssml: Synthesize the SSML in the body, the audio format can be set with the `format` query parameter\n\
+                      GET /voices: List the available voices, optionally filtered by the `locale` or `voice` query parameter\n\n\
+                      In websocket mode, the audio is streamed to the client as it arrives from Azure, \
+                      unless a cache is used, in which case the audio is sent when the synthesis finishes. \
+                      Request bodies are limited to 1 MiB."
+    )]
+    Serve {
+        #[arg(
+            long,
+            default_value = "127.0.0.1:8080",
+            help = "The address to listen on"
+        )]
+        listen: std::net::SocketAddr,
+        #[arg(short, long, help = "Mode of synthesizer, default to `rest`")]
+        mode: Option<SynthesizerMode>,
+        #[arg(
+            short = 'j',
+            long,
+            default_value_t = 4,
+            value_parser = clap::value_parser!(u16).range(1..),
+            help = "Number of connections per audio format in websocket mode"
+        )]
+        concurrency: u16,
+        #[arg(
+            short,
+            long,
+            help = "The voice list API url for GET /voices. \
+                    If this option is not specified and the region option is specified, \
+                    then aspeak will use the API url designated for that region."
+        )]
+        url: Option<String>,
+        #[command(flatten)]
+        text_options_args: TextOptionsArgs,
+        #[command(flatten)]
+        format_args: FormatArgs,
+    },
     #[command(about = "Configure settings of aspeak")]
     Config {
         #[command(subcommand)]
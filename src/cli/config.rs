@@ -88,7 +88,7 @@ pub(crate) enum EndpointConfig {
 }
 
 impl EndpointConfig {
-    pub(crate) fn to_cow_str(&self, mode: SynthesizerMode) -> Cow<str> {
+    pub(crate) fn to_cow_str(&self, mode: SynthesizerMode) -> Cow<'_, str> {
         match self {
             EndpointConfig::Endpoint { endpoint } => Cow::Borrowed(endpoint),
             EndpointConfig::Region { region } => Cow::Owned(match mode {
@@ -162,4 +162,5 @@ pub(crate) struct OutputConfig {
     pub format: Option<AudioFormat>,
     pub container: Option<ContainerFormat>,
     pub quality: Option<i32>,
+    pub device: Option<String>,
 }
@@ -0,0 +1,384 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Seek, SeekFrom},
+    mem,
+    sync::{mpsc, Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use aspeak::{open_output_stream, AudioCodec, AudioContainer, AudioFormat};
+use color_eyre::eyre::{eyre, WrapErr};
+use log::{debug, info, warn};
+use rodio::{Decoder, Sink, Source};
+
+/// Audio that is still being received
+#[derive(Default)]
+struct SharedBuffer {
+    state: Mutex<BufferState>,
+    ready: Condvar,
+}
+
+#[derive(Default)]
+struct BufferState {
+    data: Vec<u8>,
+    finished: bool,
+}
+
+impl SharedBuffer {
+    fn push(&self, chunk: &[u8]) {
+        self.state.lock().unwrap().data.extend_from_slice(chunk);
+        self.ready.notify_all();
+    }
+
+    fn finish(&self) {
+        self.state.lock().unwrap().finished = true;
+        self.ready.notify_all();
+    }
+}
+
+/// A reader of a [`SharedBuffer`] that blocks until the requested data arrives,
+/// so that decoders can read the audio as if it was a complete file.
+///
+/// It is only read on the playback thread, never by the audio callback.
+struct BufferReader {
+    buffer: Arc<SharedBuffer>,
+    pos: usize,
+}
+
+impl Read for BufferReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.buffer.state.lock().unwrap();
+        while state.data.len() <= self.pos && !state.finished {
+            state = self.buffer.ready.wait(state).unwrap();
+        }
+        let available = state.data.get(self.pos..).unwrap_or_default();
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+impl Seek for BufferReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(offset) => {
+                self.pos = offset as usize;
+                return Ok(offset);
+            }
+            SeekFrom::Current(offset) => (self.pos, offset),
+            SeekFrom::End(offset) => {
+                // The end is only known after all the audio has arrived
+                let mut state = self.buffer.state.lock().unwrap();
+                while !state.finished {
+                    state = self.buffer.ready.wait(state).unwrap();
+                }
+                (state.data.len(), offset)
+            }
+        };
+        self.pos = base
+            .checked_add_signed(offset as isize)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid seek"))?;
+        Ok(self.pos as u64)
+    }
+}
+
+/// The amount of audio buffered before playback starts or resumes after an underrun
+const PREBUFFER: Duration = Duration::from_millis(200);
+
+/// Decoded samples that are handed from the playback thread to the audio callback
+#[derive(Default)]
+struct SampleQueue {
+    state: Mutex<SampleState>,
+}
+
+#[derive(Default)]
+struct SampleState {
+    samples: VecDeque<i16>,
+    finished: bool,
+}
+
+impl SampleQueue {
+    fn push(&self, samples: &[i16]) {
+        self.state.lock().unwrap().samples.extend(samples);
+    }
+
+    fn finish(&self) {
+        self.state.lock().unwrap().finished = true;
+    }
+}
+
+/// A source that never blocks the audio callback.
+///
+/// It plays silence while the queue is buffering, i.e. at the start
+/// and whenever the samples run out before the audio is complete.
+struct QueueSource {
+    queue: Arc<SampleQueue>,
+    /// Samples taken from the queue but not played yet
+    samples: VecDeque<i16>,
+    buffering: bool,
+    channels: u16,
+    sample_rate: u32,
+}
+
+impl QueueSource {
+    fn new(queue: Arc<SampleQueue>, channels: u16, sample_rate: u32) -> Self {
+        Self {
+            queue,
+            samples: VecDeque::new(),
+            buffering: true,
+            channels,
+            sample_rate,
+        }
+    }
+
+    /// Take the samples of the queue, returns whether the audio is complete
+    fn refill(&mut self) -> bool {
+        let mut state = self.queue.state.lock().unwrap();
+        self.samples.append(&mut mem::take(&mut state.samples));
+        state.finished
+    }
+}
+
+impl Iterator for QueueSource {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        if self.buffering || self.samples.is_empty() {
+            let finished = self.refill();
+            let prebuffer =
+                PREBUFFER.as_millis() as usize * self.sample_rate as usize * self.channels as usize
+                    / 1000;
+            self.buffering = !finished && self.samples.len() < prebuffer;
+            if self.buffering {
+                return Some(0);
+            }
+        }
+        self.samples.pop_front()
+    }
+}
+
+impl Source for QueueSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// 16 bit little endian mono PCM samples
+struct PcmSamples(BufferReader);
+
+impl Iterator for PcmSamples {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        let mut sample = [0; 2];
+        self.0.read_exact(&mut sample).ok()?;
+        Some(i16::from_le_bytes(sample))
+    }
+}
+
+/// Whether the audio of the format can be played.
+///
+/// Rodio cannot decode Opus, AMR-WB, SILK and G.711 audio.
+pub(crate) fn is_playable(format: AudioFormat) -> bool {
+    is_plain_pcm(format)
+        || format.codec() == AudioCodec::Mp3
+        || (format.codec() == AudioCodec::Pcm && format.container() == AudioContainer::Riff)
+}
+
+/// Whether the samples of the format can be played without a decoder,
+/// i.e. 16 bit mono PCM with or without a RIFF header
+fn is_plain_pcm(format: AudioFormat) -> bool {
+    format.codec() == AudioCodec::Pcm
+        && matches!(
+            format.container(),
+            AudioContainer::Raw | AudioContainer::Riff
+        )
+        && format.bit_depth() == Some(16)
+        && format.channels() == 1
+}
+
+/// Skip the RIFF header, leaving the reader at the start of the samples.
+fn skip_riff_header(reader: &mut BufferReader) -> color_eyre::Result<()> {
+    let mut header = [0; 12];
+    reader.read_exact(&mut header)?;
+    if &header[..4] != b"RIFF" || &header[8..] != b"WAVE" {
+        return Err(eyre!("Invalid RIFF header"));
+    }
+    loop {
+        let mut chunk_header = [0; 8];
+        reader.read_exact(&mut chunk_header)?;
+        if &chunk_header[..4] == b"data" {
+            return Ok(());
+        }
+        let size = u32::from_le_bytes(chunk_header[4..].try_into().unwrap()) as i64;
+        // Chunks are padded to an even size
+        reader.seek(SeekFrom::Current(size + (size & 1)))?;
+    }
+}
+
+/// Move the samples into the queue in batches as they are decoded
+fn decode(queue: &SampleQueue, samples: impl Iterator<Item = i16>) {
+    const BATCH_SIZE: usize = 1024;
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    for sample in samples {
+        batch.push(sample);
+        if batch.len() == BATCH_SIZE {
+            queue.push(&batch);
+            batch.clear();
+        }
+    }
+    queue.push(&batch);
+    queue.finish();
+}
+
+fn play(
+    format: AudioFormat,
+    device: Option<String>,
+    documents: mpsc::Receiver<Arc<SharedBuffer>>,
+) -> color_eyre::Result<()> {
+    let (_stream, stream_handle) = open_output_stream(device.as_deref())?;
+    let sink = Sink::try_new(&stream_handle)?;
+    for buffer in documents {
+        let mut reader = BufferReader { buffer, pos: 0 };
+        // Wait for the first chunk, the server might not send any audio at all
+        if reader.read(&mut [0])? == 0 {
+            warn!("Got empty audio buffer, nothing to play");
+            continue;
+        }
+        reader.pos = 0;
+        // The audio is decoded on this thread as it arrives, and played from the queue.
+        // The documents are decoded one after another, so the sink plays them in order.
+        let queue = Arc::new(SampleQueue::default());
+        if is_plain_pcm(format) {
+            if format.container() == AudioContainer::Riff {
+                skip_riff_header(&mut reader)?;
+            }
+            sink.append(QueueSource::new(queue.clone(), 1, format.sample_rate()));
+            decode(&queue, PcmSamples(reader));
+        } else {
+            let decoder = Decoder::new(reader).wrap_err("Failed to decode the audio")?;
+            sink.append(QueueSource::new(
+                queue.clone(),
+                decoder.channels(),
+                decoder.sample_rate(),
+            ));
+            decode(&queue, decoder);
+        }
+    }
+    sink.sleep_until_end();
+    debug!("Done playing audio");
+    Ok(())
+}
+
+/// Plays audio while it is being synthesized.
+///
+/// Every document passed to [`StreamingPlayer::begin`] is played after the previous one.
+/// The audio is decoded and played on a separate thread so that the async runtime is never blocked.
+pub(crate) struct StreamingPlayer {
+    sender: Option<mpsc::Sender<Arc<SharedBuffer>>>,
+    current: Option<Arc<SharedBuffer>>,
+    thread: Option<JoinHandle<color_eyre::Result<()>>>,
+}
+
+impl StreamingPlayer {
+    /// Start playing on the given output device, or on the default device if it is `None`.
+    pub fn start(format: AudioFormat, device: Option<String>) -> Self {
+        info!("Playing audio as it arrives...");
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            sender: Some(sender),
+            current: None,
+            thread: Some(thread::spawn(move || play(format, device, receiver))),
+        }
+    }
+
+    /// Start a new audio document, e.g. the audio of the next SSML chunk.
+    pub fn begin(&mut self) {
+        if let Some(current) = self.current.take() {
+            current.finish();
+        }
+        let buffer = Arc::new(SharedBuffer::default());
+        // If the playback thread has stopped, its error is reported by `finish`
+        let _ = self.sender.as_ref().unwrap().send(buffer.clone());
+        self.current = Some(buffer);
+    }
+
+    /// Append audio to the current document.
+    pub fn push(&mut self, chunk: &[u8]) {
+        if self.current.is_none() {
+            self.begin();
+        }
+        self.current.as_ref().unwrap().push(chunk);
+    }
+
+    /// Wait until all the audio has been played.
+    pub fn finish(mut self) -> color_eyre::Result<()> {
+        self.close();
+        self.thread
+            .take()
+            .unwrap()
+            .join()
+            .map_err(|_| eyre!("The playback thread panicked"))?
+    }
+
+    fn close(&mut self) {
+        if let Some(current) = self.current.take() {
+            current.finish();
+        }
+        self.sender = None;
+    }
+}
+
+impl Drop for StreamingPlayer {
+    fn drop(&mut self) {
+        // Let the playback thread play what has been received and exit
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queue_source_never_waits() {
+        let queue = Arc::new(SampleQueue::default());
+        // 200ms of 1kHz mono audio are buffered before playback starts
+        let mut source = QueueSource::new(queue.clone(), 1, 1000);
+        assert_eq!(source.next(), Some(0));
+        queue.push(&[1; 150]);
+        assert_eq!(source.next(), Some(0));
+        queue.push(&[2; 50]);
+        assert_eq!(source.by_ref().take(200).sum::<i16>(), 250);
+        // Playback resumes after an underrun once the queue is buffered again
+        queue.push(&[3; 10]);
+        assert_eq!(source.next(), Some(0));
+        // The rest of the audio is played once it is complete
+        queue.finish();
+        assert_eq!(source.collect::<Vec<_>>(), [3; 10]);
+    }
+
+    #[test]
+    fn playable_formats() {
+        assert!(is_playable(AudioFormat::Audio24Khz48KBitRateMonoMp3));
+        assert!(is_playable(AudioFormat::Riff24Khz16BitMonoPcm));
+        assert!(is_playable(AudioFormat::Raw24Khz16BitMonoPcm));
+        assert!(!is_playable(AudioFormat::Ogg24Khz16BitMonoOpus));
+        assert!(!is_playable(AudioFormat::Webm24Khz16BitMonoOpus));
+        assert!(!is_playable(AudioFormat::Riff8Khz8BitMonoMULaw));
+    }
+}
@@ -0,0 +1,388 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use aspeak::{
+    get_default_voice_by_locale,
+    synthesizer::{
+        CachedSynthesizer, SynthesisCache, SynthesizerConfig, UnifiedSynthesizer,
+        UnifiedSynthesizerError, UnifiedSynthesizerErrorKind, WebsocketSynthesizerPool,
+    },
+    transport::{HttpTransport, NetworkTransport},
+    AudioFormat, AuthOptions, RichSsmlOptions, Role, TextOptions, Voice,
+};
+use hyper::{
+    body::HttpBody,
+    header,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use log::{info, warn};
+use serde::Deserialize;
+use tokio::sync::OnceCell;
+
+use super::{args::SynthesizerMode, parse};
+use crate::request_voices;
+
+/// The maximum size of request bodies, which is far beyond the input limits of the service
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+/// Everything the request handlers need, shared by all connections
+pub(crate) struct ServerState {
+    auth_options: AuthOptions<'static>,
+    mode: SynthesizerMode,
+    audio_format: AudioFormat,
+    text_options: TextOptions<'static>,
+    cache: Option<SynthesisCache>,
+    voice_list_url: Option<String>,
+    /// Max number of connections per audio format in websocket mode
+    concurrency: usize,
+    /// Shared by the rest synthesizers so that connections to the service are reused
+    http_transport: Arc<dyn HttpTransport>,
+    pools: Mutex<HashMap<AudioFormat, WebsocketSynthesizerPool>>,
+    voices: OnceCell<Vec<Voice>>,
+}
+
+impl ServerState {
+    pub fn new(
+        auth_options: AuthOptions<'static>,
+        mode: SynthesizerMode,
+        audio_format: AudioFormat,
+        text_options: TextOptions<'static>,
+        cache: Option<SynthesisCache>,
+        voice_list_url: Option<String>,
+        concurrency: usize,
+    ) -> color_eyre::Result<Self> {
+        Ok(Self {
+            http_transport: Arc::new(NetworkTransport::new(auth_options.proxy())?),
+            auth_options,
+            mode,
+            audio_format,
+            text_options,
+            cache,
+            voice_list_url,
+            concurrency,
+            pools: Mutex::new(HashMap::new()),
+            voices: OnceCell::new(),
+        })
+    }
+
+    /// The pool of websocket connections for the format
+    fn pool(&self, format: AudioFormat) -> WebsocketSynthesizerPool {
+        self.pools
+            .lock()
+            .unwrap()
+            .entry(format)
+            .or_insert_with(|| {
+                SynthesizerConfig::new(self.auth_options.clone(), format)
+                    .websocket_pool(self.concurrency)
+            })
+            .clone()
+    }
+
+    fn synthesizer(&self, format: AudioFormat) -> color_eyre::Result<Box<dyn UnifiedSynthesizer>> {
+        let synthesizer: Box<dyn UnifiedSynthesizer> = match self.mode {
+            SynthesizerMode::Rest => {
+                let mut conf = SynthesizerConfig::new(self.auth_options.clone(), format);
+                *conf.http_transport_mut() = Some(self.http_transport.clone());
+                Box::new(conf.rest_synthesizer()?)
+            }
+            SynthesizerMode::Websocket => Box::new(self.pool(format)),
+        };
+        Ok(match &self.cache {
+            Some(cache) => Box::new(CachedSynthesizer::new(
+                synthesizer,
+                cache.clone(),
+                self.auth_options.endpoint(),
+            )),
+            None => synthesizer,
+        })
+    }
+}
+
+/// The body of `POST /tts`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TtsRequest {
+    text: String,
+    voice: Option<String>,
+    locale: Option<String>,
+    rate: Option<String>,
+    pitch: Option<String>,
+    style: Option<String>,
+    role: Option<Role>,
+    style_degree: Option<f32>,
+    format: Option<AudioFormat>,
+}
+
+impl TtsRequest {
+    /// The text options of this request, which override the defaults of the server
+    fn text_options<'a>(&'a self, defaults: &TextOptions<'a>) -> Result<TextOptions<'a>, ApiError> {
+        let mut options = defaults.clone();
+        match (self.voice.as_deref(), self.locale.as_deref()) {
+            (Some(voice), _) => *options.voice_mut() = Cow::Borrowed(voice),
+            (None, Some(locale)) => {
+                *options.voice_mut() =
+                    Cow::Borrowed(get_default_voice_by_locale(locale).ok_or_else(|| {
+                        ApiError::bad_request(format!("No default voice found for locale {locale}"))
+                    })?)
+            }
+            (None, None) => {}
+        }
+        if let Some(rate) = self.rate.as_deref() {
+            *options.rate_mut() = Some(parse::parse_rate(rate).map_err(ApiError::bad_request)?);
+        }
+        if let Some(pitch) = self.pitch.as_deref() {
+            *options.pitch_mut() = Some(parse::parse_pitch(pitch).map_err(ApiError::bad_request)?);
+        }
+        if self.style.is_some() || self.role.is_some() || self.style_degree.is_some() {
+            let rich_ssml_options = options
+                .rich_ssml_options_mut()
+                .get_or_insert_with(RichSsmlOptions::default);
+            if let Some(style) = self.style.as_deref() {
+                *rich_ssml_options.style_mut() = Some(Cow::Borrowed(style));
+            }
+            if let Some(role) = self.role {
+                *rich_ssml_options.role_mut() = Some(role);
+            }
+            if let Some(style_degree) = self.style_degree {
+                if !parse::validate_style_degree(style_degree) {
+                    return Err(ApiError::bad_request("Style degree out of range [0.01, 2]"));
+                }
+                *rich_ssml_options.style_degree_mut() = Some(style_degree);
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// An error response with a plain text message
+struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, message: impl ToString) -> Self {
+        Self {
+            status,
+            message: message.to_string(),
+        }
+    }
+
+    fn bad_request(message: impl ToString) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, message)
+    }
+
+    fn into_response(self) -> Response<Body> {
+        Response::builder()
+            .status(self.status)
+            .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+            .body(Body::from(self.message + "\n"))
+            .unwrap()
+    }
+}
+
+impl From<UnifiedSynthesizerError> for ApiError {
+    fn from(e: UnifiedSynthesizerError) -> Self {
+        let status = match e.kind {
+            UnifiedSynthesizerErrorKind::InvalidRequest | UnifiedSynthesizerErrorKind::Ssml => {
+                StatusCode::BAD_REQUEST
+            }
+            _ => StatusCode::BAD_GATEWAY,
+        };
+        Self::new(status, format!("{:#}", color_eyre::Report::from(e)))
+    }
+}
+
+/// Serve the API on the given address until the process is terminated.
+pub(crate) async fn serve(addr: SocketAddr, state: ServerState) -> color_eyre::Result<()> {
+    let state = Arc::new(state);
+    let make_service = make_service_fn(move |_| {
+        let state = state.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |req| handle(state.clone(), req))) }
+    });
+    let server = Server::try_bind(&addr)?.serve(make_service);
+    info!("Listening on http://{}", server.local_addr());
+    server.await?;
+    Ok(())
+}
+
+async fn handle(state: Arc<ServerState>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let response = match (&method, path.as_str()) {
+        (&Method::POST, "/tts") => handle_tts(&state, req).await,
+        (&Method::POST, "/ssml") => handle_ssml(&state, req).await,
+        (&Method::GET, "/voices") => handle_voices(&state, req).await,
+        (_, "/tts" | "/ssml" | "/voices") => Err(ApiError::new(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "Method not allowed",
+        )),
+        _ => Err(ApiError::new(StatusCode::NOT_FOUND, "Not found")),
+    }
+    .unwrap_or_else(|e| {
+        warn!("{method} {path}: {}", e.message);
+        e.into_response()
+    });
+    info!("{method} {path} {}", response.status());
+    Ok(response)
+}
+
+/// The value of a query parameter
+fn query_param(req: &Request<Body>, name: &str) -> Option<String> {
+    url::form_urlencoded::parse(req.uri().query()?.as_bytes())
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned())
+}
+
+/// Read the request body, rejecting bodies larger than [`MAX_BODY_SIZE`]
+async fn read_body(req: Request<Body>) -> Result<Vec<u8>, ApiError> {
+    let too_large = || {
+        ApiError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("The request body is larger than {MAX_BODY_SIZE} bytes"),
+        )
+    };
+    let content_length = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|len| len.to_str().ok()?.parse::<u64>().ok());
+    if content_length.is_some_and(|len| len > MAX_BODY_SIZE as u64) {
+        return Err(too_large());
+    }
+    let mut body = req.into_body();
+    let mut buffer = Vec::with_capacity(content_length.unwrap_or_default() as usize);
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk
+            .map_err(|e| ApiError::bad_request(format!("Failed to read the request body: {e}")))?;
+        if buffer.len() + chunk.len() > MAX_BODY_SIZE {
+            return Err(too_large());
+        }
+        buffer.extend_from_slice(&chunk);
+    }
+    Ok(buffer)
+}
+
+fn audio_response(format: AudioFormat, body: Body) -> Response<Body> {
+    Response::builder()
+        .header(header::CONTENT_TYPE, format.mime_type())
+        .body(body)
+        .unwrap()
+}
+
+async fn synthesize(
+    state: &ServerState,
+    format: AudioFormat,
+    ssml: &str,
+) -> Result<Response<Body>, ApiError> {
+    if state.mode == SynthesizerMode::Websocket && state.cache.is_none() {
+        // Send the audio as it arrives. A failure after the first chunk aborts the response.
+        let stream = state
+            .pool(format)
+            .synthesize_ssml_stream(ssml)
+            .await
+            .map_err(UnifiedSynthesizerError::from)?;
+        return Ok(audio_response(format, Body::wrap_stream(stream)));
+    }
+    let mut synthesizer = state
+        .synthesizer(format)
+        .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, format!("{e:#}")))?;
+    let audio = synthesizer.process_ssml(ssml).await?;
+    Ok(audio_response(format, Body::from(audio)))
+}
+
+async fn handle_tts(state: &ServerState, req: Request<Body>) -> Result<Response<Body>, ApiError> {
+    let body = read_body(req).await?;
+    let request: TtsRequest = serde_json::from_slice(&body)
+        .map_err(|e| ApiError::bad_request(format!("Invalid request: {e}")))?;
+    let options = request.text_options(&state.text_options)?;
+    let ssml = aspeak::interpolate_ssml(&request.text, &options)
+        .map_err(|e| ApiError::bad_request(format!("{:#}", color_eyre::Report::from(e))))?;
+    synthesize(state, request.format.unwrap_or(state.audio_format), &ssml).await
+}
+
+async fn handle_ssml(state: &ServerState, req: Request<Body>) -> Result<Response<Body>, ApiError> {
+    let format = query_param(&req, "format")
+        .map(|format| {
+            format
+                .parse::<AudioFormat>()
+                .map_err(|_| ApiError::bad_request(format!("Unknown audio format {format}")))
+        })
+        .transpose()?
+        .unwrap_or(state.audio_format);
+    let body = read_body(req).await?;
+    let ssml = std::str::from_utf8(&body)
+        .map_err(|_| ApiError::bad_request("The SSML is not valid UTF-8"))?;
+    synthesize(state, format, ssml).await
+}
+
+async fn handle_voices(
+    state: &ServerState,
+    req: Request<Body>,
+) -> Result<Response<Body>, ApiError> {
+    let url = state.voice_list_url.as_deref().ok_or_else(|| {
+        ApiError::new(
+            StatusCode::NOT_FOUND,
+            "No voice list API url is configured for this server",
+        )
+    })?;
+    let voices = state
+        .voices
+        .get_or_try_init(|| request_voices(url, &state.auth_options))
+        .await
+        .map_err(|e| ApiError::new(StatusCode::BAD_GATEWAY, format!("{e:#}")))?;
+    let locale = query_param(&req, "locale");
+    let voice = query_param(&req, "voice");
+    let voices = voices
+        .iter()
+        .filter(|v| {
+            locale
+                .as_deref()
+                .map_or(true, |locale| v.locale() == locale)
+        })
+        .filter(|v| {
+            voice
+                .as_deref()
+                .map_or(true, |voice| v.short_name() == voice)
+        })
+        .collect::<Vec<_>>();
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_vec(&voices).unwrap()))
+        .unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(body: Vec<u8>, content_length: Option<usize>) -> Request<Body> {
+        let mut request = Request::post("/ssml");
+        if let Some(len) = content_length {
+            request = request.header(header::CONTENT_LENGTH, len);
+        }
+        request.body(Body::from(body)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn large_bodies_are_rejected() {
+        let body = vec![b'a'; MAX_BODY_SIZE];
+        assert_eq!(
+            read_body(request(body.clone(), None)).await.ok(),
+            Some(body)
+        );
+        for request in [
+            request(vec![b'a'; MAX_BODY_SIZE + 1], None),
+            // The declared length is checked before the body is read
+            request(Vec::new(), Some(MAX_BODY_SIZE + 1)),
+        ] {
+            let error = read_body(request).await.err().unwrap();
+            assert_eq!(error.status, StatusCode::PAYLOAD_TOO_LARGE);
+        }
+    }
+}
@@ -0,0 +1,285 @@
+use std::{fmt::Write, path::Path, time::Duration};
+
+use aspeak::synthesizer::{BoundaryEvent, MetadataOptions, SynthesisEvent};
+use color_eyre::{eyre::eyre, Help};
+
+/// Cues longer than this will be split at word boundaries
+const MAX_CUE_CHARS: usize = 84;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum SubtitleFormat {
+    Srt,
+    WebVtt,
+}
+
+impl SubtitleFormat {
+    pub fn from_path(path: &Path) -> color_eyre::Result<Self> {
+        match path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase())
+            .as_deref()
+        {
+            Some("srt") => Ok(Self::Srt),
+            Some("vtt") => Ok(Self::WebVtt),
+            _ => Err(eyre!(
+                "Cannot determine subtitle format from file name {}",
+                path.display()
+            )
+            .suggestion("Use a file name that ends with .srt or .vtt")),
+        }
+    }
+
+    /// The metadata we need from the websocket API to generate subtitles
+    pub fn metadata_options() -> MetadataOptions {
+        MetadataOptions::builder()
+            .word_boundary(true)
+            .sentence_boundary(true)
+            .build()
+    }
+
+    pub fn render(&self, events: &[SynthesisEvent]) -> String {
+        let mut out = String::new();
+        if *self == Self::WebVtt {
+            out.push_str("WEBVTT\n\n");
+        }
+        for (i, cue) in build_cues(events).iter().enumerate() {
+            if *self == Self::Srt {
+                writeln!(out, "{}", i + 1).unwrap();
+            }
+            writeln!(
+                out,
+                "{} --> {}\n{}\n",
+                self.timestamp(cue.start),
+                self.timestamp(cue.end),
+                cue.text
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    fn timestamp(&self, time: Duration) -> String {
+        let millis = time.as_millis();
+        let (h, m, s, ms) = (
+            millis / 3_600_000,
+            millis / 60_000 % 60,
+            millis / 1000 % 60,
+            millis % 1000,
+        );
+        match self {
+            Self::Srt => format!("{h:02}:{m:02}:{s:02},{ms:03}"),
+            Self::WebVtt => format!("{h:02}:{m:02}:{s:02}.{ms:03}"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Cue {
+    start: Duration,
+    end: Duration,
+    text: String,
+}
+
+/// The end of the last event, a lower bound of the duration of the audio.
+pub(crate) fn events_end(events: &[SynthesisEvent]) -> Duration {
+    events
+        .iter()
+        .map(|event| match event {
+            SynthesisEvent::WordBoundary(e)
+            | SynthesisEvent::PunctuationBoundary(e)
+            | SynthesisEvent::SentenceBoundary(e) => e.audio_offset + e.duration,
+            e => e.audio_offset(),
+        })
+        .max()
+        .unwrap_or_default()
+}
+
+/// Shift the audio offsets of the events, e.g. for events of a later chunk.
+pub(crate) fn shift_event(mut event: SynthesisEvent, offset: Duration) -> SynthesisEvent {
+    match &mut event {
+        SynthesisEvent::WordBoundary(e)
+        | SynthesisEvent::PunctuationBoundary(e)
+        | SynthesisEvent::SentenceBoundary(e) => e.audio_offset += offset,
+        SynthesisEvent::Viseme(e) => e.audio_offset += offset,
+        SynthesisEvent::Bookmark(e) => e.audio_offset += offset,
+        _ => {}
+    }
+    event
+}
+
+/// Build cues from sentence boundaries, splitting long sentences at word boundaries.
+/// If there are no sentence boundaries, cues are built from word boundaries only.
+fn build_cues(events: &[SynthesisEvent]) -> Vec<Cue> {
+    let words: Vec<&BoundaryEvent> = events
+        .iter()
+        .filter_map(|e| match e {
+            SynthesisEvent::WordBoundary(b) | SynthesisEvent::PunctuationBoundary(b) => Some(b),
+            _ => None,
+        })
+        .collect();
+    let sentences: Vec<&BoundaryEvent> = events
+        .iter()
+        .filter_map(|e| match e {
+            SynthesisEvent::SentenceBoundary(b) => Some(b),
+            _ => None,
+        })
+        .collect();
+    if sentences.is_empty() {
+        return split_words(None, &words);
+    }
+    let mut cues = Vec::new();
+    for sentence in sentences {
+        let end = sentence.audio_offset + sentence.duration;
+        if sentence.text.chars().count() <= MAX_CUE_CHARS {
+            cues.push(Cue {
+                start: sentence.audio_offset,
+                end,
+                text: sentence.text.trim().to_string(),
+            });
+            continue;
+        }
+        let words_in_sentence: Vec<&BoundaryEvent> = words
+            .iter()
+            .copied()
+            .filter(|w| w.audio_offset >= sentence.audio_offset && w.audio_offset < end)
+            .collect();
+        if words_in_sentence.is_empty() {
+            cues.push(Cue {
+                start: sentence.audio_offset,
+                end,
+                text: sentence.text.trim().to_string(),
+            });
+        } else {
+            cues.extend(split_words(Some(&sentence.text), &words_in_sentence));
+        }
+    }
+    cues
+}
+
+/// Group words into cues of at most [`MAX_CUE_CHARS`] characters.
+///
+/// If the source text is available, cue text is sliced from it so that
+/// spacing and punctuation are preserved.
+fn split_words(source: Option<&str>, words: &[&BoundaryEvent]) -> Vec<Cue> {
+    let mut cues = Vec::new();
+    let mut cursor = 0;
+    let mut group: Vec<&BoundaryEvent> = Vec::new();
+    let mut group_start_pos = 0;
+    let mut group_chars = 0;
+    let flush = |group: &mut Vec<&BoundaryEvent>, text: String, cues: &mut Vec<Cue>| {
+        if let (Some(first), Some(last)) = (group.first(), group.last()) {
+            cues.push(Cue {
+                start: first.audio_offset,
+                end: last.audio_offset + last.duration,
+                text: text.trim().to_string(),
+            });
+        }
+        group.clear();
+    };
+    for word in words {
+        let word_chars = word.text.chars().count();
+        if !group.is_empty() && group_chars + word_chars + 1 > MAX_CUE_CHARS {
+            let text = match source {
+                Some(source) => source[group_start_pos..cursor].to_string(),
+                None => join_words(&group),
+            };
+            flush(&mut group, text, &mut cues);
+            group_start_pos = cursor;
+            group_chars = 0;
+        }
+        if let Some(source) = source {
+            cursor = advance_past_word(source, cursor, word);
+        }
+        group_chars += word_chars + 1;
+        group.push(word);
+    }
+    let text = match source {
+        Some(source) => source[group_start_pos..].to_string(),
+        None => join_words(&group),
+    };
+    flush(&mut group, text, &mut cues);
+    cues
+}
+
+/// The position in `source` right after the word that is expected at `cursor`.
+///
+/// The words are reported in the order they are spoken, so the word is never searched for
+/// further ahead in the source. If the spoken text differs from the source, e.g. "10" is
+/// normalized to "ten", the cursor is advanced by the length of the word in the metadata instead,
+/// so that later words don't match earlier occurrences.
+fn advance_past_word(source: &str, cursor: usize, word: &BoundaryEvent) -> usize {
+    let rest = &source[cursor..];
+    let start = cursor + (rest.len() - rest.trim_start().len());
+    if source[start..].starts_with(word.text.as_str()) {
+        return start + word.text.len();
+    }
+    source[start..]
+        .char_indices()
+        .nth(word.text.chars().count())
+        .map_or(source.len(), |(pos, _)| start + pos)
+}
+
+fn join_words(words: &[&BoundaryEvent]) -> String {
+    words
+        .iter()
+        .map(|w| w.text.as_str())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(index: u64, text: &str) -> BoundaryEvent {
+        BoundaryEvent::new(
+            Duration::from_millis(index * 500),
+            Duration::from_millis(400),
+            text,
+        )
+    }
+
+    #[test]
+    fn words_are_matched_in_order() {
+        let source = "I have 10 apples and 10 pears";
+        let mut cursor = 0;
+        let mut ends = Vec::new();
+        for (i, text) in ["I", "have", "ten", "apples", "and", "10", "pears"]
+            .into_iter()
+            .enumerate()
+        {
+            cursor = advance_past_word(source, cursor, &word(i as u64, text));
+            ends.push(cursor);
+        }
+        // "ten" doesn't occur in the source, the cursor must not stay before "10"
+        assert_eq!(&source[..ends[2]], "I have 10 ");
+        assert_eq!(&source[..ends[3]], "I have 10 apples");
+        // The second "10" is matched, not the first one
+        assert_eq!(&source[..ends[5]], "I have 10 apples and 10");
+        assert_eq!(ends[6], source.len());
+    }
+
+    #[test]
+    fn long_sentences_are_split_at_word_boundaries() {
+        let source = "word ".repeat(30);
+        let words = (0..30).map(|i| word(i, "word")).collect::<Vec<_>>();
+        let cues = split_words(Some(&source), &words.iter().collect::<Vec<_>>());
+        assert_eq!(cues.len(), 2);
+        assert!(cues
+            .iter()
+            .all(|cue| cue.text.chars().count() <= MAX_CUE_CHARS));
+        assert_eq!(
+            cues[1].start,
+            words[cues[0].text.split(' ').count()].audio_offset
+        );
+        assert_eq!(cues[1].end, Duration::from_millis(29 * 500 + 400));
+    }
+
+    #[test]
+    fn timestamps() {
+        let time = Duration::from_millis(3_723_004);
+        assert_eq!(SubtitleFormat::Srt.timestamp(time), "01:02:03,004");
+        assert_eq!(SubtitleFormat::WebVtt.timestamp(time), "01:02:03.004");
+    }
+}
@@ -0,0 +1,328 @@
+//! Multi-speaker dialogue scripts.
+//!
+//! A dialogue script starts with a header that declares the speakers,
+//! followed by the lines of the dialogue, each prefixed by the name of its speaker:
+//!
+//! ```text
+//! # Lines starting with `#` are comments
+//! @ALICE voice=en-US-JennyNeural style=cheerful rate=fast
+//! @BOB voice=en-US-GuyNeural role=OlderAdultMale pitch=-5% styledegree=1.5
+//!
+//! ALICE: Hello, Bob!
+//! BOB: Hi, Alice.
+//!   Indented lines continue the previous line.
+//! ```
+//!
+//! A declaration takes `key=value` options. `voice` is required while
+//! `style`, `role`, `styledegree`, `rate` and `pitch` are optional and accept the same values as the CLI options.
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+};
+
+use strum::AsRefStr;
+
+use crate::{
+    parse::{parse_pitch, parse_rate, parse_style_degree},
+    ContentBuilder, RichSsmlOptions, Role, SsmlDocument, TextOptions, TextSplitter,
+};
+
+/// The maximum number of `<voice>` elements that the service accepts in a single request.
+const MAX_VOICES_PER_DOCUMENT: usize = 50;
+
+/// A parsed multi-speaker dialogue script.
+///
+/// ```
+/// use aspeak::{DialogueScript, TextSplitter};
+///
+/// let script = DialogueScript::parse(
+///     "@ALICE voice=en-US-JennyNeural style=cheerful\n\
+///      @BOB voice=en-US-GuyNeural rate=slow\n\
+///      ALICE: Hello, Bob!\n\
+///      BOB: Hi, Alice.",
+/// )
+/// .unwrap();
+/// assert_eq!(script.lines().len(), 2);
+/// let documents = script.to_ssml_documents(&TextSplitter::default());
+/// assert_eq!(documents.len(), 1);
+/// assert!(documents[0].to_ssml().unwrap().contains("en-US-GuyNeural"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct DialogueScript {
+    pub(crate) speakers: HashMap<String, TextOptions<'static>>,
+    pub(crate) lines: Vec<DialogueLine>,
+}
+
+/// A line of a [`DialogueScript`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DialogueLine {
+    pub(crate) speaker: String,
+    pub(crate) text: String,
+}
+
+impl DialogueLine {
+    /// The name of the speaker
+    pub fn speaker(&self) -> &str {
+        &self.speaker
+    }
+
+    /// The text spoken by the speaker
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+impl DialogueScript {
+    /// Parse a dialogue script.
+    pub fn parse(script: &str) -> Result<Self, DialogueError> {
+        let mut speakers = HashMap::new();
+        let mut lines: Vec<DialogueLine> = Vec::new();
+        for (index, line) in script.lines().enumerate() {
+            let line_number = index + 1;
+            let error = |kind| DialogueError {
+                kind,
+                line: Some(line_number),
+                source: None,
+            };
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(declaration) = line.strip_prefix('@') {
+                if !lines.is_empty() {
+                    return Err(error(DialogueErrorKind::LateDeclaration));
+                }
+                let (name, options) = parse_declaration(declaration).map_err(|mut e| {
+                    e.line = Some(line_number);
+                    e
+                })?;
+                if speakers.contains_key(&name) {
+                    return Err(error(DialogueErrorKind::DuplicateSpeaker(name)));
+                }
+                speakers.insert(name, options);
+            } else if line.starts_with(char::is_whitespace) {
+                // Continuation of the previous line
+                let previous = lines
+                    .last_mut()
+                    .ok_or_else(|| error(DialogueErrorKind::InvalidLine))?;
+                previous.text.push(' ');
+                previous.text.push_str(line.trim());
+            } else {
+                let (speaker, text) = line
+                    .split_once(':')
+                    .filter(|(speaker, _)| !speaker.contains(char::is_whitespace))
+                    .ok_or_else(|| error(DialogueErrorKind::InvalidLine))?;
+                if !speakers.contains_key(speaker) {
+                    return Err(error(DialogueErrorKind::UnknownSpeaker(
+                        speaker.to_string(),
+                    )));
+                }
+                lines.push(DialogueLine {
+                    speaker: speaker.to_string(),
+                    text: text.trim().to_string(),
+                });
+            }
+        }
+        if lines.is_empty() {
+            return Err(DialogueError {
+                kind: DialogueErrorKind::NoDialogue,
+                line: None,
+                source: None,
+            });
+        }
+        Ok(Self { speakers, lines })
+    }
+
+    /// The options of the given speaker
+    pub fn speaker(&self, name: &str) -> Option<&TextOptions<'static>> {
+        self.speakers.get(name)
+    }
+
+    /// The lines of the dialogue
+    pub fn lines(&self) -> &[DialogueLine] {
+        &self.lines
+    }
+
+    /// Convert the whole dialogue into a single SSML document with a `<voice>` element per line.
+    pub fn to_ssml_document(&self) -> SsmlDocument {
+        self.lines
+            .iter()
+            .fold(SsmlDocument::builder(), |builder, line| {
+                builder.voice(self.speakers[&line.speaker].voice(), |content| {
+                    self.speak(content, &line.speaker, &line.text)
+                })
+            })
+            .build()
+    }
+
+    /// Convert the dialogue into SSML documents that can be synthesized by separate requests.
+    ///
+    /// Every document contains at most [`TextSplitter::max_chars`] characters of text
+    /// and at most 50 `<voice>` elements. Lines that are too long are split by the [`TextSplitter`].
+    pub fn to_ssml_documents(&self, splitter: &TextSplitter) -> Vec<SsmlDocument> {
+        let mut documents = Vec::new();
+        let mut builder = SsmlDocument::builder();
+        let (mut chars, mut voices) = (0, 0);
+        for line in &self.lines {
+            for chunk in splitter.split(&line.text) {
+                let len = chunk.chars().count();
+                if voices > 0
+                    && (chars + len > splitter.max_chars() || voices == MAX_VOICES_PER_DOCUMENT)
+                {
+                    documents.push(std::mem::take(&mut builder).build());
+                    (chars, voices) = (0, 0);
+                }
+                builder = builder.voice(self.speakers[&line.speaker].voice(), |content| {
+                    self.speak(content, &line.speaker, chunk)
+                });
+                chars += len;
+                voices += 1;
+            }
+        }
+        if voices > 0 {
+            documents.push(builder.build());
+        }
+        documents
+    }
+
+    fn speak(&self, content: ContentBuilder, speaker: &str, text: &str) -> ContentBuilder {
+        content.text_options(&self.speakers[speaker], |content| content.text(text))
+    }
+}
+
+impl FromStr for DialogueScript {
+    type Err = DialogueError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+fn parse_declaration(declaration: &str) -> Result<(String, TextOptions<'static>), DialogueError> {
+    let error = |kind| DialogueError {
+        kind,
+        line: None,
+        source: None,
+    };
+    let mut parts = declaration.split_whitespace();
+    let name = parts
+        .next()
+        .ok_or_else(|| error(DialogueErrorKind::InvalidDeclaration))?;
+    let mut options = TextOptions::builder();
+    let mut rich_ssml_options = RichSsmlOptions::builder();
+    let (mut voice, mut rich) = (None, false);
+    for part in parts {
+        let (key, value) = part
+            .split_once('=')
+            .ok_or_else(|| error(DialogueErrorKind::InvalidDeclaration))?;
+        let invalid_option = |e: crate::parse::ParseError| DialogueError {
+            kind: DialogueErrorKind::InvalidOption(key.to_string()),
+            line: None,
+            source: Some(e.into()),
+        };
+        match key {
+            "voice" => voice = Some(value.to_string()),
+            "pitch" => {
+                options = options.pitch(parse_pitch(value).map_err(invalid_option)?.into_owned())
+            }
+            "rate" => {
+                options = options.rate(parse_rate(value).map_err(invalid_option)?.into_owned())
+            }
+            "style" => rich_ssml_options = rich_ssml_options.style(value.to_string()),
+            "role" => {
+                rich_ssml_options =
+                    rich_ssml_options.role(Role::from_str(value).map_err(|e| DialogueError {
+                        kind: DialogueErrorKind::InvalidOption(key.to_string()),
+                        line: None,
+                        source: Some(e.into()),
+                    })?)
+            }
+            "styledegree" => {
+                rich_ssml_options = rich_ssml_options
+                    .style_degree(parse_style_degree(value).map_err(invalid_option)?)
+            }
+            _ => return Err(error(DialogueErrorKind::UnknownOption(key.to_string()))),
+        }
+        rich |= matches!(key, "style" | "role" | "styledegree");
+    }
+    let voice = voice.ok_or_else(|| error(DialogueErrorKind::MissingVoice(name.to_string())))?;
+    if rich {
+        options = options.chain_rich_ssml_options_builder(rich_ssml_options);
+    }
+    Ok((name.to_string(), options.voice(voice).build()))
+}
+
+/// Errors that can occur while parsing a [`DialogueScript`]
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct DialogueError {
+    pub kind: DialogueErrorKind,
+    /// The 1-based line number where the error occurred, if any.
+    pub line: Option<usize>,
+    pub(crate) source: Option<anyhow::Error>,
+}
+
+impl Display for DialogueError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        use DialogueErrorKind::*;
+        write!(f, "dialogue error: ")?;
+        if let Some(line) = self.line {
+            write!(f, "line {line}: ")?;
+        }
+        match &self.kind {
+            InvalidDeclaration => {
+                write!(
+                    f,
+                    "invalid speaker declaration, expected `@NAME key=value ...`"
+                )
+            }
+            DuplicateSpeaker(name) => write!(f, "speaker {name} is declared more than once"),
+            MissingVoice(name) => write!(f, "no voice is specified for speaker {name}"),
+            UnknownOption(key) => write!(f, "unknown option {key}"),
+            InvalidOption(key) => write!(f, "invalid value for option {key}"),
+            LateDeclaration => write!(f, "speakers must be declared before the dialogue"),
+            UnknownSpeaker(name) => write!(f, "speaker {name} is not declared"),
+            InvalidLine => write!(f, "expected a line like `NAME: text`"),
+            NoDialogue => write!(f, "the script contains no dialogue"),
+        }
+    }
+}
+
+impl Error for DialogueError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_ref().map(|e| e.as_ref() as _)
+    }
+}
+
+#[cfg(feature = "python")]
+impl From<DialogueError> for pyo3::PyErr {
+    fn from(value: DialogueError) -> Self {
+        pyo3::exceptions::PyValueError::new_err(format!("{:?}", color_eyre::Report::from(value)))
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, AsRefStr)]
+#[non_exhaustive]
+#[strum(serialize_all = "title_case")]
+pub enum DialogueErrorKind {
+    /// The speaker declaration is malformed.
+    InvalidDeclaration,
+    /// The speaker is declared more than once.
+    DuplicateSpeaker(String),
+    /// The speaker declaration does not specify a voice.
+    MissingVoice(String),
+    /// The speaker declaration contains an unknown option.
+    UnknownOption(String),
+    /// The value of an option is invalid.
+    InvalidOption(String),
+    /// A speaker is declared after the dialogue has started.
+    LateDeclaration,
+    /// The line is spoken by a speaker that is not declared.
+    UnknownSpeaker(String),
+    /// The line is neither a declaration nor a line of the dialogue.
+    InvalidLine,
+    /// The script does not contain any line of the dialogue.
+    NoDialogue,
+}
@@ -21,6 +21,17 @@
 //! let config = SynthesizerConfig::new(auth, AudioFormat::Riff16Khz16BitMonoPcm);
 //! ```
 //!
+//! If you don't want to send the subscription key with every request,
+//! a [TokenIssuer][crate::TokenIssuer] can exchange it for short-lived tokens that are refreshed automatically.
+//!
+//! ```ignore
+//! use aspeak::TokenIssuer;
+//!
+//! let auth = AuthOptionsBuilder::new(get_rest_endpoint_by_region("eastus"))
+//!     .token_issuer(TokenIssuer::from_region("eastus", "YOUR_AZURE_SUBSCRIPTION_KEY"))
+//!     .build();
+//! ```
+//!
 //! ## RESTful Synthesizer
 //!
 //! Then, you can create a [RestSynthesizer][crate::synthesizer::RestSynthesizer]
@@ -74,6 +85,29 @@
 //!
 //! The full code can be found in [examples/04-websocket-synthesizer-simple.rs](https://github.com/kxxt/aspeak/blob/main/examples/04-websocket-synthesizer-simple.rs)
 //!
+//! If you want to start processing the audio before the whole synthesis is done,
+//! you can get a stream of audio chunks instead.
+//!
+//! ```ignore
+//! use futures_util::TryStreamExt;
+//! let mut stream = std::pin::pin!(ws_syn.synthesize_ssml_stream(ssml).await?);
+//! while let Some(chunk) = stream.try_next().await? {
+//!     // Do something with the chunk
+//! }
+//! ```
+//!
+//! The websocket API can also emit metadata like word boundaries, sentence boundaries, visemes and bookmarks.
+//! Enable them with [MetadataOptions][crate::synthesizer::MetadataOptions] and they will be returned along with the audio.
+//!
+//! ```ignore
+//! use aspeak::synthesizer::MetadataOptions;
+//! *ws_syn.metadata_options_mut() = MetadataOptions::builder().word_boundary(true).build();
+//! let output = ws_syn.synthesize_ssml_with_metadata(ssml).await?;
+//! for event in output.events {
+//!     println!("{:?}", event);
+//! }
+//! ```
+//!
 //! # Unified synthesizer trait
 //!
 //! There is also a unified synthesizer trait [Synthesizer][crate::synthesizer::UnifiedSynthesizer] that can be used to
@@ -95,19 +129,31 @@
 //! - `websocket-synthesizer`: Enable the Websocket synthesizer.
 //! - `unified-synthesizer`: Enable the unified synthesizer trait.
 //! - `synthesizers`: Enable all synthesizers.
+//! - `external-credentials`: Enable [FileCredential][crate::FileCredential] and [CommandCredential][crate::CommandCredential],
+//!   which read credentials from files and commands.
+//! - `record-replay`: Enable recording exchanges with the service into fixtures and replaying them,
+//!   see [transport][crate::transport].
+//! - `cache`: Enable [CachedSynthesizer][crate::synthesizer::CachedSynthesizer], an on-disk cache of synthesized audio.
+//! - `markdown`: Enable converting Markdown to SSML with [markdown_to_ssml][crate::markdown_to_ssml].
+//! - `mock-server`: Enable the [mock][crate::mock] module, a local mock of the Azure TTS service for testing.
 
 mod audio;
 mod auth;
+mod chunk;
 mod constants;
+mod dialogue;
 mod errors;
+#[cfg(feature = "mock-server")]
+pub mod mock;
 #[cfg(feature = "websocket-synthesizer")]
 mod msg;
 #[cfg(feature = "websocket-synthesizer")]
 mod net;
-#[cfg(feature = "python")]
 mod parse;
 mod ssml;
 pub mod synthesizer;
+#[cfg(any(feature = "rest-synthesizer", feature = "websocket-synthesizer"))]
+pub mod transport;
 mod types;
 mod utils;
 pub mod voice;
@@ -117,13 +163,28 @@ pub fn get_websocket_endpoint_by_region(region: &str) -> String {
     format!("wss://{region}.tts.speech.microsoft.com/cognitiveservices/websocket/v1")
 }
 
+/// Get the official endpoint that issues tokens by its region (e.g. `eastus`)
+pub fn get_issue_token_endpoint_by_region(region: &str) -> String {
+    format!("https://{region}.api.cognitive.microsoft.com/sts/v1.0/issueToken")
+}
+
 /// Get the official REST endpoint by its region (e.g. `eastus`)
 pub fn get_rest_endpoint_by_region(region: &str) -> String {
     format!("https://{region}.tts.speech.microsoft.com/cognitiveservices/v1")
 }
 
-pub use audio::{AudioFormat, AudioFormatParseError, QUALITY_MAP, QUALITY_RANGE_MAP};
+pub use audio::{
+    audio_duration, join_audio, AudioCodec, AudioContainer, AudioFormat, AudioFormatParseError,
+    AudioJoinError, AudioJoinErrorKind, QUALITY_MAP, QUALITY_RANGE_MAP,
+};
+#[cfg(feature = "audio")]
+pub use audio::{
+    open_output_stream, output_device_names, play_borrowed_audio_blocking,
+    play_owned_audio_blocking, play_owned_audio_blocking_on, AudioError, AudioErrorKind,
+};
 pub use auth::*;
+pub use chunk::{TextSplitter, DEFAULT_CHUNK_SIZE};
+pub use dialogue::{DialogueError, DialogueErrorKind, DialogueLine, DialogueScript};
 use phf::phf_map;
 pub use ssml::*;
 pub use types::*;
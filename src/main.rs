@@ -4,15 +4,28 @@ use std::{
     borrow::Cow,
     error::Error,
     fmt::{self, Display, Formatter},
-    path::PathBuf,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::Duration,
 };
 
-use cli::{commands::Command, Cli};
+use cli::{
+    batch::{self, BatchContext, BatchState},
+    book::{self, Book},
+    commands::Command,
+    playback::{self, StreamingPlayer},
+    serve::{self, ServerState},
+    subtitles::{self, SubtitleFormat},
+    Cli, SubtitlesProcessor,
+};
 
 use aspeak::{
-    synthesizer::{SynthesizerConfig, UnifiedSynthesizer},
+    audio_duration, interpolate_ssml, join_audio, markdown_to_ssml_chunks, output_device_names,
+    synthesizer::{CachedSynthesizer, SynthesisCache, SynthesizerConfig, UnifiedSynthesizer},
+    validate_ssml,
     voice::{VoiceListAPIAuth, VoiceListAPIEndpoint, VoiceListAPIError, VoiceListAPIErrorKind},
-    AudioFormat, Voice, QUALITY_MAP,
+    AudioContainer, AudioFormat, AuthOptions, DialogueScript, TextSplitter, Voice, QUALITY_MAP,
 };
 use clap::Parser;
 use color_eyre::{
@@ -20,17 +33,18 @@ use color_eyre::{
     Help,
 };
 use colored::Colorize;
+use futures_util::{pin_mut, TryStreamExt};
 
 use env_logger::WriteStyle;
-use log::debug;
+use log::{debug, info, warn};
 
 use reqwest::header::HeaderMap;
 use strum::IntoEnumIterator;
 
 use crate::cli::{
-    args::{Color, SynthesizerMode},
+    args::{Color, InputArgs, InputFormat, OutputArgs, SynthesizerMode},
     commands::ConfigCommand,
-    config::{Config, EndpointConfig},
+    config::Config,
 };
 
 #[derive(Debug)]
@@ -56,13 +70,191 @@ impl Error for CliError {
 async fn synthesizer_by_mode(
     conf: SynthesizerConfig<'_>,
     mode: SynthesizerMode,
+    cache: Option<&SynthesisCache>,
 ) -> color_eyre::eyre::Result<Box<dyn UnifiedSynthesizer>> {
-    Ok(match mode {
+    let endpoint = conf.auth().endpoint().to_string();
+    let synthesizer: Box<dyn UnifiedSynthesizer> = match mode {
         SynthesizerMode::Websocket => Box::new(conf.connect_websocket().await?),
         SynthesizerMode::Rest => Box::new(conf.rest_synthesizer()?),
+    };
+    Ok(match cache {
+        Some(cache) => Box::new(CachedSynthesizer::new(synthesizer, cache.clone(), endpoint)),
+        None => synthesizer,
+    })
+}
+
+/// Where the audio goes while it is being synthesized
+#[derive(Debug, Clone, PartialEq)]
+enum ProgressiveOutput {
+    /// Play on the given output device, or on the default device
+    Play(Option<String>),
+    Stdout,
+}
+
+/// How the audio can be output while it is being synthesized, if at all.
+///
+/// Only the websocket API streams the audio. With a cache, the complete audio is needed to fill it.
+/// Clips of most formats can only be joined after all of them have been synthesized,
+/// so they are only written to stdout progressively if there is a single clip.
+///
+/// Formats that cannot be played are rejected before anything is synthesized.
+fn progressive_output(
+    mode: SynthesizerMode,
+    output_args: &OutputArgs,
+    cache: Option<&SynthesisCache>,
+    audio_format: AudioFormat,
+    clips: usize,
+    device: Option<String>,
+) -> color_eyre::eyre::Result<Option<ProgressiveOutput>> {
+    if output_args.output.is_none() && !playback::is_playable(audio_format) {
+        let format: &str = audio_format.into();
+        return Err(anyhow!("Cannot play audio in the format {format}")
+            .suggestion("Use -o to save the audio to a file, or choose an MP3 or PCM format."));
+    }
+    if mode != SynthesizerMode::Websocket || output_args.subtitles.is_some() || cache.is_some() {
+        return Ok(None);
+    }
+    Ok(match output_args.output.as_deref() {
+        None => Some(ProgressiveOutput::Play(device)),
+        Some("-") if clips == 1 || audio_format.container() == AudioContainer::Raw => {
+            Some(ProgressiveOutput::Stdout)
+        }
+        _ => None,
     })
 }
 
+/// Synthesize the SSML documents and output the audio while it is being received.
+async fn synthesize_progressively(
+    conf: SynthesizerConfig<'_>,
+    ssml_chunks: &[String],
+    output: ProgressiveOutput,
+) -> color_eyre::eyre::Result<()> {
+    let mut synthesizer = conf.connect_websocket().await?;
+    let mut player = match output {
+        ProgressiveOutput::Play(device) => {
+            Some(StreamingPlayer::start(synthesizer.audio_format(), device))
+        }
+        ProgressiveOutput::Stdout => None,
+    };
+    let mut stdout = io::stdout().lock();
+    for (i, ssml) in ssml_chunks.iter().enumerate() {
+        debug!("Synthesizing chunk {}/{}", i + 1, ssml_chunks.len());
+        if let Some(player) = &mut player {
+            player.begin();
+        }
+        let stream = synthesizer.synthesize_ssml_stream(ssml).await?;
+        pin_mut!(stream);
+        while let Some(chunk) = stream.try_next().await? {
+            match &mut player {
+                Some(player) => player.push(&chunk),
+                None => {
+                    stdout.write_all(&chunk)?;
+                    stdout.flush()?;
+                }
+            }
+        }
+    }
+    player.map_or(Ok(()), StreamingPlayer::finish)
+}
+
+async fn request_voices(
+    url: &str,
+    auth_options: &AuthOptions<'_>,
+) -> color_eyre::eyre::Result<Vec<Voice>> {
+    let auth = match (auth_options.key(), auth_options.token()) {
+        (_, Some(token)) => Some(VoiceListAPIAuth::AuthToken(token)),
+        (Some(key), None) => Some(VoiceListAPIAuth::SubscriptionKey(key)),
+        (None, None) => None,
+    };
+    let voices_result = Voice::request_available_voices_with_additional_headers(
+        VoiceListAPIEndpoint::Url(url),
+        auth,
+        auth_options.proxy(),
+        Some(HeaderMap::from_iter(
+            auth_options.headers().iter().map(Clone::clone),
+        )),
+    )
+    .await;
+    Ok(
+        if let Err(VoiceListAPIError {
+            kind: VoiceListAPIErrorKind::Response,
+            ..
+        }) = voices_result
+        {
+            voices_result.with_note(|| "Maybe you are not authorized. Did you specify an auth token or a subscription key? Did the key/token expire?")?
+        } else {
+            voices_result?
+        },
+    )
+}
+
+async fn synthesize_with_subtitles(
+    mut conf: SynthesizerConfig<'_>,
+    ssml_chunks: &[String],
+    subtitles_callback: SubtitlesProcessor,
+) -> color_eyre::eyre::Result<Vec<u8>> {
+    *conf.metadata_options_mut() = SubtitleFormat::metadata_options();
+    let mut synthesizer = conf.connect_websocket().await?;
+    let audio_format = synthesizer.audio_format();
+    let mut clips = Vec::with_capacity(ssml_chunks.len());
+    let mut events = Vec::new();
+    let mut offset = Duration::ZERO;
+    for ssml in ssml_chunks {
+        let output = synthesizer.synthesize_ssml_with_metadata(ssml).await?;
+        // The events usually end before the audio, which has trailing silence
+        let end = audio_duration(audio_format, &output.audio).unwrap_or_else(|| {
+            warn!("Cannot determine the duration of the audio, subtitles might drift");
+            subtitles::events_end(&output.events)
+        });
+        events.extend(
+            output
+                .events
+                .into_iter()
+                .map(|event| subtitles::shift_event(event, offset)),
+        );
+        offset += end;
+        clips.push(output.audio);
+    }
+    subtitles_callback(&events)?;
+    Ok(join_audio(audio_format, &clips)?)
+}
+
+/// Synthesize the SSML documents into the outputs of the [`OutputArgs`]
+async fn synthesize_chunks(
+    mode: SynthesizerMode,
+    auth_options: AuthOptions<'_>,
+    audio_format: AudioFormat,
+    output_args: OutputArgs,
+    cache: Option<&SynthesisCache>,
+    config: Option<&Config>,
+    ssml_chunks: &[String],
+) -> color_eyre::eyre::Result<()> {
+    let device = output_args.device(config.and_then(|c| c.output.as_ref()));
+    let progressive = progressive_output(
+        mode,
+        &output_args,
+        cache,
+        audio_format,
+        ssml_chunks.len(),
+        device.clone(),
+    )?;
+    let subtitles_callback =
+        Cli::process_subtitles_output(output_args.subtitles, output_args.overwrite)?;
+    let callback = Cli::process_output(output_args.output, output_args.overwrite, device)?;
+    let conf = SynthesizerConfig::new(auth_options, audio_format);
+    if let Some(output) = progressive {
+        return synthesize_progressively(conf, ssml_chunks, output).await;
+    }
+    let audio_data = if let Some(subtitles_callback) = subtitles_callback {
+        synthesize_with_subtitles(conf, ssml_chunks, subtitles_callback).await?
+    } else {
+        let mut synthesizer = synthesizer_by_mode(conf, mode, cache).await?;
+        synthesizer.process_ssml_chunked(ssml_chunks).await?
+    };
+    callback(audio_data)?;
+    Ok(())
+}
+
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> color_eyre::eyre::Result<()> {
     let mut cli = Cli::parse();
@@ -86,14 +278,33 @@ async fn main() -> color_eyre::eyre::Result<()> {
         .init();
     debug!("Commandline args: {cli:?}");
     debug!("Profile: {config:?}");
-    let Cli { command, auth, .. } = cli;
+    let Cli {
+        command,
+        auth,
+        cache,
+        ..
+    } = cli;
+    let cache = cache.open_cache()?;
     match command.unwrap_or_default() {
+        Command::Ssml {
+            ssml,
+            check: true,
+            input_args,
+            ..
+        } => {
+            let ssml = ssml
+                .ok_or(CliError::Input)
+                .or_else(|_| Cli::process_input_text(&input_args))?;
+            validate_ssml(&ssml)?;
+            println!("No issues found in the SSML");
+        }
         Command::Ssml {
             ssml,
             input_args,
             output_args,
+            ..
         } => {
-            let mode = Cli::get_synthesizer_mode(&input_args, &config);
+            let mode = Cli::get_synthesizer_mode(&input_args, &output_args, &config)?;
             let auth_options =
                 auth.to_auth_options(config.as_ref().and_then(|c| c.auth.as_ref()), mode)?;
             debug!("Auth options: {auth_options:?}");
@@ -102,18 +313,23 @@ async fn main() -> color_eyre::eyre::Result<()> {
                 .or_else(|_| Cli::process_input_text(&input_args))?;
             let audio_format =
                 output_args.get_audio_format(config.as_ref().and_then(|c| c.output.as_ref()))?;
-            let callback = Cli::process_output(output_args.output, output_args.overwrite)?;
-            let conf = SynthesizerConfig::new(auth_options, audio_format);
-            let mut synthesizer = synthesizer_by_mode(conf, mode).await?;
-            let audio_data = synthesizer.process_ssml(&ssml).await?;
-            callback(audio_data)?;
+            synthesize_chunks(
+                mode,
+                auth_options,
+                audio_format,
+                output_args,
+                cache.as_ref(),
+                config.as_ref(),
+                &[ssml],
+            )
+            .await?;
         }
         Command::Text {
             text_args,
             input_args,
             output_args,
         } => {
-            let mode = Cli::get_synthesizer_mode(&input_args, &config);
+            let mode = Cli::get_synthesizer_mode(&input_args, &output_args, &config)?;
             let auth_options =
                 auth.to_auth_options(config.as_ref().and_then(|c| c.auth.as_ref()), mode)?;
             debug!("Auth options: {auth_options:?}");
@@ -125,16 +341,242 @@ async fn main() -> color_eyre::eyre::Result<()> {
                 .or_else(|_| Cli::process_input_text(&input_args).map(Cow::Owned))?;
             let audio_format =
                 output_args.get_audio_format(config.as_ref().and_then(|c| c.output.as_ref()))?;
-            let callback = Cli::process_output(output_args.output, output_args.overwrite)?;
-            let conf = SynthesizerConfig::new(auth_options, audio_format);
-            let mut synthesizer = synthesizer_by_mode(conf, mode).await?;
             let options = &Cli::process_text_options(
-                &text_args,
+                &text_args.options,
+                config.as_ref().and_then(|c| c.text.as_ref()),
+            )?;
+            let splitter = text_args
+                .chunk_size
+                .map(TextSplitter::new)
+                .unwrap_or_default();
+            let ssml_chunks = if text_args.input_format == InputFormat::Markdown {
+                markdown_to_ssml_chunks(&text, options, &splitter)?
+            } else {
+                splitter
+                    .split(&text)
+                    .into_iter()
+                    .map(|chunk| interpolate_ssml(chunk, options))
+                    .collect::<Result<Vec<_>, _>>()?
+            };
+            synthesize_chunks(
+                mode,
+                auth_options,
+                audio_format,
+                output_args,
+                cache.as_ref(),
+                config.as_ref(),
+                &ssml_chunks,
+            )
+            .await?;
+        }
+        Command::Dialogue {
+            chunk_size,
+            input_args,
+            output_args,
+        } => {
+            let mode = Cli::get_synthesizer_mode(&input_args, &output_args, &config)?;
+            let auth_options =
+                auth.to_auth_options(config.as_ref().and_then(|c| c.auth.as_ref()), mode)?;
+            debug!("Auth options: {auth_options:?}");
+            let script = DialogueScript::parse(&Cli::process_input_text(&input_args)?)?;
+            let splitter = chunk_size.map(TextSplitter::new).unwrap_or_default();
+            let ssml_chunks = script
+                .to_ssml_documents(&splitter)
+                .iter()
+                .map(|document| document.to_ssml())
+                .collect::<Result<Vec<_>, _>>()?;
+            let audio_format =
+                output_args.get_audio_format(config.as_ref().and_then(|c| c.output.as_ref()))?;
+            synthesize_chunks(
+                mode,
+                auth_options,
+                audio_format,
+                output_args,
+                cache.as_ref(),
+                config.as_ref(),
+                &ssml_chunks,
+            )
+            .await?;
+        }
+        Command::Book {
+            input,
+            output_dir,
+            resume,
+            overwrite,
+            encoding,
+            mode,
+            chunk_size,
+            text_options_args,
+            format_args,
+        } => {
+            let mode = Cli::get_synthesizer_mode(
+                &InputArgs {
+                    mode,
+                    ..Default::default()
+                },
+                &OutputArgs::default(),
+                &config,
+            )?;
+            let auth_options =
+                auth.to_auth_options(config.as_ref().and_then(|c| c.auth.as_ref()), mode)?;
+            debug!("Auth options: {auth_options:?}");
+            let book = Book::load(Path::new(&input), encoding.as_deref())?;
+            let audio_format =
+                format_args.get_audio_format(config.as_ref().and_then(|c| c.output.as_ref()))?;
+            let options = &Cli::process_text_options(
+                &text_options_args,
                 config.as_ref().and_then(|c| c.text.as_ref()),
             )?;
-            let result = synthesizer.process_text(&text, options).await;
-            let audio_data = result?;
-            callback(audio_data)?;
+            let splitter = chunk_size.map(TextSplitter::new).unwrap_or_default();
+            let output_dir = Path::new(&output_dir);
+            let files = book.file_names(audio_format.file_extension());
+            if !resume && !overwrite {
+                if let Some(existing) = files
+                    .iter()
+                    .map(String::as_str)
+                    .chain([book::PLAYLIST_FILE, book::METADATA_FILE])
+                    .map(|file| output_dir.join(file))
+                    .find(|path| path.exists())
+                {
+                    return Err(anyhow!("File {} already exists!", existing.display())
+                        .suggestion("You can use --resume to skip the chapters that have been synthesized, or --overwrite to overwrite them."));
+                }
+            }
+            fs::create_dir_all(output_dir)?;
+            let conf = SynthesizerConfig::new(auth_options, audio_format);
+            let mut synthesizer = synthesizer_by_mode(conf, mode, cache.as_ref()).await?;
+            for (i, (chapter, file)) in book.chapters.iter().zip(&files).enumerate() {
+                let path = output_dir.join(file);
+                if resume && path.exists() {
+                    info!(
+                        "Skipping chapter {}: {} already exists",
+                        i + 1,
+                        path.display()
+                    );
+                    continue;
+                }
+                info!(
+                    "Synthesizing chapter {}/{}: {}",
+                    i + 1,
+                    files.len(),
+                    chapter.title
+                );
+                let ssml_chunks = match chapter.format {
+                    InputFormat::Markdown => {
+                        markdown_to_ssml_chunks(&chapter.text, options, &splitter)?
+                    }
+                    InputFormat::Plain => splitter
+                        .split(&chapter.text)
+                        .into_iter()
+                        .map(|chunk| interpolate_ssml(chunk, options))
+                        .collect::<Result<Vec<_>, _>>()?,
+                };
+                let audio_data = synthesizer.process_ssml_chunked(&ssml_chunks).await?;
+                // Write to a temporary file first so that an interrupted run never leaves
+                // a truncated chapter behind, which would be skipped by --resume.
+                let partial = output_dir.join(format!("{file}.part"));
+                fs::write(&partial, audio_data)?;
+                fs::rename(&partial, &path)?;
+            }
+            fs::write(output_dir.join(book::PLAYLIST_FILE), book.playlist(&files))?;
+            fs::write(
+                output_dir.join(book::METADATA_FILE),
+                book.metadata(&files, audio_format)?,
+            )?;
+        }
+        Command::Batch {
+            manifest,
+            state,
+            concurrency,
+            mode,
+            text_options_args,
+            format_args,
+        } => {
+            let mode = Cli::get_synthesizer_mode(
+                &InputArgs {
+                    mode,
+                    ..Default::default()
+                },
+                &OutputArgs::default(),
+                &config,
+            )?;
+            let auth_options =
+                auth.to_auth_options(config.as_ref().and_then(|c| c.auth.as_ref()), mode)?;
+            debug!("Auth options: {auth_options:?}");
+            let manifest = Path::new(&manifest);
+            let items = batch::load_manifest(manifest)?;
+            let state_path = state
+                .map(PathBuf::from)
+                .unwrap_or_else(|| manifest.with_extension("state.jsonl"));
+            let mut state = BatchState::open(&state_path)?;
+            let context = BatchContext {
+                auth_options,
+                mode,
+                cache,
+                audio_format: format_args
+                    .get_audio_format(config.as_ref().and_then(|c| c.output.as_ref()))?,
+                text_options: Cli::process_text_options(
+                    &text_options_args,
+                    config.as_ref().and_then(|c| c.text.as_ref()),
+                )?,
+            };
+            let pending = items
+                .iter()
+                .filter(|item| !state.is_done(item, &context))
+                .collect::<Vec<_>>();
+            let summary = batch::run(&pending, &mut state, &context, concurrency as usize).await?;
+            println!(
+                "{} succeeded, {} failed, {} skipped",
+                summary.succeeded,
+                summary.failed,
+                items.len() - pending.len()
+            );
+            if summary.failed > 0 {
+                return Err(eyre!("Failed to synthesize {} items", summary.failed)
+                    .with_note(|| format!("The errors are recorded in {}", state_path.display()))
+                    .suggestion("Run the same command again to retry the failed items."));
+            }
+        }
+        Command::Serve {
+            listen,
+            mode,
+            concurrency,
+            url,
+            text_options_args,
+            format_args,
+        } => {
+            let mode = Cli::get_synthesizer_mode(
+                &InputArgs {
+                    mode,
+                    ..Default::default()
+                },
+                &OutputArgs::default(),
+                &config,
+            )?;
+            let auth_config = config.as_ref().and_then(|c| c.auth.as_ref());
+            let auth_options = auth.to_auth_options(auth_config, mode)?;
+            debug!("Auth options: {auth_options:?}");
+            let voice_list_url = match auth.voice_list_url(url.as_deref(), auth_config) {
+                Ok(url) => Some(url.into_owned()),
+                Err(_) => {
+                    warn!("No voice list API url is available, GET /voices is disabled");
+                    None
+                }
+            };
+            let state = ServerState::new(
+                auth_options.into_owned(),
+                mode,
+                format_args.get_audio_format(config.as_ref().and_then(|c| c.output.as_ref()))?,
+                Cli::process_text_options(
+                    &text_options_args,
+                    config.as_ref().and_then(|c| c.text.as_ref()),
+                )?
+                .into_owned(),
+                cache,
+                voice_list_url,
+                concurrency as usize,
+            )?;
+            serve::serve(listen, state).await?;
         }
         Command::ListVoices {
             ref voice,
@@ -146,56 +588,11 @@ async fn main() -> color_eyre::eyre::Result<()> {
                 SynthesizerMode::Rest,
             )?;
             debug!("Auth options: {auth_options:?}");
-            // Look for --url first,
-            // then look for auth.voice_list_api in profile,
-            // then try to determine the url by region
-            // otherwise, try to use the trial voice list url
-            let url = url.as_deref().map(Cow::Borrowed).or_else(|| {
-                    config.as_ref().and_then(|c| c.auth.as_ref().and_then(|a| a.voice_list_api.as_deref().map(Cow::Borrowed)))
-                }).or_else(|| {
-                    auth.region.as_deref().or_else(||
-                        config.as_ref().and_then(
-                            |c| c.auth.as_ref().and_then(
-                                |a| a.endpoint_config.as_ref().and_then(
-                                    |e| if let EndpointConfig::Region { ref region } =  e {
-                                        Some(region.as_str())
-                                    } else {
-                                        None
-                                    }
-                                )
-                            )
-                        )
-                    ).map(|r| Cow::Owned(format!("https://{r}.tts.speech.microsoft.com/cognitiveservices/voices/list")))
-                })
-                // .or_else(|| TRIAL_VOICE_LIST_URL.map(Cow::Borrowed))
-                .ok_or_else(
-                    || eyre!("No voice list API url specified!".to_string())
-                        .with_note(|| "The default voice list API that is used in aspeak v4 has been shutdown and is no longer available.")
-                        .with_suggestion(|| "You can still use the list-voices command by specifying a region(authentication needed) or a custom voice list API url.")
-                )?;
-            let auth = match (auth_options.key(), auth_options.token()) {
-                (_, Some(token)) => Some(VoiceListAPIAuth::AuthToken(token)),
-                (Some(key), None) => Some(VoiceListAPIAuth::SubscriptionKey(key)),
-                (None, None) => None,
-            };
-            let voices_result = Voice::request_available_voices_with_additional_headers(
-                VoiceListAPIEndpoint::Url(url.as_ref()),
-                auth,
-                auth_options.proxy(),
-                Some(HeaderMap::from_iter(
-                    auth_options.headers().iter().map(Clone::clone),
-                )),
-            )
-            .await;
-            let voices = if let Err(VoiceListAPIError {
-                kind: VoiceListAPIErrorKind::Response,
-                ..
-            }) = voices_result
-            {
-                voices_result.with_note(|| "Maybe you are not authorized. Did you specify an auth token or a subscription key? Did the key/token expire?")?
-            } else {
-                voices_result?
-            };
+            let url = auth.voice_list_url(
+                url.as_deref(),
+                config.as_ref().and_then(|c| c.auth.as_ref()),
+            )?;
+            let voices = request_voices(&url, &auth_options).await?;
             let voices = voices.iter();
             let locale_id = locale.as_deref();
             let voice_id = voice.as_deref();
@@ -230,6 +627,11 @@ async fn main() -> color_eyre::eyre::Result<()> {
                 println!("{}", Into::<&str>::into(format));
             }
         }
+        Command::ListDevices => {
+            for device in output_device_names()? {
+                println!("{device}");
+            }
+        }
         Command::Config { command } => match command {
             ConfigCommand::Edit => {
                 let path = Config::default_location()?;
@@ -0,0 +1,601 @@
+//! A local mock of the Azure TTS service for testing.
+//!
+//! The [`MockServer`] speaks the REST synthesis endpoint, the websocket synthesis protocol,
+//! the voice list endpoint and the `issueToken` endpoint,
+//! so that integrations can be tested offline.
+//! Errors can be injected with [`MockServer::inject_fault`].
+//!
+//! ```
+//! use aspeak::{mock::MockServer, synthesizer::SynthesizerConfig, AudioFormat, AuthOptionsBuilder};
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let server = MockServer::start().await?;
+//! let auth = AuthOptionsBuilder::new(server.rest_endpoint()).build();
+//! let syn = SynthesizerConfig::new(auth, AudioFormat::Riff24Khz16BitMonoPcm).rest_synthesizer()?;
+//! let ssml = r#"<speak version="1.0" xmlns="http://www.w3.org/2001/10/synthesis" xml:lang="en-US">
+//!     <voice name="en-US-JennyNeural">Hello, world!</voice>
+//! </speak>"#;
+//! let audio = syn.synthesize_ssml(ssml).await?;
+//! assert_eq!(audio, server.audio());
+//! assert_eq!(server.requests()[0].ssml.as_deref(), Some(ssml));
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! This module is only available with the `mock-server` feature.
+
+use std::{
+    collections::{HashSet, VecDeque},
+    convert::Infallible,
+    io,
+    net::{SocketAddr, TcpListener},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures_util::{SinkExt, StreamExt};
+use hyper::{
+    header::{self, HeaderValue},
+    service::{make_service_fn, service_fn},
+    Body, HeaderMap, Method, Request, Response, Server, StatusCode,
+};
+use log::{debug, warn};
+use tokio::sync::oneshot;
+use tokio_tungstenite::{
+    tungstenite::{
+        handshake::derive_accept_key,
+        protocol::{frame::coding::CloseCode, CloseFrame, Role},
+        Message,
+    },
+    WebSocketStream,
+};
+
+const REST_PATH: &str = "/cognitiveservices/v1";
+const WEBSOCKET_PATH: &str = "/cognitiveservices/websocket/v1";
+const VOICE_LIST_PATH: &str = "/cognitiveservices/voices/list";
+const ISSUE_TOKEN_PATH: &str = "/sts/v1.0/issueToken";
+
+/// The audio is sent to websocket clients in chunks of this size.
+const AUDIO_CHUNK_SIZE: usize = 4096;
+/// Every word of the text takes this long in the generated metadata.
+const WORD_DURATION: Duration = Duration::from_millis(300);
+
+const DEFAULT_VOICES: &str = r#"[{"Name":"Microsoft Server Speech Text to Speech Voice (en-US, JennyNeural)","DisplayName":"Jenny","LocalName":"Jenny","ShortName":"en-US-JennyNeural","Gender":"Female","Locale":"en-US","LocaleName":"English (United States)","StyleList":["cheerful","sad"],"SampleRateHertz":"24000","VoiceType":"Neural","Status":"GA","WordsPerMinute":"152"},{"Name":"Microsoft Server Speech Text to Speech Voice (zh-CN, XiaoxiaoNeural)","DisplayName":"Xiaoxiao","LocalName":"晓晓","ShortName":"zh-CN-XiaoxiaoNeural","Gender":"Female","Locale":"zh-CN","LocaleName":"Chinese (Mandarin, Simplified)","StyleList":["cheerful","sad"],"SampleRateHertz":"24000","VoiceType":"Neural","Status":"GA","WordsPerMinute":"285"}]"#;
+
+/// An error injected into the responses of a [`MockServer`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MockFault {
+    /// Respond with 401 Unauthorized
+    Unauthorized,
+    /// Respond with 429 Too Many Requests, optionally with a `Retry-After` header in seconds
+    TooManyRequests { retry_after: Option<u64> },
+    /// Respond with 503 Service Unavailable
+    ServiceUnavailable,
+    /// Close the websocket connection with the given close frame instead of synthesizing.
+    ///
+    /// It only affects websocket requests and stays queued until one arrives.
+    Close { code: u16, reason: String },
+}
+
+/// The kind of a request received by a [`MockServer`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MockRequestKind {
+    /// A synthesis request to the REST endpoint
+    Rest,
+    /// A synthesis request over a websocket connection
+    Websocket,
+    /// A request to the voice list endpoint
+    VoiceList,
+    /// A request to the `issueToken` endpoint
+    IssueToken,
+}
+
+/// A request received by a [`MockServer`]
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct MockRequest {
+    pub kind: MockRequestKind,
+    /// Headers of the HTTP request, or of the websocket handshake
+    pub headers: HeaderMap,
+    /// The SSML of synthesis requests
+    pub ssml: Option<String>,
+    /// The requested output format of synthesis requests
+    pub output_format: Option<String>,
+}
+
+struct State {
+    audio: Vec<u8>,
+    voices: String,
+    key: Option<String>,
+    issued_tokens: HashSet<String>,
+    faults: VecDeque<MockFault>,
+    requests: Vec<MockRequest>,
+}
+
+impl State {
+    /// Take the next fault if it is an HTTP error
+    fn take_http_fault(&mut self) -> Option<MockFault> {
+        match self.faults.front()? {
+            MockFault::Close { .. } => None,
+            _ => self.faults.pop_front(),
+        }
+    }
+
+    /// Take the next fault if it closes the websocket connection
+    fn take_close_fault(&mut self) -> Option<(u16, String)> {
+        match self.faults.front()? {
+            MockFault::Close { .. } => match self.faults.pop_front() {
+                Some(MockFault::Close { code, reason }) => Some((code, reason)),
+                _ => unreachable!(),
+            },
+            _ => None,
+        }
+    }
+
+    /// Check the subscription key or the token in the headers,
+    /// or the token in the `Authorization` query parameter, which is used by websocket clients.
+    fn is_authorized(&self, headers: &HeaderMap, query: Option<&str>) -> bool {
+        let Some(key) = &self.key else {
+            return true;
+        };
+        let is_issued = |authorization: &str| {
+            let token = authorization
+                .strip_prefix("Bearer ")
+                .unwrap_or(authorization);
+            self.issued_tokens.contains(token)
+        };
+        let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+        header("Ocp-Apim-Subscription-Key") == Some(key)
+            || header("Authorization").is_some_and(is_issued)
+            || url::form_urlencoded::parse(query.unwrap_or_default().as_bytes())
+                .any(|(name, value)| name == "Authorization" && is_issued(&value))
+    }
+}
+
+type SharedState = Arc<Mutex<State>>;
+
+/// Builder for [`MockServer`]
+#[derive(Debug, Default)]
+pub struct MockServerBuilder {
+    audio: Option<Vec<u8>>,
+    voices: Option<String>,
+    key: Option<String>,
+}
+
+impl MockServerBuilder {
+    /// Create a new builder with the default settings
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// The audio returned by every synthesis request, regardless of the requested format.
+    ///
+    /// It defaults to 100ms of silence in `riff-24khz-16bit-mono-pcm`.
+    pub fn audio(mut self, audio: impl Into<Vec<u8>>) -> Self {
+        self.audio = Some(audio.into());
+        self
+    }
+
+    /// The JSON returned by the voice list endpoint
+    pub fn voices_json(mut self, voices: impl Into<String>) -> Self {
+        self.voices = Some(voices.into());
+        self
+    }
+
+    /// Require this subscription key, or a token issued for it, on every request.
+    ///
+    /// By default, all requests are accepted.
+    pub fn key(mut self, key: impl Into<String>) -> Self {
+        self.key = Some(key.into());
+        self
+    }
+
+    /// Start the server on a random local port
+    pub async fn start(self) -> io::Result<MockServer> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(State {
+            audio: self.audio.unwrap_or_else(silent_wav),
+            voices: self.voices.unwrap_or_else(|| DEFAULT_VOICES.to_string()),
+            key: self.key,
+            issued_tokens: HashSet::new(),
+            faults: VecDeque::new(),
+            requests: Vec::new(),
+        }));
+        let service_state = state.clone();
+        let make_service = make_service_fn(move |_| {
+            let state = service_state.clone();
+            async move { Ok::<_, Infallible>(service_fn(move |req| handle(state.clone(), req))) }
+        });
+        let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+        let server = Server::from_tcp(listener)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?
+            .serve(make_service)
+            .with_graceful_shutdown(async {
+                shutdown_rx.await.ok();
+            });
+        tokio::spawn(async move {
+            if let Err(e) = server.await {
+                warn!("Mock server error: {e}");
+            }
+        });
+        debug!("Mock server listening on {addr}");
+        Ok(MockServer {
+            addr,
+            state,
+            shutdown: Some(shutdown),
+        })
+    }
+}
+
+/// A local mock of the Azure TTS service
+///
+/// The server runs on the current tokio runtime and stops when it is dropped.
+pub struct MockServer {
+    addr: SocketAddr,
+    state: SharedState,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl MockServer {
+    /// Create a builder for [`MockServer`]
+    pub fn builder() -> MockServerBuilder {
+        MockServerBuilder::new()
+    }
+
+    /// Start a server with the default settings on a random local port
+    pub async fn start() -> io::Result<Self> {
+        MockServerBuilder::new().start().await
+    }
+
+    /// The address the server is listening on
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The endpoint for [`crate::synthesizer::RestSynthesizer`]
+    pub fn rest_endpoint(&self) -> String {
+        format!("http://{}{REST_PATH}", self.addr)
+    }
+
+    /// The endpoint for [`crate::synthesizer::WebsocketSynthesizer`]
+    pub fn websocket_endpoint(&self) -> String {
+        format!("ws://{}{WEBSOCKET_PATH}", self.addr)
+    }
+
+    /// The endpoint for [`crate::Voice::request_available_voices`]
+    pub fn voice_list_endpoint(&self) -> String {
+        format!("http://{}{VOICE_LIST_PATH}", self.addr)
+    }
+
+    /// The endpoint for [`crate::TokenIssuer`]
+    pub fn issue_token_endpoint(&self) -> String {
+        format!("http://{}{ISSUE_TOKEN_PATH}", self.addr)
+    }
+
+    /// The audio returned by synthesis requests
+    pub fn audio(&self) -> Vec<u8> {
+        self.state.lock().unwrap().audio.clone()
+    }
+
+    /// Inject a fault into the response of a future request.
+    ///
+    /// Faults are consumed by the requests in the order they are injected.
+    pub fn inject_fault(&self, fault: MockFault) {
+        self.state.lock().unwrap().faults.push_back(fault);
+    }
+
+    /// The requests received so far
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
+
+/// 100ms of silence in `riff-24khz-16bit-mono-pcm`
+fn silent_wav() -> Vec<u8> {
+    const SAMPLE_RATE: u32 = 24000;
+    let data_len = SAMPLE_RATE / 10 * 2;
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&1u16.to_le_bytes()); // Mono
+    wav.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    wav.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes()); // Byte rate
+    wav.extend_from_slice(&2u16.to_le_bytes()); // Block align
+    wav.extend_from_slice(&16u16.to_le_bytes()); // Bits per sample
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    wav.resize(44 + data_len as usize, 0);
+    wav
+}
+
+fn status_response(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::from(status.canonical_reason().unwrap_or_default()))
+        .unwrap()
+}
+
+fn fault_response(fault: MockFault) -> Response<Body> {
+    match fault {
+        MockFault::Unauthorized => status_response(StatusCode::UNAUTHORIZED),
+        MockFault::TooManyRequests { retry_after } => {
+            let mut response = status_response(StatusCode::TOO_MANY_REQUESTS);
+            if let Some(retry_after) = retry_after {
+                response
+                    .headers_mut()
+                    .insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+            }
+            response
+        }
+        MockFault::ServiceUnavailable => status_response(StatusCode::SERVICE_UNAVAILABLE),
+        MockFault::Close { .. } => {
+            unreachable!("close faults are only taken by websocket requests")
+        }
+    }
+}
+
+async fn handle(state: SharedState, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    debug!("Mock server received {} {}", req.method(), req.uri());
+    let kind = match (req.method(), req.uri().path()) {
+        (&Method::POST, REST_PATH) => MockRequestKind::Rest,
+        (&Method::GET, WEBSOCKET_PATH) => MockRequestKind::Websocket,
+        (&Method::GET, VOICE_LIST_PATH) => MockRequestKind::VoiceList,
+        (&Method::POST, ISSUE_TOKEN_PATH) => MockRequestKind::IssueToken,
+        _ => return Ok(status_response(StatusCode::NOT_FOUND)),
+    };
+    {
+        let mut state = state.lock().unwrap();
+        if !state.is_authorized(req.headers(), req.uri().query()) {
+            return Ok(status_response(StatusCode::UNAUTHORIZED));
+        }
+        if kind != MockRequestKind::IssueToken {
+            if let Some(fault) = state.take_http_fault() {
+                return Ok(fault_response(fault));
+            }
+        }
+    }
+    Ok(match kind {
+        MockRequestKind::Rest => handle_rest(state, req).await,
+        MockRequestKind::Websocket => handle_websocket_upgrade(state, req),
+        MockRequestKind::VoiceList => {
+            let mut state = state.lock().unwrap();
+            state.requests.push(MockRequest {
+                kind,
+                headers: req.headers().clone(),
+                ssml: None,
+                output_format: None,
+            });
+            Response::builder()
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(state.voices.clone()))
+                .unwrap()
+        }
+        MockRequestKind::IssueToken => {
+            let mut state = state.lock().unwrap();
+            let token = format!("mock-token-{}", state.issued_tokens.len());
+            state.issued_tokens.insert(token.clone());
+            state.requests.push(MockRequest {
+                kind,
+                headers: req.headers().clone(),
+                ssml: None,
+                output_format: None,
+            });
+            Response::new(Body::from(token))
+        }
+    })
+}
+
+async fn handle_rest(state: SharedState, req: Request<Body>) -> Response<Body> {
+    let (parts, body) = req.into_parts();
+    let ssml = match hyper::body::to_bytes(body).await {
+        Ok(body) => String::from_utf8_lossy(&body).into_owned(),
+        Err(_) => return status_response(StatusCode::BAD_REQUEST),
+    };
+    let output_format = parts
+        .headers
+        .get("X-Microsoft-OutputFormat")
+        .and_then(|v| v.to_str().ok())
+        .map(ToString::to_string);
+    let mut state = state.lock().unwrap();
+    state.requests.push(MockRequest {
+        kind: MockRequestKind::Rest,
+        headers: parts.headers,
+        ssml: Some(ssml),
+        output_format,
+    });
+    Response::new(Body::from(state.audio.clone()))
+}
+
+fn handle_websocket_upgrade(state: SharedState, mut req: Request<Body>) -> Response<Body> {
+    let Some(accept_key) = req
+        .headers()
+        .get(header::SEC_WEBSOCKET_KEY)
+        .map(|key| derive_accept_key(key.as_bytes()))
+    else {
+        return status_response(StatusCode::BAD_REQUEST);
+    };
+    let headers = req.headers().clone();
+    let upgrade = hyper::upgrade::on(&mut req);
+    tokio::spawn(async move {
+        match upgrade.await {
+            Ok(upgraded) => {
+                let ws = WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await;
+                if let Err(e) = serve_websocket(state, headers, ws).await {
+                    debug!("Mock websocket connection ended with an error: {e}");
+                }
+            }
+            Err(e) => warn!("Mock websocket upgrade failed: {e}"),
+        }
+    });
+    Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(header::CONNECTION, "Upgrade")
+        .header(header::UPGRADE, "websocket")
+        .header(header::SEC_WEBSOCKET_ACCEPT, accept_key)
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// Split a text message of the websocket protocol into its path, request id and body.
+fn parse_text_message(text: &str) -> Option<(&str, &str, &str)> {
+    let (headers, body) = text.split_once("\r\n\r\n")?;
+    let mut path = None;
+    let mut request_id = "";
+    for header in headers.split("\r\n") {
+        // The client doesn't always separate the headers properly
+        if let Some((name, value)) = header.split_once(':') {
+            match name.trim() {
+                "Path" => path = Some(value.trim()),
+                "X-RequestId" => request_id = value.trim(),
+                _ => {}
+            }
+        }
+    }
+    Some((path?, request_id, body))
+}
+
+fn text_message(path: &str, request_id: &str, body: &str) -> Message {
+    Message::Text(format!(
+        "X-RequestId:{request_id}\r\nContent-Type:application/json; charset=utf-8\r\nPath:{path}\r\n\r\n{body}"
+    ))
+}
+
+fn audio_message(request_id: &str, data: &[u8]) -> Message {
+    let header = format!("X-RequestId:{request_id}\r\nContent-Type:audio/x-wav\r\nPath:audio\r\n");
+    let mut message = Vec::with_capacity(2 + header.len() + data.len());
+    message.extend_from_slice(&(header.len() as u16).to_be_bytes());
+    message.extend_from_slice(header.as_bytes());
+    message.extend_from_slice(data);
+    Message::Binary(message)
+}
+
+/// Metadata events for every word and the whole text of the SSML
+fn metadata_messages(ssml: &str, context: &serde_json::Value, request_id: &str) -> Vec<Message> {
+    let options = &context["synthesis"]["audio"]["metadataOptions"];
+    let enabled = |name: &str| options[name].as_bool().unwrap_or(false);
+    let text = xml::EventReader::from_str(ssml)
+        .into_iter()
+        .filter_map(|event| match event {
+            Ok(xml::reader::XmlEvent::Characters(text)) => Some(text),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    let ticks = |duration: Duration| duration.as_nanos() as u64 / 100;
+    let boundary = |kind: &str, offset: Duration, duration: Duration, text: &str| {
+        serde_json::json!({
+            "Metadata": [{
+                "Type": kind,
+                "Data": {
+                    "Offset": ticks(offset),
+                    "Duration": ticks(duration),
+                    "text": {"Text": text, "Length": text.chars().count(), "BoundaryType": kind}
+                }
+            }]
+        })
+        .to_string()
+    };
+    let words = text.split_whitespace().collect::<Vec<_>>();
+    let mut messages = Vec::new();
+    if enabled("sentenceBoundaryEnabled") && !words.is_empty() {
+        let body = boundary(
+            "SentenceBoundary",
+            Duration::ZERO,
+            WORD_DURATION * words.len() as u32,
+            text.trim(),
+        );
+        messages.push(text_message("audio.metadata", request_id, &body));
+    }
+    if enabled("wordBoundaryEnabled") {
+        for (i, word) in words.iter().enumerate() {
+            let body = boundary(
+                "WordBoundary",
+                WORD_DURATION * i as u32,
+                WORD_DURATION,
+                word,
+            );
+            messages.push(text_message("audio.metadata", request_id, &body));
+        }
+    }
+    messages
+}
+
+async fn serve_websocket(
+    state: SharedState,
+    headers: HeaderMap,
+    mut ws: WebSocketStream<hyper::upgrade::Upgraded>,
+) -> Result<(), tokio_tungstenite::tungstenite::Error> {
+    let mut context = serde_json::Value::Null;
+    let mut output_format = None;
+    while let Some(message) = ws.next().await {
+        let text = match message? {
+            Message::Text(text) => text,
+            Message::Close(_) => break,
+            _ => continue,
+        };
+        let Some((path, request_id, body)) = parse_text_message(&text) else {
+            warn!("Mock server received an invalid message: {text}");
+            continue;
+        };
+        match path {
+            "speech.config" => {}
+            "synthesis.context" => {
+                context = serde_json::from_str(body).unwrap_or_default();
+                output_format = context["synthesis"]["audio"]["outputFormat"]
+                    .as_str()
+                    .map(ToString::to_string);
+            }
+            "ssml" => {
+                let (fault, audio) = {
+                    let mut state = state.lock().unwrap();
+                    state.requests.push(MockRequest {
+                        kind: MockRequestKind::Websocket,
+                        headers: headers.clone(),
+                        ssml: Some(body.to_string()),
+                        output_format: output_format.clone(),
+                    });
+                    (state.take_close_fault(), state.audio.clone())
+                };
+                if let Some((code, reason)) = fault {
+                    ws.close(Some(CloseFrame {
+                        code: CloseCode::from(code),
+                        reason: reason.into(),
+                    }))
+                    .await?;
+                    break;
+                }
+                ws.send(text_message(
+                    "turn.start",
+                    request_id,
+                    r#"{"context":{"serviceTag":"mock"}}"#,
+                ))
+                .await?;
+                for message in metadata_messages(body, &context, request_id) {
+                    ws.send(message).await?;
+                }
+                for chunk in audio.chunks(AUDIO_CHUNK_SIZE) {
+                    ws.send(audio_message(request_id, chunk)).await?;
+                }
+                ws.send(text_message("turn.end", request_id, "{}")).await?;
+            }
+            path => warn!("Mock server received a message with unknown path {path}"),
+        }
+    }
+    Ok(())
+}
@@ -20,6 +20,9 @@ pub(crate) enum WebSocketMessage<'a> {
     Audio {
         data: &'a [u8],
     },
+    AudioMetadata {
+        body: &'a str,
+    },
     Close(Option<&'a CloseFrame<'a>>),
     Ping,
     Pong,
@@ -65,6 +68,9 @@ impl<'a> TryFrom<&'a Message> for WebSocketMessage<'a> {
                             "turn.end" => result = Some(WebSocketMessage::TurnEnd),
                             "turn.start" => result = Some(WebSocketMessage::TurnStart),
                             "response" => result = Some(WebSocketMessage::Response { body }),
+                            "audio.metadata" => {
+                                result = Some(WebSocketMessage::AudioMetadata { body })
+                            }
                             _ => break,
                         }
                     }
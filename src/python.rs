@@ -9,7 +9,7 @@ use pyo3::{prelude::*, types::PyDict};
 use reqwest::header::{HeaderName, HeaderValue};
 use tokio::runtime::Runtime;
 
-use crate::audio::play_owned_audio_blocking;
+use crate::audio::play_owned_audio_blocking_on;
 use crate::get_rest_endpoint_by_region;
 use crate::parse::{parse_pitch, parse_rate, parse_style_degree};
 use crate::synthesizer::UnifiedSynthesizer;
@@ -99,6 +99,14 @@ impl SpeechService {
             })
             .transpose()
     }
+
+    /// The output device to play the audio on, `None` for the default device
+    fn parse_device(options: Option<&PyDict>) -> PyResult<Option<&str>> {
+        options
+            .and_then(|opts| opts.get_item("device"))
+            .map(|d| d.extract())
+            .transpose()
+    }
 }
 
 #[pymethods]
@@ -190,6 +198,7 @@ impl SpeechService {
                         headers: Cow::Borrowed(headers.as_slice()),
                         token: token.as_deref().map(Cow::Borrowed),
                         proxy: proxy.as_deref().map(Cow::Borrowed),
+                        credential_provider: None,
                     },
                     audio_format,
                 );
@@ -204,11 +213,12 @@ impl SpeechService {
         })
     }
 
-    fn speak_ssml(&self, ssml: &str) -> PyResult<()> {
+    #[pyo3(signature = (ssml, **options))]
+    fn speak_ssml(&self, ssml: &str, options: Option<&PyDict>) -> PyResult<()> {
         let buffer = self
             .runtime
             .block_on(self.synthesizer.borrow_mut().as_mut().process_ssml(ssml))?;
-        play_owned_audio_blocking(buffer)?;
+        play_owned_audio_blocking_on(buffer, Self::parse_device(options)?)?;
         Ok(())
     }
 
@@ -242,7 +252,7 @@ impl SpeechService {
                 text,
                 &Self::parse_text_options(options)?.unwrap_or_default(),
             ))?;
-        play_owned_audio_blocking(buffer)?;
+        play_owned_audio_blocking_on(buffer, Self::parse_device(options)?)?;
         Ok(())
     }
 
@@ -12,6 +12,19 @@ use xml::{
     EventWriter,
 };
 
+mod document;
+#[cfg(feature = "markdown")]
+mod markdown;
+mod validate;
+
+pub use document::{
+    BackgroundAudio, BreakStrength, ContentBuilder, EmphasisLevel, ExpressAs, PhonemeAlphabet,
+    Prosody, SayAs, SilenceType, SsmlDocument, SsmlDocumentBuilder,
+};
+#[cfg(feature = "markdown")]
+pub use markdown::{markdown_to_ssml, markdown_to_ssml_chunks};
+pub use validate::{validate_ssml, SsmlIssue, SsmlIssueKind, SsmlValidationError};
+
 trait StartElementBuilderExt<'a> {
     fn optional_attrs(self, attrs: &'a [(&str, Option<&str>)]) -> Self;
     fn optional_ns(self, cond: bool, ns: &'a str, uri: &'a str) -> Self;
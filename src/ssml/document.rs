@@ -0,0 +1,850 @@
+use std::{io::Write, time::Duration};
+
+use log::info;
+use strum::IntoStaticStr;
+use xml::{
+    writer::{events::StartElementBuilder, XmlEvent},
+    EventWriter,
+};
+
+use super::{SsmlError, StartElementBuilderExt};
+use crate::types::{Role, TextOptions};
+
+const SSML_NS: &str = "http://www.w3.org/2001/10/synthesis";
+const MSTTS_NS: &str = "http://www.w3.org/2001/mstts";
+const EMO_NS: &str = "http://www.w3.org/2009/10/emotionml";
+
+/// A SSML document composed with [`SsmlDocumentBuilder`].
+///
+/// ```
+/// use std::time::Duration;
+/// use aspeak::{EmphasisLevel, Prosody, SsmlDocument};
+///
+/// let ssml = SsmlDocument::builder()
+///     .voice("en-US-JennyNeural", |v| {
+///         v.text("Hello,")
+///             .break_time(Duration::from_millis(300))
+///             .emphasis(EmphasisLevel::Strong, |e| e.text("world"))
+///     })
+///     .voice("en-US-GuyNeural", |v| {
+///         v.prosody(Prosody::new().rate("slow"), |p| p.text("Tom & Jerry"))
+///     })
+///     .build()
+///     .to_ssml()
+///     .unwrap();
+/// assert!(ssml.contains("Tom &amp; Jerry"));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct SsmlDocument {
+    pub(crate) lang: String,
+    pub(crate) background_audio: Option<BackgroundAudio>,
+    pub(crate) voices: Vec<VoiceSegment>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct VoiceSegment {
+    pub(crate) name: String,
+    pub(crate) content: Vec<SsmlNode>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum SsmlNode {
+    Text(String),
+    Break(BreakKind),
+    Emphasis(EmphasisLevel, Vec<SsmlNode>),
+    SayAs(SayAs),
+    Sub {
+        alias: String,
+        text: String,
+    },
+    Phoneme {
+        alphabet: PhonemeAlphabet,
+        ph: String,
+        text: String,
+    },
+    Lexicon(String),
+    Audio {
+        src: String,
+        fallback: Vec<SsmlNode>,
+    },
+    Bookmark(String),
+    Silence(SilenceType, Duration),
+    Prosody(Prosody, Vec<SsmlNode>),
+    Lang(String, Vec<SsmlNode>),
+    ExpressAs(ExpressAs, Vec<SsmlNode>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum BreakKind {
+    Strength(BreakStrength),
+    Time(Duration),
+}
+
+/// The strength of a `<break>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, IntoStaticStr)]
+#[non_exhaustive]
+#[strum(serialize_all = "kebab-case")]
+pub enum BreakStrength {
+    None,
+    XWeak,
+    Weak,
+    Medium,
+    Strong,
+    XStrong,
+}
+
+/// The level of an `<emphasis>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, IntoStaticStr)]
+#[non_exhaustive]
+#[strum(serialize_all = "kebab-case")]
+pub enum EmphasisLevel {
+    Reduced,
+    None,
+    Moderate,
+    Strong,
+}
+
+/// The phonetic alphabet of a `<phoneme>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, IntoStaticStr)]
+#[non_exhaustive]
+#[strum(serialize_all = "kebab-case")]
+pub enum PhonemeAlphabet {
+    Ipa,
+    Sapi,
+    Ups,
+    XSampa,
+}
+
+/// The position of the silence inserted by `<mstts:silence>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, IntoStaticStr)]
+#[non_exhaustive]
+pub enum SilenceType {
+    /// Extra silence at the beginning of the text
+    Leading,
+    /// Silence at the beginning of the text, replacing the natural silence
+    #[strum(serialize = "Leading-exact")]
+    LeadingExact,
+    /// Extra silence at the end of the text
+    Tailing,
+    /// Silence at the end of the text, replacing the natural silence
+    #[strum(serialize = "Tailing-exact")]
+    TailingExact,
+    /// Extra silence between adjacent sentences
+    #[strum(serialize = "Sentenceboundary")]
+    SentenceBoundary,
+    /// Silence between adjacent sentences, replacing the natural silence
+    #[strum(serialize = "Sentenceboundary-exact")]
+    SentenceBoundaryExact,
+    /// Silence at commas, replacing the natural silence
+    #[strum(serialize = "Comma-exact")]
+    CommaExact,
+    /// Silence at semicolons, replacing the natural silence
+    #[strum(serialize = "Semicolon-exact")]
+    SemicolonExact,
+    /// Silence at enumeration commas, replacing the natural silence
+    #[strum(serialize = "Enumerationcomma-exact")]
+    EnumerationCommaExact,
+}
+
+/// Attributes of a `<prosody>` element.
+///
+/// The values are inserted into the SSML as is, e.g. `high`, `+20%` or `-2st` for the pitch.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Prosody {
+    pub(crate) pitch: Option<String>,
+    pub(crate) contour: Option<String>,
+    pub(crate) range: Option<String>,
+    pub(crate) rate: Option<String>,
+    pub(crate) volume: Option<String>,
+}
+
+impl Prosody {
+    /// Create a `<prosody>` without attributes
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// The baseline pitch
+    pub fn pitch(mut self, pitch: impl Into<String>) -> Self {
+        self.pitch = Some(pitch.into());
+        self
+    }
+
+    /// Changes of the pitch, e.g. `(0%,+20Hz) (10%,-2st)`
+    pub fn contour(mut self, contour: impl Into<String>) -> Self {
+        self.contour = Some(contour.into());
+        self
+    }
+
+    /// The range of the pitch
+    pub fn range(mut self, range: impl Into<String>) -> Self {
+        self.range = Some(range.into());
+        self
+    }
+
+    /// The speaking rate
+    pub fn rate(mut self, rate: impl Into<String>) -> Self {
+        self.rate = Some(rate.into());
+        self
+    }
+
+    /// The volume
+    pub fn volume(mut self, volume: impl Into<String>) -> Self {
+        self.volume = Some(volume.into());
+        self
+    }
+}
+
+/// Attributes of a `<mstts:express-as>` element
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpressAs {
+    pub(crate) style: String,
+    pub(crate) style_degree: Option<f32>,
+    pub(crate) role: Option<Role>,
+}
+
+impl ExpressAs {
+    /// Speak in the given style, e.g. `cheerful`
+    pub fn new(style: impl Into<String>) -> Self {
+        Self {
+            style: style.into(),
+            style_degree: None,
+            role: None,
+        }
+    }
+
+    /// Speech style degree, which should be in range [0.01, 2]
+    pub fn style_degree(mut self, style_degree: f32) -> Self {
+        self.style_degree = Some(style_degree);
+        self
+    }
+
+    /// Speech role
+    pub fn role(mut self, role: Role) -> Self {
+        self.role = Some(role);
+        self
+    }
+}
+
+/// A `<say-as>` element
+#[derive(Debug, Clone, PartialEq)]
+pub struct SayAs {
+    pub(crate) interpret_as: String,
+    pub(crate) format: Option<String>,
+    pub(crate) detail: Option<String>,
+    pub(crate) text: String,
+}
+
+impl SayAs {
+    /// Interpret the text as the given content type, e.g. `date`, `cardinal` or `spell-out`
+    pub fn new(interpret_as: impl Into<String>, text: impl Into<String>) -> Self {
+        Self {
+            interpret_as: interpret_as.into(),
+            format: None,
+            detail: None,
+            text: text.into(),
+        }
+    }
+
+    /// The format of the content, e.g. `mdy` for dates
+    pub fn format(mut self, format: impl Into<String>) -> Self {
+        self.format = Some(format.into());
+        self
+    }
+
+    /// The level of detail to be spoken
+    pub fn detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+}
+
+/// A `<mstts:backgroundaudio>` element that plays audio during the whole document
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackgroundAudio {
+    pub(crate) src: String,
+    pub(crate) volume: Option<String>,
+    pub(crate) fade_in: Option<Duration>,
+    pub(crate) fade_out: Option<Duration>,
+}
+
+impl BackgroundAudio {
+    /// Play the audio file at the given url
+    pub fn new(src: impl Into<String>) -> Self {
+        Self {
+            src: src.into(),
+            volume: None,
+            fade_in: None,
+            fade_out: None,
+        }
+    }
+
+    /// The volume of the background audio, e.g. `0.7`
+    pub fn volume(mut self, volume: impl Into<String>) -> Self {
+        self.volume = Some(volume.into());
+        self
+    }
+
+    /// The duration of the fade in, up to 10 seconds
+    pub fn fade_in(mut self, fade_in: Duration) -> Self {
+        self.fade_in = Some(fade_in);
+        self
+    }
+
+    /// The duration of the fade out, up to 10 seconds
+    pub fn fade_out(mut self, fade_out: Duration) -> Self {
+        self.fade_out = Some(fade_out);
+        self
+    }
+}
+
+/// Builder for the content of a `<voice>` or another element that contains text
+#[derive(Debug, Clone, Default)]
+pub struct ContentBuilder {
+    nodes: Vec<SsmlNode>,
+}
+
+impl ContentBuilder {
+    /// Create an empty content builder
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    fn push(mut self, node: SsmlNode) -> Self {
+        self.nodes.push(node);
+        self
+    }
+
+    fn nested(f: impl FnOnce(ContentBuilder) -> ContentBuilder) -> Vec<SsmlNode> {
+        f(ContentBuilder::new()).nodes
+    }
+
+    /// Plain text. It is escaped when serialized.
+    pub fn text(self, text: impl Into<String>) -> Self {
+        self.push(SsmlNode::Text(text.into()))
+    }
+
+    /// A `<break>` with the given strength
+    pub fn break_strength(self, strength: BreakStrength) -> Self {
+        self.push(SsmlNode::Break(BreakKind::Strength(strength)))
+    }
+
+    /// A `<break>` with the given duration
+    pub fn break_time(self, time: Duration) -> Self {
+        self.push(SsmlNode::Break(BreakKind::Time(time)))
+    }
+
+    /// An `<emphasis>` around the content
+    pub fn emphasis(
+        self,
+        level: EmphasisLevel,
+        f: impl FnOnce(ContentBuilder) -> ContentBuilder,
+    ) -> Self {
+        self.push(SsmlNode::Emphasis(level, Self::nested(f)))
+    }
+
+    /// A `<say-as>`
+    pub fn say_as(self, say_as: SayAs) -> Self {
+        self.push(SsmlNode::SayAs(say_as))
+    }
+
+    /// A `<sub>` that speaks the `alias` instead of the `text`
+    pub fn sub(self, alias: impl Into<String>, text: impl Into<String>) -> Self {
+        self.push(SsmlNode::Sub {
+            alias: alias.into(),
+            text: text.into(),
+        })
+    }
+
+    /// A `<phoneme>` that pronounces the `text` as `ph`
+    pub fn phoneme(
+        self,
+        alphabet: PhonemeAlphabet,
+        ph: impl Into<String>,
+        text: impl Into<String>,
+    ) -> Self {
+        self.push(SsmlNode::Phoneme {
+            alphabet,
+            ph: ph.into(),
+            text: text.into(),
+        })
+    }
+
+    /// A `<lexicon>` that loads a custom lexicon from the url.
+    ///
+    /// It should come before any text of the voice.
+    pub fn lexicon(self, uri: impl Into<String>) -> Self {
+        self.push(SsmlNode::Lexicon(uri.into()))
+    }
+
+    /// An `<audio>` that plays the audio file at the url.
+    /// The fallback content is spoken if the audio is unavailable.
+    pub fn audio(
+        self,
+        src: impl Into<String>,
+        fallback: impl FnOnce(ContentBuilder) -> ContentBuilder,
+    ) -> Self {
+        self.push(SsmlNode::Audio {
+            src: src.into(),
+            fallback: Self::nested(fallback),
+        })
+    }
+
+    /// A `<bookmark>`, which is reported as a [`crate::synthesizer::BookmarkEvent`] by the websocket synthesizer
+    pub fn bookmark(self, mark: impl Into<String>) -> Self {
+        self.push(SsmlNode::Bookmark(mark.into()))
+    }
+
+    /// A `<mstts:silence>`
+    pub fn silence(self, kind: SilenceType, duration: Duration) -> Self {
+        self.push(SsmlNode::Silence(kind, duration))
+    }
+
+    /// A `<prosody>` around the content
+    pub fn prosody(
+        self,
+        prosody: Prosody,
+        f: impl FnOnce(ContentBuilder) -> ContentBuilder,
+    ) -> Self {
+        self.push(SsmlNode::Prosody(prosody, Self::nested(f)))
+    }
+
+    /// A `<lang>` that speaks the content in the given language, e.g. `de-DE`
+    pub fn lang(
+        self,
+        lang: impl Into<String>,
+        f: impl FnOnce(ContentBuilder) -> ContentBuilder,
+    ) -> Self {
+        self.push(SsmlNode::Lang(lang.into(), Self::nested(f)))
+    }
+
+    /// A `<mstts:express-as>` around the content
+    pub fn express_as(
+        self,
+        express_as: ExpressAs,
+        f: impl FnOnce(ContentBuilder) -> ContentBuilder,
+    ) -> Self {
+        self.push(SsmlNode::ExpressAs(express_as, Self::nested(f)))
+    }
+
+    /// Wrap the content in the `<mstts:express-as>` and `<prosody>` described by the [`TextOptions`].
+    ///
+    /// The voice of the options is not used.
+    pub(crate) fn text_options(
+        self,
+        options: &TextOptions,
+        f: impl FnOnce(ContentBuilder) -> ContentBuilder,
+    ) -> Self {
+        let prosody = move |content: ContentBuilder| {
+            if options.pitch().is_none() && options.rate().is_none() {
+                return f(content);
+            }
+            let mut prosody = Prosody::new();
+            if let Some(pitch) = options.pitch() {
+                prosody = prosody.pitch(pitch);
+            }
+            if let Some(rate) = options.rate() {
+                prosody = prosody.rate(rate);
+            }
+            content.prosody(prosody, f)
+        };
+        match options.rich_ssml_options() {
+            Some(rich_ssml_options) => {
+                let mut express_as = ExpressAs::new(rich_ssml_options.style().unwrap_or("general"));
+                if let Some(role) = rich_ssml_options.role() {
+                    express_as = express_as.role(role);
+                }
+                if let Some(style_degree) = rich_ssml_options.style_degree() {
+                    express_as = express_as.style_degree(style_degree);
+                }
+                self.express_as(express_as, prosody)
+            }
+            None => prosody(self),
+        }
+    }
+}
+
+/// Builder for [`SsmlDocument`]
+#[derive(Debug, Clone)]
+pub struct SsmlDocumentBuilder {
+    document: SsmlDocument,
+}
+
+impl Default for SsmlDocumentBuilder {
+    fn default() -> Self {
+        Self {
+            document: SsmlDocument {
+                lang: "en-US".to_string(),
+                background_audio: None,
+                voices: Vec::new(),
+            },
+        }
+    }
+}
+
+impl SsmlDocumentBuilder {
+    /// Create a new builder for an empty `en-US` document
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// The language of the document, default to `en-US`
+    pub fn lang(mut self, lang: impl Into<String>) -> Self {
+        self.document.lang = lang.into();
+        self
+    }
+
+    /// Play the background audio during the whole document
+    pub fn background_audio(mut self, background_audio: BackgroundAudio) -> Self {
+        self.document.background_audio = Some(background_audio);
+        self
+    }
+
+    /// Append a `<voice>` segment spoken by the given voice, e.g. `en-US-JennyNeural`
+    pub fn voice(
+        mut self,
+        name: impl Into<String>,
+        f: impl FnOnce(ContentBuilder) -> ContentBuilder,
+    ) -> Self {
+        self.document.voices.push(VoiceSegment {
+            name: name.into(),
+            content: ContentBuilder::nested(f),
+        });
+        self
+    }
+
+    /// Build [`SsmlDocument`]
+    pub fn build(self) -> SsmlDocument {
+        self.document
+    }
+}
+
+/// Format the duration in milliseconds, which is accepted by all time attributes
+fn format_duration(duration: Duration) -> String {
+    format!("{}ms", duration.as_millis())
+}
+
+impl SsmlDocument {
+    /// Create a builder for [`SsmlDocument`]
+    pub fn builder() -> SsmlDocumentBuilder {
+        SsmlDocumentBuilder::new()
+    }
+
+    /// Serialize the document into SSML
+    pub fn to_ssml(&self) -> Result<String, SsmlError> {
+        let mut buf = Vec::new();
+        self.write_to(&mut buf)?;
+        let ssml = String::from_utf8(buf).unwrap();
+        info!("Created SSML: {}", &ssml);
+        Ok(ssml)
+    }
+
+    /// Serialize the document into SSML and write it to the writer
+    pub fn write_to(&self, sink: impl Write) -> Result<(), SsmlError> {
+        let mut writer = EventWriter::new_with_config(
+            sink,
+            xml::EmitterConfig::new().write_document_declaration(false),
+        );
+        writer.write(
+            XmlEvent::start_element("speak")
+                .default_ns(SSML_NS)
+                .ns("mstts", MSTTS_NS)
+                .ns("emo", EMO_NS)
+                .attr("version", "1.0")
+                .attr("xml:lang", &self.lang),
+        )?;
+        if let Some(background_audio) = &self.background_audio {
+            // Fade durations are plain numbers of milliseconds
+            let fade_in = background_audio
+                .fade_in
+                .map(|fade_in| fade_in.as_millis().to_string());
+            let fade_out = background_audio
+                .fade_out
+                .map(|fade_out| fade_out.as_millis().to_string());
+            writer.write(
+                XmlEvent::start_element("mstts:backgroundaudio")
+                    .attr("src", &background_audio.src)
+                    .optional_attrs(&[
+                        ("volume", background_audio.volume.as_deref()),
+                        ("fadein", fade_in.as_deref()),
+                        ("fadeout", fade_out.as_deref()),
+                    ]),
+            )?;
+            writer.write(XmlEvent::end_element())?;
+        }
+        for voice in &self.voices {
+            writer.write(XmlEvent::start_element("voice").attr("name", &voice.name))?;
+            write_nodes(&mut writer, &voice.content)?;
+            writer.write(XmlEvent::end_element())?;
+        }
+        writer.write(XmlEvent::end_element())?;
+        Ok(())
+    }
+}
+
+fn write_element<W: Write>(
+    writer: &mut EventWriter<W>,
+    start: StartElementBuilder,
+    children: &[SsmlNode],
+) -> Result<(), SsmlError> {
+    writer.write(start)?;
+    write_nodes(writer, children)?;
+    writer.write(XmlEvent::end_element())?;
+    Ok(())
+}
+
+fn write_text_element<W: Write>(
+    writer: &mut EventWriter<W>,
+    start: StartElementBuilder,
+    text: &str,
+) -> Result<(), SsmlError> {
+    writer.write(start)?;
+    writer.write(XmlEvent::characters(text))?;
+    writer.write(XmlEvent::end_element())?;
+    Ok(())
+}
+
+fn write_nodes<W: Write>(writer: &mut EventWriter<W>, nodes: &[SsmlNode]) -> Result<(), SsmlError> {
+    for node in nodes {
+        match node {
+            SsmlNode::Text(text) => writer.write(XmlEvent::characters(text))?,
+            SsmlNode::Break(BreakKind::Strength(strength)) => write_element(
+                writer,
+                XmlEvent::start_element("break").attr("strength", strength.into()),
+                &[],
+            )?,
+            SsmlNode::Break(BreakKind::Time(time)) => write_element(
+                writer,
+                XmlEvent::start_element("break").attr("time", &format_duration(*time)),
+                &[],
+            )?,
+            SsmlNode::Emphasis(level, children) => write_element(
+                writer,
+                XmlEvent::start_element("emphasis").attr("level", level.into()),
+                children,
+            )?,
+            SsmlNode::SayAs(say_as) => write_text_element(
+                writer,
+                XmlEvent::start_element("say-as")
+                    .attr("interpret-as", &say_as.interpret_as)
+                    .optional_attrs(&[
+                        ("format", say_as.format.as_deref()),
+                        ("detail", say_as.detail.as_deref()),
+                    ]),
+                &say_as.text,
+            )?,
+            SsmlNode::Sub { alias, text } => write_text_element(
+                writer,
+                XmlEvent::start_element("sub").attr("alias", alias),
+                text,
+            )?,
+            SsmlNode::Phoneme { alphabet, ph, text } => write_text_element(
+                writer,
+                XmlEvent::start_element("phoneme")
+                    .attr("alphabet", alphabet.into())
+                    .attr("ph", ph),
+                text,
+            )?,
+            SsmlNode::Lexicon(uri) => write_element(
+                writer,
+                XmlEvent::start_element("lexicon").attr("uri", uri),
+                &[],
+            )?,
+            SsmlNode::Audio { src, fallback } => write_element(
+                writer,
+                XmlEvent::start_element("audio").attr("src", src),
+                fallback,
+            )?,
+            SsmlNode::Bookmark(mark) => write_element(
+                writer,
+                XmlEvent::start_element("bookmark").attr("mark", mark),
+                &[],
+            )?,
+            SsmlNode::Silence(kind, duration) => write_element(
+                writer,
+                XmlEvent::start_element("mstts:silence")
+                    .attr("type", kind.into())
+                    .attr("value", &format_duration(*duration)),
+                &[],
+            )?,
+            SsmlNode::Prosody(prosody, children) => write_element(
+                writer,
+                XmlEvent::start_element("prosody").optional_attrs(&[
+                    ("pitch", prosody.pitch.as_deref()),
+                    ("contour", prosody.contour.as_deref()),
+                    ("range", prosody.range.as_deref()),
+                    ("rate", prosody.rate.as_deref()),
+                    ("volume", prosody.volume.as_deref()),
+                ]),
+                children,
+            )?,
+            SsmlNode::Lang(lang, children) => write_element(
+                writer,
+                XmlEvent::start_element("lang").attr("xml:lang", lang),
+                children,
+            )?,
+            SsmlNode::ExpressAs(express_as, children) => {
+                let style_degree = express_as.style_degree.map(|x| x.to_string());
+                let role: Option<&str> = express_as.role.map(Into::into);
+                write_element(
+                    writer,
+                    XmlEvent::start_element("mstts:express-as")
+                        .attr("style", &express_as.style)
+                        .optional_attrs(&[
+                            ("role", role),
+                            ("styledegree", style_degree.as_deref()),
+                        ]),
+                    children,
+                )?
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPEAK: &str = r#"<speak xmlns="http://www.w3.org/2001/10/synthesis" xmlns:emo="http://www.w3.org/2009/10/emotionml" xmlns:mstts="http://www.w3.org/2001/mstts" version="1.0" xml:lang="en-US">"#;
+
+    fn voice_ssml(f: impl FnOnce(ContentBuilder) -> ContentBuilder) -> String {
+        SsmlDocument::builder()
+            .voice("en-US-JennyNeural", f)
+            .build()
+            .to_ssml()
+            .unwrap()
+    }
+
+    fn expected(voice: &str) -> String {
+        format!(r#"{SPEAK}<voice name="en-US-JennyNeural">{voice}</voice></speak>"#)
+    }
+
+    #[test]
+    fn breaks() {
+        assert_eq!(
+            voice_ssml(|v| v
+                .text("a")
+                .break_time(Duration::from_millis(1500))
+                .break_strength(BreakStrength::XStrong)
+                .text("b")),
+            expected(r#"a<break time="1500ms" /><break strength="x-strong" />b"#)
+        );
+    }
+
+    #[test]
+    fn say_as_and_phoneme() {
+        assert_eq!(
+            voice_ssml(|v| v
+                .say_as(SayAs::new("date", "10-17-2026").format("mdy"))
+                .say_as(SayAs::new("cardinal", "42"))
+                .phoneme(PhonemeAlphabet::XSampa, "t@\"meItoU", "tomato")
+                .sub("World Wide Web Consortium", "W3C")),
+            expected(concat!(
+                r#"<say-as interpret-as="date" format="mdy">10-17-2026</say-as>"#,
+                r#"<say-as interpret-as="cardinal">42</say-as>"#,
+                r#"<phoneme alphabet="x-sampa" ph="t@&quot;meItoU">tomato</phoneme>"#,
+                r#"<sub alias="World Wide Web Consortium">W3C</sub>"#,
+            ))
+        );
+    }
+
+    #[test]
+    fn silence_and_bookmark() {
+        assert_eq!(
+            voice_ssml(|v| v
+                .silence(SilenceType::SentenceBoundaryExact, Duration::from_secs(1))
+                .silence(SilenceType::Leading, Duration::from_millis(200))
+                .bookmark("start")
+                .text("Hello")),
+            expected(concat!(
+                r#"<mstts:silence type="Sentenceboundary-exact" value="1000ms" />"#,
+                r#"<mstts:silence type="Leading" value="200ms" />"#,
+                r#"<bookmark mark="start" />Hello"#,
+            ))
+        );
+    }
+
+    #[test]
+    fn background_audio() {
+        let ssml = SsmlDocument::builder()
+            .lang("de-DE")
+            .background_audio(
+                BackgroundAudio::new("https://example.com/music.mp3")
+                    .volume("0.7")
+                    .fade_in(Duration::from_secs(3))
+                    .fade_out(Duration::from_millis(1500)),
+            )
+            .voice("de-DE-KatjaNeural", |v| v.text("Hallo"))
+            .build()
+            .to_ssml()
+            .unwrap();
+        assert_eq!(
+            ssml,
+            format!(
+                "{}{}{}",
+                SPEAK.replace("en-US", "de-DE"),
+                r#"<mstts:backgroundaudio src="https://example.com/music.mp3" volume="0.7" fadein="3000" fadeout="1500" />"#,
+                r#"<voice name="de-DE-KatjaNeural">Hallo</voice></speak>"#
+            )
+        );
+    }
+
+    #[test]
+    fn nested_elements() {
+        assert_eq!(
+            voice_ssml(|v| v.express_as(
+                ExpressAs::new("cheerful")
+                    .role(Role::Girl)
+                    .style_degree(1.5),
+                |e| e.prosody(Prosody::new().rate("+20%").pitch("high"), |p| p
+                    .text("Hello")
+                    .lang("fr-FR", |l| l
+                        .emphasis(EmphasisLevel::Strong, |e| e.text("bonjour"))))
+            )),
+            expected(concat!(
+                r#"<mstts:express-as style="cheerful" role="Girl" styledegree="1.5">"#,
+                r#"<prosody pitch="high" rate="+20%">Hello<lang xml:lang="fr-FR">"#,
+                r#"<emphasis level="strong">bonjour</emphasis></lang></prosody>"#,
+                r#"</mstts:express-as>"#,
+            ))
+        );
+    }
+
+    #[test]
+    fn audio_fallback_and_lexicon() {
+        assert_eq!(
+            voice_ssml(|v| v
+                .lexicon("https://example.com/lexicon.xml")
+                .audio("https://example.com/beep.wav", |a| a.text("beep"))),
+            expected(concat!(
+                r#"<lexicon uri="https://example.com/lexicon.xml" />"#,
+                r#"<audio src="https://example.com/beep.wav">beep</audio>"#,
+            ))
+        );
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(
+            voice_ssml(|v| v
+                .text("Tom & Jerry <3 \"quotes\"")
+                .bookmark("a\"b<c>&d")
+                .sub("x & y", "x&y")),
+            expected(concat!(
+                "Tom &amp; Jerry &lt;3 \"quotes\"",
+                r#"<bookmark mark="a&quot;b&lt;c&gt;&amp;d" />"#,
+                r#"<sub alias="x &amp; y">x&amp;y</sub>"#,
+            ))
+        );
+        let ssml = SsmlDocument::builder()
+            .voice("en-US-\"Jenny\"", |v| v.text("</voice>"))
+            .build()
+            .to_ssml()
+            .unwrap();
+        assert_eq!(
+            ssml,
+            format!(r#"{SPEAK}<voice name="en-US-&quot;Jenny&quot;">&lt;/voice></voice></speak>"#)
+        );
+    }
+}
@@ -0,0 +1,193 @@
+use std::time::Duration;
+
+use pulldown_cmark::{Event, HeadingLevel, Options, Parser, Tag};
+
+use super::{BreakStrength, ContentBuilder, EmphasisLevel, SsmlDocument, SsmlError};
+use crate::{TextOptions, TextSplitter};
+
+/// The pause after a top level heading
+const MAJOR_HEADING_BREAK: Duration = Duration::from_millis(1000);
+/// The pause after other headings
+const MINOR_HEADING_BREAK: Duration = Duration::from_millis(750);
+/// The pause between list items and table rows
+const ITEM_BREAK: Duration = Duration::from_millis(400);
+
+/// A block of speakable text extracted from Markdown
+#[derive(Debug, Clone, PartialEq)]
+struct Block {
+    kind: BlockKind,
+    text: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BlockKind {
+    Heading(HeadingLevel),
+    Paragraph,
+    ListItem,
+    /// A piece of a block that is split into several chunks, which is not followed by a pause
+    Fragment,
+}
+
+impl Block {
+    fn speak(&self, content: ContentBuilder) -> ContentBuilder {
+        match self.kind {
+            BlockKind::Heading(level) => content
+                .emphasis(EmphasisLevel::Strong, |e| e.text(&self.text))
+                .break_time(if level <= HeadingLevel::H2 {
+                    MAJOR_HEADING_BREAK
+                } else {
+                    MINOR_HEADING_BREAK
+                }),
+            BlockKind::Paragraph => content
+                .text(&self.text)
+                .break_strength(BreakStrength::Strong),
+            BlockKind::ListItem => content.text(&self.text).break_time(ITEM_BREAK),
+            BlockKind::Fragment => content.text(&self.text),
+        }
+    }
+}
+
+/// Extract the speakable blocks from Markdown.
+///
+/// Code blocks, HTML, images and link destinations are dropped.
+fn blocks(markdown: &str) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut text = String::new();
+    // The next number of every enclosing list, `None` for unordered lists
+    let mut lists: Vec<Option<u64>> = Vec::new();
+    let mut skip_depth = 0usize;
+    let mut flush = |text: &mut String, kind| {
+        let trimmed = text.trim();
+        if !trimmed.is_empty() {
+            blocks.push(Block {
+                kind,
+                text: trimmed.to_string(),
+            });
+        }
+        text.clear();
+    };
+    for event in Parser::new_ext(markdown, Options::ENABLE_TABLES) {
+        if skip_depth > 0 {
+            match event {
+                Event::Start(Tag::CodeBlock(_) | Tag::Image(..)) => skip_depth += 1,
+                Event::End(Tag::CodeBlock(_) | Tag::Image(..)) => skip_depth -= 1,
+                _ => {}
+            }
+            continue;
+        }
+        let item_kind = if lists.is_empty() {
+            BlockKind::Paragraph
+        } else {
+            BlockKind::ListItem
+        };
+        match event {
+            Event::Start(Tag::CodeBlock(_) | Tag::Image(..)) => skip_depth += 1,
+            Event::Start(Tag::Heading(..) | Tag::Paragraph | Tag::BlockQuote) => {
+                flush(&mut text, item_kind)
+            }
+            Event::End(Tag::Heading(level, ..)) => flush(&mut text, BlockKind::Heading(level)),
+            Event::End(Tag::Paragraph | Tag::BlockQuote) => flush(&mut text, item_kind),
+            Event::Start(Tag::List(start)) => {
+                // Text of the enclosing item before the nested list
+                flush(&mut text, item_kind);
+                lists.push(start);
+            }
+            Event::End(Tag::List(_)) => {
+                lists.pop();
+            }
+            Event::Start(Tag::Item) => {
+                flush(&mut text, item_kind);
+                if let Some(Some(number)) = lists.last_mut() {
+                    text.push_str(&format!("{number}. "));
+                    *number += 1;
+                }
+            }
+            Event::End(Tag::Item) => flush(&mut text, BlockKind::ListItem),
+            Event::End(Tag::TableHead | Tag::TableRow) => flush(&mut text, BlockKind::ListItem),
+            Event::Start(Tag::TableCell) if !text.is_empty() => text.push_str(", "),
+            Event::Rule => flush(&mut text, item_kind),
+            Event::Text(s) | Event::Code(s) => text.push_str(&s),
+            Event::SoftBreak | Event::HardBreak => text.push(' '),
+            _ => {}
+        }
+    }
+    flush(&mut text, BlockKind::Paragraph);
+    blocks
+}
+
+fn document<'a>(
+    blocks: impl IntoIterator<Item = &'a Block>,
+    options: &TextOptions,
+) -> SsmlDocument {
+    SsmlDocument::builder()
+        .voice(options.voice(), |voice| {
+            voice.text_options(options, |content| {
+                blocks
+                    .into_iter()
+                    .fold(content, |content, block| block.speak(content))
+            })
+        })
+        .build()
+}
+
+/// Convert Markdown to SSML.
+///
+/// Code blocks, HTML and link destinations are dropped. Headings are emphasized and followed by long pauses,
+/// paragraphs, list items and table rows are separated by shorter pauses.
+/// The whole document is spoken with the given [`TextOptions`].
+///
+/// ```
+/// use aspeak::{markdown_to_ssml, TextOptions};
+///
+/// let ssml = markdown_to_ssml(
+///     "# Title\n\nSee [the docs](https://example.com).\n\n```\nlet x = 1;\n```\n\n- one\n- two",
+///     &TextOptions::default(),
+/// )
+/// .unwrap();
+/// assert!(ssml.contains("<emphasis level=\"strong\">Title</emphasis>"));
+/// assert!(!ssml.contains("example.com"));
+/// assert!(!ssml.contains("let x"));
+/// ```
+pub fn markdown_to_ssml(markdown: &str, options: &TextOptions) -> Result<String, SsmlError> {
+    document(&blocks(markdown), options).to_ssml()
+}
+
+/// Convert Markdown to SSML documents that can be synthesized by separate requests.
+///
+/// Every document contains at most [`TextSplitter::max_chars`] characters of text.
+/// See [`markdown_to_ssml`] for how the Markdown is spoken.
+pub fn markdown_to_ssml_chunks(
+    markdown: &str,
+    options: &TextOptions,
+    splitter: &TextSplitter,
+) -> Result<Vec<String>, SsmlError> {
+    let mut chunks = Vec::new();
+    let mut pending = Vec::new();
+    let mut chars = 0;
+    for block in blocks(markdown) {
+        let pieces = splitter.split(&block.text);
+        let last = pieces.len().saturating_sub(1);
+        for (i, piece) in pieces.into_iter().enumerate() {
+            let len = piece.chars().count();
+            if !pending.is_empty() && chars + len > splitter.max_chars() {
+                chunks.push(document(&pending, options).to_ssml()?);
+                pending.clear();
+                chars = 0;
+            }
+            pending.push(Block {
+                // Only the last piece of a split block ends with the pause of the block
+                kind: if i == last {
+                    block.kind
+                } else {
+                    BlockKind::Fragment
+                },
+                text: piece.to_string(),
+            });
+            chars += len;
+        }
+    }
+    if !pending.is_empty() {
+        chunks.push(document(&pending, options).to_ssml()?);
+    }
+    Ok(chunks)
+}
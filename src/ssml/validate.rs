@@ -0,0 +1,586 @@
+use std::{
+    borrow::Cow,
+    error::Error,
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+};
+
+use strum::AsRefStr;
+use xml::{
+    attribute::OwnedAttribute,
+    common::Position,
+    name::OwnedName,
+    reader::{ErrorKind, EventReader, XmlEvent},
+};
+
+use crate::{
+    parse::{parse_pitch, parse_rate, parse_style_degree},
+    types::Role,
+};
+
+const SSML_NS: &str = "http://www.w3.org/2001/10/synthesis";
+const MSTTS_NS: &str = "http://www.w3.org/2001/mstts";
+const MATHML_NS: &str = "http://www.w3.org/1998/Math/MathML";
+
+const SSML_ELEMENTS: &[&str] = &[
+    "speak", "voice", "prosody", "break", "emphasis", "say-as", "sub", "phoneme", "lexicon",
+    "audio", "bookmark", "lang", "p", "s",
+];
+const MSTTS_ELEMENTS: &[&str] = &[
+    "express-as",
+    "silence",
+    "backgroundaudio",
+    "viseme",
+    "audioduration",
+    "ttsembedding",
+];
+
+const BREAK_STRENGTHS: &[&str] = &["none", "x-weak", "weak", "medium", "strong", "x-strong"];
+const EMPHASIS_LEVELS: &[&str] = &["reduced", "none", "moderate", "strong"];
+const PHONEME_ALPHABETS: &[&str] = &["ipa", "sapi", "ups", "x-sampa"];
+const SILENCE_TYPES: &[&str] = &[
+    "Leading",
+    "Leading-exact",
+    "Tailing",
+    "Tailing-exact",
+    "Sentenceboundary",
+    "Sentenceboundary-exact",
+    "Comma-exact",
+    "Semicolon-exact",
+    "Enumerationcomma-exact",
+];
+
+/// Validate SSML against the subset of SSML supported by Azure TTS.
+///
+/// All issues found in the document are reported,
+/// except that validation stops at the first XML syntax error.
+///
+/// ```
+/// use aspeak::validate_ssml;
+///
+/// let ssml = r#"<speak version="1.0" xmlns="http://www.w3.org/2001/10/synthesis" xml:lang="en-US">
+/// <voice><prosody rate="very-fast">Hello</prosody></voice>
+/// </speak>"#;
+/// let error = validate_ssml(ssml).unwrap_err();
+/// assert_eq!(error.issues.len(), 2);
+/// assert_eq!((error.issues[0].line, error.issues[0].column), (2, 1));
+/// ```
+pub fn validate_ssml(ssml: &str) -> Result<(), SsmlValidationError> {
+    let mut validator = Validator::default();
+    let mut reader = EventReader::from_str(ssml);
+    loop {
+        let event = reader.next();
+        let position = reader.position();
+        validator.line = position.row + 1;
+        validator.column = position.column + 1;
+        match event {
+            Ok(XmlEvent::StartElement {
+                name, attributes, ..
+            }) => validator.start_element(&name, &attributes),
+            Ok(XmlEvent::EndElement { .. }) => {
+                validator.stack.pop();
+            }
+            Ok(XmlEvent::Characters(text)) => validator.characters(&text),
+            Ok(XmlEvent::EndDocument) => break,
+            Ok(_) => {}
+            Err(e) => {
+                let position = e.position();
+                let kind = match e.kind() {
+                    // Prefixes like `mstts:` without the `xmlns:mstts` declaration
+                    ErrorKind::Syntax(msg) if msg.ends_with("prefix is unbound") => {
+                        SsmlIssueKind::MissingNamespace
+                    }
+                    _ => SsmlIssueKind::Syntax,
+                };
+                validator.issues.push(SsmlIssue {
+                    line: position.row + 1,
+                    column: position.column + 1,
+                    kind,
+                    message: e.msg().to_string(),
+                });
+                break;
+            }
+        }
+    }
+    if validator.issues.is_empty() {
+        Ok(())
+    } else {
+        Err(SsmlValidationError {
+            issues: validator.issues,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct Validator {
+    /// Local names of the open SSML and mstts elements
+    stack: Vec<String>,
+    issues: Vec<SsmlIssue>,
+    line: u64,
+    column: u64,
+}
+
+impl Validator {
+    fn report(&mut self, kind: SsmlIssueKind, message: String) {
+        self.issues.push(SsmlIssue {
+            line: self.line,
+            column: self.column,
+            kind,
+            message,
+        });
+    }
+
+    fn in_voice(&self) -> bool {
+        self.stack.iter().any(|name| name == "voice")
+    }
+
+    fn start_element(&mut self, name: &OwnedName, attributes: &[OwnedAttribute]) {
+        let namespace = name.namespace.as_deref();
+        let local_name = name.local_name.as_str();
+        let qualified_name = name.borrow().to_repr();
+        self.stack.push(local_name.to_string());
+        let depth = self.stack.len();
+        if depth == 1 {
+            if local_name != "speak" {
+                self.report(
+                    SsmlIssueKind::UnexpectedElement,
+                    format!("the root element must be <speak>, found <{qualified_name}>"),
+                );
+                return;
+            }
+            if namespace != Some(SSML_NS) {
+                self.report(
+                    SsmlIssueKind::MissingNamespace,
+                    format!("<speak> must declare the default namespace xmlns=\"{SSML_NS}\""),
+                );
+            }
+        }
+        let known = match namespace {
+            Some(SSML_NS) | None => SSML_ELEMENTS.contains(&local_name),
+            Some(MSTTS_NS) => MSTTS_ELEMENTS.contains(&local_name),
+            Some(MATHML_NS) => return,
+            Some(_) => false,
+        };
+        if !known {
+            self.report(
+                SsmlIssueKind::UnknownElement,
+                format!("unknown element <{qualified_name}>"),
+            );
+            return;
+        }
+        match local_name {
+            "speak" if depth > 1 => self.report(
+                SsmlIssueKind::UnexpectedElement,
+                "<speak> must be the root element".to_string(),
+            ),
+            "voice" if depth != 2 => self.report(
+                SsmlIssueKind::UnexpectedElement,
+                "<voice> must be a direct child of <speak>".to_string(),
+            ),
+            "backgroundaudio" if depth != 2 => self.report(
+                SsmlIssueKind::UnexpectedElement,
+                format!("<{qualified_name}> must be a direct child of <speak>"),
+            ),
+            "speak" | "voice" | "backgroundaudio" => {}
+            _ if !self.in_voice() => self.report(
+                SsmlIssueKind::UnexpectedElement,
+                format!("<{qualified_name}> must be inside a <voice>"),
+            ),
+            _ => {}
+        }
+        self.check_attributes(local_name, &qualified_name, attributes);
+    }
+
+    fn characters(&mut self, text: &str) {
+        if !self.in_voice() && !text.trim().is_empty() {
+            // Point at the text itself instead of the whitespace before it
+            let whitespace = &text[..text.len() - text.trim_start().len()];
+            match whitespace.rsplit_once('\n') {
+                Some((before, after)) => {
+                    self.line += before.matches('\n').count() as u64 + 1;
+                    self.column = after.chars().count() as u64 + 1;
+                }
+                None => self.column += whitespace.chars().count() as u64,
+            }
+            self.report(
+                SsmlIssueKind::UnexpectedText,
+                "text must be inside a <voice>".to_string(),
+            );
+        }
+    }
+
+    fn require(&mut self, element: &str, attributes: &[OwnedAttribute], attr: &str) {
+        if find_attribute(attributes, attr).map_or(true, |value| value.trim().is_empty()) {
+            self.report(
+                SsmlIssueKind::MissingAttribute,
+                format!("<{element}> requires the {attr} attribute"),
+            );
+        }
+    }
+
+    fn check_value(&mut self, attr: &str, value: Option<&str>, allowed: &[&str]) {
+        if let Some(value) = value {
+            if !allowed.contains(&value) {
+                self.report(
+                    SsmlIssueKind::InvalidAttribute,
+                    format!(
+                        "invalid {attr}: {value}, expected one of {}",
+                        allowed.join(", ")
+                    ),
+                );
+            }
+        }
+    }
+
+    fn check_time(&mut self, attr: &str, value: Option<&str>) {
+        if let Some(value) = value {
+            if !is_time(value) {
+                self.report(
+                    SsmlIssueKind::InvalidAttribute,
+                    format!("invalid {attr}: {value}, expected a duration like 500ms or 2s"),
+                );
+            }
+        }
+    }
+
+    /// Unlike `--pitch`, bare numbers are not converted to percentages in SSML
+    fn check_pitch(&mut self, value: Option<&str>) {
+        if let Some(value) = value {
+            if !matches!(parse_pitch(value), Ok(Cow::Borrowed(_))) {
+                self.report(
+                    SsmlIssueKind::InvalidAttribute,
+                    format!(
+                        "invalid pitch: {value}, expected a value like +10%, -2st, 200Hz or high"
+                    ),
+                );
+            }
+        }
+    }
+
+    /// A bare number is a multiplier of the default rate in SSML,
+    /// and the `f` suffix is only understood by `--rate`
+    fn check_rate(&mut self, value: Option<&str>) {
+        if let Some(value) = value {
+            let valid = match value.parse::<f32>() {
+                Ok(rate) => rate >= 0.0,
+                Err(_) => matches!(parse_rate(value), Ok(Cow::Borrowed(rate)) if rate == value),
+            };
+            if !valid {
+                self.report(
+                    SsmlIssueKind::InvalidAttribute,
+                    format!("invalid rate: {value}, expected a value like +10%, 1.5 or slow"),
+                );
+            }
+        }
+    }
+
+    fn check_attributes(&mut self, local_name: &str, element: &str, attributes: &[OwnedAttribute]) {
+        let attr = |name: &str| find_attribute(attributes, name);
+        match local_name {
+            "speak" => {
+                self.require(element, attributes, "version");
+                self.require(element, attributes, "xml:lang");
+            }
+            "voice" => self.require(element, attributes, "name"),
+            "prosody" => {
+                self.check_pitch(attr("pitch"));
+                self.check_rate(attr("rate"));
+            }
+            "express-as" => {
+                self.require(element, attributes, "style");
+                if let Some(Err(e)) = attr("styledegree").map(parse_style_degree) {
+                    self.report(SsmlIssueKind::InvalidAttribute, e.reason);
+                }
+                if let Some(role) = attr("role") {
+                    if Role::from_str(role).is_err() {
+                        self.report(
+                            SsmlIssueKind::InvalidAttribute,
+                            format!("invalid role: {role}"),
+                        );
+                    }
+                }
+            }
+            "break" => {
+                self.check_value("strength", attr("strength"), BREAK_STRENGTHS);
+                self.check_time("time", attr("time"));
+            }
+            "emphasis" => self.check_value("level", attr("level"), EMPHASIS_LEVELS),
+            "say-as" => self.require(element, attributes, "interpret-as"),
+            "sub" => self.require(element, attributes, "alias"),
+            "phoneme" => {
+                self.require(element, attributes, "ph");
+                self.check_value("alphabet", attr("alphabet"), PHONEME_ALPHABETS);
+            }
+            "lexicon" => self.require(element, attributes, "uri"),
+            "audio" | "backgroundaudio" => self.require(element, attributes, "src"),
+            "bookmark" => self.require(element, attributes, "mark"),
+            "lang" => self.require(element, attributes, "xml:lang"),
+            "silence" => {
+                self.require(element, attributes, "type");
+                self.require(element, attributes, "value");
+                self.check_value("type", attr("type"), SILENCE_TYPES);
+                self.check_time("value", attr("value"));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Find an attribute by its qualified name, e.g. `name` or `xml:lang`
+fn find_attribute<'a>(attributes: &'a [OwnedAttribute], name: &str) -> Option<&'a str> {
+    let (prefix, local_name) = match name.split_once(':') {
+        Some((prefix, local_name)) => (Some(prefix), local_name),
+        None => (None, name),
+    };
+    attributes
+        .iter()
+        .find(|attr| attr.name.local_name == local_name && attr.name.prefix.as_deref() == prefix)
+        .map(|attr| attr.value.as_str())
+}
+
+fn is_time(value: &str) -> bool {
+    value
+        .strip_suffix("ms")
+        .or_else(|| value.strip_suffix('s'))
+        .and_then(|number| number.parse::<f32>().ok())
+        .is_some_and(|number| number >= 0.0)
+}
+
+/// An issue found by [`validate_ssml`]
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct SsmlIssue {
+    /// Line of the issue, counting from 1
+    pub line: u64,
+    /// Column of the issue, counting from 1
+    pub column: u64,
+    pub kind: SsmlIssueKind,
+    pub message: String,
+}
+
+impl Display for SsmlIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, AsRefStr)]
+#[non_exhaustive]
+#[strum(serialize_all = "title_case")]
+pub enum SsmlIssueKind {
+    /// The SSML is not well-formed XML.
+    Syntax,
+    /// A required namespace is not declared.
+    MissingNamespace,
+    /// The element is not supported by Azure TTS.
+    UnknownElement,
+    /// The element is not allowed at this position.
+    UnexpectedElement,
+    /// Text is not allowed at this position.
+    UnexpectedText,
+    /// A required attribute is missing.
+    MissingAttribute,
+    /// The value of an attribute is invalid.
+    InvalidAttribute,
+}
+
+/// The issues found by [`validate_ssml`]
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct SsmlValidationError {
+    pub issues: Vec<SsmlIssue>,
+}
+
+impl Display for SsmlValidationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "ssml validation error: ")?;
+        match self.issues.as_slice() {
+            [issue] => write!(f, "{issue}"),
+            issues => {
+                write!(f, "found {} issues", issues.len())?;
+                for issue in issues {
+                    write!(f, "\n  {issue}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for SsmlValidationError {}
+
+#[cfg(feature = "python")]
+impl From<SsmlValidationError> for pyo3::PyErr {
+    fn from(value: SsmlValidationError) -> Self {
+        pyo3::exceptions::PyValueError::new_err(format!("{:?}", color_eyre::Report::from(value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn speak(voice: &str) -> String {
+        format!(
+            r#"<speak version="1.0" xmlns="{SSML_NS}" xmlns:mstts="{MSTTS_NS}" xml:lang="en-US"><voice name="en-US-JennyNeural">{voice}</voice></speak>"#
+        )
+    }
+
+    fn issues(ssml: &str) -> Vec<(SsmlIssueKind, String)> {
+        match validate_ssml(ssml) {
+            Ok(()) => Vec::new(),
+            Err(e) => e.issues.into_iter().map(|i| (i.kind, i.message)).collect(),
+        }
+    }
+
+    fn kinds(ssml: &str) -> Vec<SsmlIssueKind> {
+        issues(ssml).into_iter().map(|(kind, _)| kind).collect()
+    }
+
+    #[test]
+    fn valid_ssml() {
+        assert_eq!(
+            kinds(&speak(concat!(
+                r#"<mstts:express-as style="cheerful" styledegree="1.5" role="Girl">"#,
+                r#"<prosody pitch="+2st" rate="1.5">Hello<break time="300ms"/></prosody>"#,
+                r#"<mstts:silence type="Leading" value="1s"/>"#,
+                r#"</mstts:express-as>"#
+            ))),
+            []
+        );
+    }
+
+    #[test]
+    fn unknown_elements() {
+        assert_eq!(
+            issues(&speak("<foo>Hello</foo><mstts:bar/>")),
+            [
+                (
+                    SsmlIssueKind::UnknownElement,
+                    "unknown element <foo>".to_string()
+                ),
+                (
+                    SsmlIssueKind::UnknownElement,
+                    "unknown element <mstts:bar>".to_string()
+                ),
+            ]
+        );
+        assert_eq!(
+            kinds(r#"<foo version="1.0" xml:lang="en-US"/>"#),
+            [SsmlIssueKind::UnexpectedElement]
+        );
+    }
+
+    #[test]
+    fn missing_namespaces() {
+        let ssml = r#"<speak version="1.0" xml:lang="en-US"><voice name="en-US-JennyNeural"><mstts:silence type="Leading" value="1s"/></voice></speak>"#;
+        assert_eq!(
+            kinds(ssml),
+            [
+                SsmlIssueKind::MissingNamespace,
+                SsmlIssueKind::MissingNamespace
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_attributes() {
+        let ssml = format!(
+            r#"<speak version="1.0" xmlns="{SSML_NS}" xml:lang="en-US"><voice>Hello</voice><voice name=" ">Hi</voice></speak>"#
+        );
+        assert_eq!(
+            issues(&ssml),
+            [
+                (
+                    SsmlIssueKind::MissingAttribute,
+                    "<voice> requires the name attribute".to_string()
+                ),
+                (
+                    SsmlIssueKind::MissingAttribute,
+                    "<voice> requires the name attribute".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn express_as() {
+        assert_eq!(
+            kinds(&speak(
+                r#"<mstts:express-as style="sad" styledegree="3">Hello</mstts:express-as>"#
+            )),
+            [SsmlIssueKind::InvalidAttribute]
+        );
+        assert_eq!(
+            kinds(&speak(
+                r#"<mstts:express-as style="sad" styledegree="much">Hello</mstts:express-as>"#
+            )),
+            [SsmlIssueKind::InvalidAttribute]
+        );
+        assert_eq!(
+            issues(&speak(
+                r#"<mstts:express-as style="sad" role="Cat">Hello</mstts:express-as>"#
+            )),
+            [(
+                SsmlIssueKind::InvalidAttribute,
+                "invalid role: Cat".to_string()
+            )]
+        );
+        assert_eq!(
+            kinds(&speak(r#"<mstts:express-as>Hello</mstts:express-as>"#)),
+            [SsmlIssueKind::MissingAttribute]
+        );
+    }
+
+    #[test]
+    fn prosody() {
+        for valid in [
+            r#"pitch="high" rate="x-slow""#,
+            r#"pitch="-20%" rate="+10%""#,
+            r#"pitch="200Hz" rate="0.5""#,
+        ] {
+            assert_eq!(kinds(&speak(&format!("<prosody {valid}>Hi</prosody>"))), []);
+        }
+        // The forms of `--pitch` and `--rate` that are converted by the CLI
+        for invalid in [
+            r#"rate="1.5f""#,
+            r#"rate="-0.5""#,
+            r#"pitch="0.5""#,
+            r#"pitch="2st""#,
+        ] {
+            assert_eq!(
+                kinds(&speak(&format!("<prosody {invalid}>Hi</prosody>"))),
+                [SsmlIssueKind::InvalidAttribute],
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn positions() {
+        let ssml = format!(
+            "<speak version=\"1.0\" xmlns=\"{SSML_NS}\" xml:lang=\"en-US\">\n  Hello\n  <voice name=\"en-US-JennyNeural\">\n    <break time=\"soon\"/>\n  </voice>\n</speak>"
+        );
+        let error = validate_ssml(&ssml).unwrap_err();
+        let positions = error
+            .issues
+            .iter()
+            .map(|i| (i.line, i.column, i.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            positions,
+            [
+                (2, 3, SsmlIssueKind::UnexpectedText),
+                (4, 5, SsmlIssueKind::InvalidAttribute)
+            ]
+        );
+        assert_eq!(
+            error.to_string(),
+            "ssml validation error: found 2 issues\n  2:3: text must be inside a <voice>\n  4:5: invalid time: soon, expected a duration like 500ms or 2s"
+        );
+
+        let error = validate_ssml("<speak>\n<voice></speak>").unwrap_err();
+        assert_eq!(error.issues.last().unwrap().kind, SsmlIssueKind::Syntax);
+        assert_eq!(error.issues.last().unwrap().line, 2);
+    }
+}
@@ -1,16 +1,42 @@
+#[cfg(any(feature = "rest-synthesizer", feature = "websocket-synthesizer"))]
+use std::sync::Arc;
+
 use log::info;
 
+#[cfg(feature = "rest-synthesizer")]
+use crate::transport::HttpTransport;
+#[cfg(feature = "websocket-synthesizer")]
+use crate::transport::WebsocketTransport;
 use crate::{AudioFormat, AuthOptions};
 
+#[cfg(feature = "cache")]
+mod cache;
+#[cfg(feature = "websocket-synthesizer")]
+mod metadata;
+#[cfg(feature = "websocket-synthesizer")]
+mod pool;
 #[cfg(feature = "rest-synthesizer")]
 mod rest;
+#[cfg(any(feature = "rest-synthesizer", feature = "websocket-synthesizer"))]
+mod retry;
 #[cfg(feature = "unified-synthesizer")]
 mod unified;
 #[cfg(feature = "websocket-synthesizer")]
 mod websocket;
 
+#[cfg(feature = "cache")]
+pub use cache::{CachedSynthesizer, SynthesisCache, DEFAULT_CACHE_SIZE};
+#[cfg(feature = "websocket-synthesizer")]
+pub use metadata::{
+    BookmarkEvent, BoundaryEvent, MetadataOptions, MetadataOptionsBuilder, SynthesisChunk,
+    SynthesisEvent, SynthesisOutput, VisemeEvent,
+};
+#[cfg(feature = "websocket-synthesizer")]
+pub use pool::WebsocketSynthesizerPool;
 #[cfg(feature = "rest-synthesizer")]
 pub use rest::*;
+#[cfg(any(feature = "rest-synthesizer", feature = "websocket-synthesizer"))]
+pub use retry::{RetryPolicy, RetryPolicyBuilder};
 #[cfg(feature = "unified-synthesizer")]
 pub use unified::*;
 #[cfg(feature = "websocket-synthesizer")]
@@ -23,6 +49,18 @@ pub struct SynthesizerConfig<'a> {
     pub(crate) auth: AuthOptions<'a>,
     /// The audio format of the output audio.
     pub(crate) audio_format: AudioFormat,
+    /// The policy for retrying failed requests.
+    #[cfg(any(feature = "rest-synthesizer", feature = "websocket-synthesizer"))]
+    pub(crate) retry_policy: RetryPolicy,
+    /// Options that control which metadata events are emitted by the websocket API.
+    #[cfg(feature = "websocket-synthesizer")]
+    pub(crate) metadata_options: MetadataOptions,
+    /// The transport used by [`RestSynthesizer`], [`crate::transport::NetworkTransport`] if not set.
+    #[cfg(feature = "rest-synthesizer")]
+    pub(crate) http_transport: Option<Arc<dyn HttpTransport>>,
+    /// The transport used by [`WebsocketSynthesizer`], [`crate::transport::NetworkTransport`] if not set.
+    #[cfg(feature = "websocket-synthesizer")]
+    pub(crate) websocket_transport: Option<Arc<dyn WebsocketTransport>>,
 }
 
 #[cfg(feature = "websocket-synthesizer")]
@@ -32,12 +70,118 @@ impl<'a> SynthesizerConfig<'a> {
     /// Create a new [`SynthesizerConfig`] with the given [`AuthOptions`] and [`AudioFormat`].
     pub fn new(auth: AuthOptions<'a>, audio_format: AudioFormat) -> Self {
         info!("Successfully created SynthesizerConfig");
-        Self { auth, audio_format }
+        Self {
+            auth,
+            audio_format,
+            #[cfg(any(feature = "rest-synthesizer", feature = "websocket-synthesizer"))]
+            retry_policy: Default::default(),
+            #[cfg(feature = "websocket-synthesizer")]
+            metadata_options: Default::default(),
+            #[cfg(feature = "rest-synthesizer")]
+            http_transport: None,
+            #[cfg(feature = "websocket-synthesizer")]
+            websocket_transport: None,
+        }
+    }
+
+    /// Convert into a [`SynthesizerConfig`] that owns all its data.
+    pub fn into_owned(self) -> SynthesizerConfig<'static> {
+        SynthesizerConfig {
+            auth: self.auth.into_owned(),
+            audio_format: self.audio_format,
+            #[cfg(any(feature = "rest-synthesizer", feature = "websocket-synthesizer"))]
+            retry_policy: self.retry_policy,
+            #[cfg(feature = "websocket-synthesizer")]
+            metadata_options: self.metadata_options,
+            #[cfg(feature = "rest-synthesizer")]
+            http_transport: self.http_transport,
+            #[cfg(feature = "websocket-synthesizer")]
+            websocket_transport: self.websocket_transport,
+        }
+    }
+
+    /// The authentication options.
+    pub fn auth(&self) -> &AuthOptions<'a> {
+        &self.auth
+    }
+
+    /// The audio format of the output audio.
+    pub fn audio_format(&self) -> AudioFormat {
+        self.audio_format
+    }
+
+    /// The policy for retrying failed requests.
+    ///
+    /// No request is retried by default.
+    #[cfg(any(feature = "rest-synthesizer", feature = "websocket-synthesizer"))]
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+
+    /// The policy for retrying failed requests.
+    ///
+    /// No request is retried by default.
+    #[cfg(any(feature = "rest-synthesizer", feature = "websocket-synthesizer"))]
+    pub fn retry_policy_mut(&mut self) -> &mut RetryPolicy {
+        &mut self.retry_policy
+    }
+
+    /// Options that control which metadata events are emitted by the websocket API.
+    ///
+    /// All metadata is disabled by default. It has no effect on [`RestSynthesizer`].
+    #[cfg(feature = "websocket-synthesizer")]
+    pub fn metadata_options(&self) -> &MetadataOptions {
+        &self.metadata_options
+    }
+
+    /// Options that control which metadata events are emitted by the websocket API.
+    ///
+    /// All metadata is disabled by default. It has no effect on [`RestSynthesizer`].
+    #[cfg(feature = "websocket-synthesizer")]
+    pub fn metadata_options_mut(&mut self) -> &mut MetadataOptions {
+        &mut self.metadata_options
+    }
+
+    /// The transport used by [`RestSynthesizer`].
+    ///
+    /// If it is not set, the synthesizer talks to the service over the network
+    /// with a [`crate::transport::NetworkTransport`] that uses the proxy of the [`AuthOptions`].
+    #[cfg(feature = "rest-synthesizer")]
+    pub fn http_transport(&self) -> Option<&Arc<dyn HttpTransport>> {
+        self.http_transport.as_ref()
+    }
+
+    /// The transport used by [`RestSynthesizer`].
+    ///
+    /// If it is not set, the synthesizer talks to the service over the network
+    /// with a [`crate::transport::NetworkTransport`] that uses the proxy of the [`AuthOptions`].
+    #[cfg(feature = "rest-synthesizer")]
+    pub fn http_transport_mut(&mut self) -> &mut Option<Arc<dyn HttpTransport>> {
+        &mut self.http_transport
+    }
+
+    /// The transport used by [`WebsocketSynthesizer`].
+    ///
+    /// If it is not set, the synthesizer talks to the service over the network
+    /// with a [`crate::transport::NetworkTransport`] that uses the proxy of the [`AuthOptions`].
+    #[cfg(feature = "websocket-synthesizer")]
+    pub fn websocket_transport(&self) -> Option<&Arc<dyn WebsocketTransport>> {
+        self.websocket_transport.as_ref()
+    }
+
+    /// The transport used by [`WebsocketSynthesizer`].
+    ///
+    /// If it is not set, the synthesizer talks to the service over the network
+    /// with a [`crate::transport::NetworkTransport`] that uses the proxy of the [`AuthOptions`].
+    #[cfg(feature = "websocket-synthesizer")]
+    pub fn websocket_transport_mut(&mut self) -> &mut Option<Arc<dyn WebsocketTransport>> {
+        &mut self.websocket_transport
     }
 
     #[cfg(feature = "websocket-synthesizer")]
     fn generate_client_request(
         &self,
+        provided_headers: Vec<(hyper::header::HeaderName, hyper::http::HeaderValue)>,
     ) -> Result<tokio_tungstenite::tungstenite::handshake::client::Request, WebsocketSynthesizerError>
     {
         use hyper::http::HeaderValue;
@@ -64,7 +208,9 @@ impl<'a> SynthesizerConfig<'a> {
                 source: Some(e.into()),
             })?;
         let headers = request.headers_mut();
-        if let Some(key) = &self.auth.key {
+        if !provided_headers.is_empty() {
+            headers.extend(provided_headers);
+        } else if let Some(key) = &self.auth.key {
             headers.append("Ocp-Apim-Subscription-Key", HeaderValue::from_str(key)?);
         }
         if !self.auth.headers.is_empty() {
@@ -76,42 +222,50 @@ impl<'a> SynthesizerConfig<'a> {
     }
 
     /// Connect to the Azure Speech Service and return a [`WebsocketSynthesizer`] on success.
+    ///
+    /// The [`WebsocketSynthesizer`] keeps a copy of this config
+    /// so that it can reconnect when the server closes the connection.
     #[cfg(feature = "websocket-synthesizer")]
     pub async fn connect_websocket(
         self,
     ) -> Result<WebsocketSynthesizer, WebsocketSynthesizerError> {
-        use crate::errors::{ConnectError, ConnectErrorKind};
-        use crate::net::{self, connect_directly};
+        let stream = self.open_websocket().await?;
+        info!("Successfully created Synthesizer");
+        Ok(WebsocketSynthesizer {
+            config: self.into_owned(),
+            stream,
+            closed: false,
+        })
+    }
+
+    /// Create a [`WebsocketSynthesizerPool`] that opens up to `size` websocket connections on demand
+    /// and serves concurrent requests with them.
+    ///
+    /// A `size` of zero is treated as one.
+    #[cfg(feature = "websocket-synthesizer")]
+    pub fn websocket_pool(self, size: usize) -> WebsocketSynthesizerPool {
+        WebsocketSynthesizerPool::new(self, size)
+    }
+
+    /// Open a websocket connection and perform the `speech.config` handshake.
+    #[cfg(feature = "websocket-synthesizer")]
+    pub(crate) async fn open_websocket(
+        &self,
+    ) -> Result<crate::transport::BoxWebsocketStream, WebsocketSynthesizerError> {
+        use crate::transport::NetworkTransport;
         use chrono::Utc;
         use futures_util::SinkExt;
         use tokio_tungstenite::tungstenite::Message;
         use uuid::Uuid;
 
-        let request = self.generate_client_request()?;
-        let proxy_url = self
-            .auth
-            .proxy
-            .as_deref()
-            .map(reqwest::Url::parse)
-            .transpose()
-            .map_err(|e| ConnectError {
-                kind: ConnectErrorKind::BadUrl(self.auth.proxy.unwrap().to_string()),
-                source: Some(e.into()),
-            })?;
-        let mut wss = match proxy_url.as_ref().map(|x| x.scheme()) {
-            Some("socks5") => {
-                net::connect_via_socks5_proxy(request, proxy_url.as_ref().unwrap()).await?
-            }
-            Some("http") | Some("https") => {
-                net::connect_via_http_proxy(request, proxy_url.as_ref().unwrap()).await?
-            }
-            None => connect_directly(request).await?,
-            Some(other_scheme) => {
-                return Err(ConnectError {
-                    kind: ConnectErrorKind::UnsupportedScheme(Some(other_scheme.to_string())),
-                    source: None,
-                }
-                .into())
+        let provided_headers = self.auth.provided_headers().await?;
+        let request = self.generate_client_request(provided_headers)?;
+        let mut wss = match &self.websocket_transport {
+            Some(transport) => transport.connect(request).await?,
+            None => {
+                NetworkTransport::new(self.auth.proxy.as_deref())?
+                    .connect(request)
+                    .await?
             }
         };
         let uuid = Uuid::new_v4();
@@ -120,68 +274,52 @@ impl<'a> SynthesizerConfig<'a> {
         wss.send(Message::Text(format!(
             "Path: speech.config\r\nX-RequestId: {request_id}\r\nX-Timestamp: {now:?}Content-Type: application/json\r\n\r\n{CLIENT_INFO_PAYLOAD}"
         ))).await?;
-        info!("Successfully created Synthesizer");
-        Ok(WebsocketSynthesizer {
-            audio_format: self.audio_format,
-            stream: wss,
-        })
+        Ok(wss)
     }
 
     #[cfg(feature = "rest-synthesizer")]
     /// Construct a [`RestSynthesizer`] from this [`SynthesizerConfig`].
     pub fn rest_synthesizer(&self) -> Result<RestSynthesizer, RestSynthesizerError> {
-        use crate::utils::{transpose_tuple_option_result, ClientBuilderExt};
+        use crate::transport::NetworkTransport;
+        use crate::utils::transpose_tuple_option_result;
         use hyper::{header, http::HeaderValue};
-        use reqwest::Proxy;
 
         Ok(RestSynthesizer {
-            client: reqwest::Client::builder()
-                .user_agent("aspeak")
-                .default_headers(header::HeaderMap::from_iter(
-                    [
-                        Some((
-                            header::CONTENT_TYPE,
-                            HeaderValue::from_static("application/ssml+xml"),
-                        )),
-                        Some((
-                            header::HeaderName::from_bytes(b"X-Microsoft-OutputFormat").unwrap(),
-                            HeaderValue::from_static(self.audio_format.into()),
-                        )),
-                        transpose_tuple_option_result(self.auth.key().map(|key| {
-                            (
-                                header::HeaderName::from_bytes(b"Ocp-Apim-Subscription-Key")
-                                    .unwrap(),
-                                HeaderValue::from_str(key),
-                            )
-                        }))?,
-                        transpose_tuple_option_result(self.auth.token().map(|token| {
-                            (
-                                header::HeaderName::from_static("Authorization"),
-                                HeaderValue::from_str(token),
-                            )
-                        }))?,
-                    ]
-                    .into_iter()
-                    .flatten()
-                    .chain(self.auth.headers.iter().map(Clone::clone)),
-                ))
-                .optional_proxy(
-                    self.auth
-                        .proxy
-                        .as_deref()
-                        .map(Proxy::all)
-                        .transpose()
-                        .map_err(|e| RestSynthesizerError {
-                            kind: RestSynthesizerErrorKind::Connect,
-                            source: Some(e.into()),
-                        })?,
-                )
-                .build()
-                .map_err(|e| RestSynthesizerError {
-                    kind: RestSynthesizerErrorKind::Connect,
-                    source: Some(e.into()),
-                })?,
+            transport: match &self.http_transport {
+                Some(transport) => transport.clone(),
+                None => Arc::new(NetworkTransport::new(self.auth.proxy.as_deref())?),
+            },
+            headers: header::HeaderMap::from_iter(
+                [
+                    Some((
+                        header::CONTENT_TYPE,
+                        HeaderValue::from_static("application/ssml+xml"),
+                    )),
+                    Some((
+                        header::HeaderName::from_bytes(b"X-Microsoft-OutputFormat").unwrap(),
+                        HeaderValue::from_static(self.audio_format.into()),
+                    )),
+                    transpose_tuple_option_result(self.auth.key().map(|key| {
+                        (
+                            header::HeaderName::from_bytes(b"Ocp-Apim-Subscription-Key").unwrap(),
+                            HeaderValue::from_str(key),
+                        )
+                    }))?,
+                    transpose_tuple_option_result(self.auth.token().map(|token| {
+                        (
+                            header::HeaderName::from_static("Authorization"),
+                            HeaderValue::from_str(token),
+                        )
+                    }))?,
+                ]
+                .into_iter()
+                .flatten()
+                .chain(self.auth.headers.iter().map(Clone::clone)),
+            ),
             endpoint: self.auth.endpoint.to_string(),
+            audio_format: self.audio_format,
+            retry_policy: self.retry_policy,
+            credential_provider: self.auth.credential_provider.clone(),
         })
     }
 }
@@ -0,0 +1,321 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use log::{debug, warn};
+use sha2::{Digest, Sha256};
+
+use super::{UnifiedSynthesizer, UnifiedSynthesizerError};
+use crate::AudioFormat;
+
+/// The default size limit of a [`SynthesisCache`], 1 GiB.
+pub const DEFAULT_CACHE_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// The extension of the cache entries
+const ENTRY_EXTENSION: &str = "audio";
+
+/// An on-disk cache of synthesized audio.
+///
+/// Every entry is a file in the cache directory named after the hash of the request.
+/// The modification time of an entry is updated whenever it is read,
+/// and the least recently used entries are evicted when the total size exceeds the limit.
+///
+/// The cache is safe to share between synthesizers, even across processes.
+/// The total size is scanned once and then tracked in memory,
+/// so entries written by other processes are only accounted for at the next eviction.
+#[derive(Debug, Clone)]
+pub struct SynthesisCache {
+    dir: PathBuf,
+    max_size: u64,
+    /// The total size of the entries, `None` until the directory is scanned.
+    /// It is shared by the clones of this cache.
+    size: Arc<Mutex<Option<u64>>>,
+}
+
+impl SynthesisCache {
+    /// Open the cache in the given directory, creating the directory if it does not exist.
+    ///
+    /// `max_size` is the maximum total size of the cached audio in bytes.
+    pub fn open(dir: impl Into<PathBuf>, max_size: u64) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            max_size,
+            size: Default::default(),
+        })
+    }
+
+    /// The directory of the cache.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The maximum total size of the cached audio in bytes.
+    pub fn max_size(&self) -> u64 {
+        self.max_size
+    }
+
+    /// The key of the audio synthesized from `ssml` in `format` by the service at `endpoint`.
+    ///
+    /// Runs of whitespace in the SSML are collapsed into a single space,
+    /// so that documents that only differ in indentation share an entry.
+    /// Whitespace is never dropped because it separates words even between tags.
+    ///
+    /// ```
+    /// use aspeak::{synthesizer::SynthesisCache, AudioFormat};
+    ///
+    /// let endpoint = "https://eastus.tts.speech.microsoft.com/cognitiveservices/v1";
+    /// let format = AudioFormat::Riff24Khz16BitMonoPcm;
+    /// assert_eq!(
+    ///     SynthesisCache::key("<speak>\n  <voice>Hello  world</voice>\n</speak>", format, endpoint),
+    ///     SynthesisCache::key("<speak> <voice>Hello world</voice> </speak>", format, endpoint),
+    /// );
+    /// assert_ne!(
+    ///     SynthesisCache::key("<speak><w>Hello</w> <w>world</w></speak>", format, endpoint),
+    ///     SynthesisCache::key("<speak><w>Hello</w><w>world</w></speak>", format, endpoint),
+    /// );
+    /// assert_ne!(
+    ///     SynthesisCache::key("<speak>Hello</speak>", format, endpoint),
+    ///     SynthesisCache::key("<speak>Hello</speak>", AudioFormat::Audio24Khz48KBitRateMonoMp3, endpoint),
+    /// );
+    /// ```
+    pub fn key(ssml: &str, format: AudioFormat, endpoint: &str) -> String {
+        let format: &str = format.into();
+        let mut hasher = Sha256::new();
+        hasher.update(endpoint.as_bytes());
+        hasher.update([0]);
+        hasher.update(format.as_bytes());
+        hasher.update([0]);
+        hasher.update(normalize_ssml(ssml).as_bytes());
+        hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(key).with_extension(ENTRY_EXTENSION)
+    }
+
+    /// Read the cached audio of the key and mark the entry as recently used.
+    pub fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        let path = self.entry_path(key);
+        let audio = match fs::read(&path) {
+            Ok(audio) => audio,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        // Rewriting the entry updates its modification time without `File::set_modified`,
+        // which is not available in our minimum supported Rust version
+        self.write_entry(key, &path, &audio)?;
+        Ok(Some(audio))
+    }
+
+    /// Write the entry to a temporary file first so that readers never see a partial entry
+    fn write_entry(&self, key: &str, path: &Path, audio: &[u8]) -> io::Result<()> {
+        let partial = self.dir.join(format!("{key}.{}.part", std::process::id()));
+        fs::write(&partial, audio)?;
+        fs::rename(&partial, path)
+    }
+
+    /// Store the audio of the key and evict the least recently used entries if the cache is too large.
+    ///
+    /// Audio larger than the size limit is not stored.
+    pub fn put(&self, key: &str, audio: &[u8]) -> io::Result<()> {
+        if audio.len() as u64 > self.max_size {
+            return Ok(());
+        }
+        let path = self.entry_path(key);
+        let replaced = match fs::metadata(&path) {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+        self.write_entry(key, &path, audio)?;
+        let mut size = self.size.lock().unwrap();
+        // An unknown size is determined by the eviction below, which scans the directory
+        if let Some(size) = size.as_mut() {
+            *size = (*size + audio.len() as u64).saturating_sub(replaced);
+            if *size <= self.max_size {
+                return Ok(());
+            }
+        }
+        *size = Some(self.evict()?);
+        Ok(())
+    }
+
+    /// Remove the least recently used entries until the total size is within the limit.
+    ///
+    /// Returns the total size after the eviction.
+    fn evict(&self) -> io::Result<u64> {
+        let mut entries = Vec::new();
+        let mut total = 0;
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(ENTRY_EXTENSION) {
+                continue;
+            }
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                // Removed by another process
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            total += metadata.len();
+            entries.push((metadata.modified()?, metadata.len(), path));
+        }
+        if total <= self.max_size {
+            return Ok(total);
+        }
+        entries.sort_unstable_by_key(|(modified, _, _)| *modified);
+        for (_, len, path) in entries {
+            if total <= self.max_size {
+                break;
+            }
+            debug!("Evicting cache entry {}", path.display());
+            match fs::remove_file(&path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+            total -= len;
+        }
+        Ok(total)
+    }
+}
+
+/// Collapse runs of whitespace into a single space and trim the document.
+fn normalize_ssml(ssml: &str) -> String {
+    ssml.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// A [`UnifiedSynthesizer`] that serves repeated requests from a [`SynthesisCache`]
+/// and forwards the others to the wrapped synthesizer.
+///
+/// Errors of the cache are logged and otherwise ignored, so that a broken cache never fails a synthesis.
+pub struct CachedSynthesizer<S> {
+    inner: S,
+    cache: SynthesisCache,
+    endpoint: String,
+}
+
+impl<S: UnifiedSynthesizer> CachedSynthesizer<S> {
+    /// Wrap the synthesizer that talks to the service at `endpoint`.
+    ///
+    /// The endpoint is part of the cache key, e.g. [`crate::AuthOptions::endpoint`].
+    pub fn new(inner: S, cache: SynthesisCache, endpoint: impl Into<String>) -> Self {
+        Self {
+            inner,
+            cache,
+            endpoint: endpoint.into(),
+        }
+    }
+
+    /// The wrapped synthesizer.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// The wrapped synthesizer.
+    pub fn inner_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// The cache.
+    pub fn cache(&self) -> &SynthesisCache {
+        &self.cache
+    }
+
+    /// Unwrap the synthesizer.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+#[async_trait]
+impl<S: UnifiedSynthesizer> UnifiedSynthesizer for CachedSynthesizer<S> {
+    fn audio_format(&self) -> AudioFormat {
+        self.inner.audio_format()
+    }
+
+    async fn process_ssml(&mut self, ssml: &str) -> Result<Vec<u8>, UnifiedSynthesizerError> {
+        let key = SynthesisCache::key(ssml, self.inner.audio_format(), &self.endpoint);
+        // The file system is accessed on the blocking thread pool
+        let (cache, entry_key) = (self.cache.clone(), key.clone());
+        match tokio::task::spawn_blocking(move || cache.get(&entry_key))
+            .await
+            .map_err(|e| io::Error::new(ErrorKind::Other, e))
+            .and_then(|result| result)
+        {
+            Ok(Some(audio)) => {
+                debug!("Cache hit: {key}");
+                return Ok(audio);
+            }
+            Ok(None) => debug!("Cache miss: {key}"),
+            Err(e) => warn!("Failed to read cache entry {key}: {e}"),
+        }
+        let audio = self.inner.process_ssml(ssml).await?;
+        let (cache, entry_key, entry) = (self.cache.clone(), key.clone(), audio.clone());
+        if let Err(e) = tokio::task::spawn_blocking(move || cache.put(&entry_key, &entry))
+            .await
+            .map_err(|e| io::Error::new(ErrorKind::Other, e))
+            .and_then(|result| result)
+        {
+            warn!("Failed to write cache entry {key}: {e}");
+        }
+        Ok(audio)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use super::*;
+
+    /// Wait until entries written afterwards have a later modification time
+    fn tick() {
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    #[test]
+    fn least_recently_used_entries_are_evicted() {
+        let dir = std::env::temp_dir().join(format!("aspeak-cache-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cache = SynthesisCache::open(&dir, 100).unwrap();
+        cache.put("a", &[1; 40]).unwrap();
+        tick();
+        cache.put("b", &[2; 40]).unwrap();
+        assert_eq!(*cache.size.lock().unwrap(), Some(80));
+        tick();
+        // Reading an entry marks it as recently used
+        assert_eq!(cache.get("a").unwrap(), Some(vec![1; 40]));
+        tick();
+        cache.clone().put("c", &[3; 40]).unwrap();
+        assert_eq!(cache.get("b").unwrap(), None);
+        assert_eq!(cache.get("c").unwrap(), Some(vec![3; 40]));
+        assert_eq!(*cache.size.lock().unwrap(), Some(80));
+        // Replacing an entry doesn't count its old size
+        cache.put("c", &[4; 10]).unwrap();
+        assert_eq!(*cache.size.lock().unwrap(), Some(50));
+        // Audio larger than the limit is not stored
+        cache.put("d", &[5; 101]).unwrap();
+        assert_eq!(cache.get("d").unwrap(), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn whitespace_is_collapsed() {
+        assert_eq!(
+            normalize_ssml("\n<speak>\n  <voice>Hello \t world</voice>\n</speak>\n"),
+            "<speak> <voice>Hello world</voice> </speak>"
+        );
+    }
+}
@@ -0,0 +1,384 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+
+/// Options that control which metadata events are emitted by the websocket API
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MetadataOptions {
+    /// Emit word boundary events
+    pub(crate) word_boundary: bool,
+    /// Emit punctuation boundary events
+    pub(crate) punctuation_boundary: bool,
+    /// Emit sentence boundary events
+    pub(crate) sentence_boundary: bool,
+    /// Emit viseme events
+    pub(crate) viseme: bool,
+    /// Emit bookmark events
+    pub(crate) bookmark: bool,
+}
+
+impl MetadataOptions {
+    /// Emit word boundary events
+    pub fn word_boundary(&self) -> bool {
+        self.word_boundary
+    }
+
+    /// Emit punctuation boundary events
+    pub fn punctuation_boundary(&self) -> bool {
+        self.punctuation_boundary
+    }
+
+    /// Emit sentence boundary events
+    pub fn sentence_boundary(&self) -> bool {
+        self.sentence_boundary
+    }
+
+    /// Emit viseme events
+    pub fn viseme(&self) -> bool {
+        self.viseme
+    }
+
+    /// Emit bookmark events
+    pub fn bookmark(&self) -> bool {
+        self.bookmark
+    }
+
+    /// Returns `true` if any kind of metadata is enabled
+    pub fn any(&self) -> bool {
+        self.word_boundary
+            || self.punctuation_boundary
+            || self.sentence_boundary
+            || self.viseme
+            || self.bookmark
+    }
+
+    /// Create a builder for [`MetadataOptions`]
+    pub fn builder() -> MetadataOptionsBuilder {
+        MetadataOptionsBuilder::new()
+    }
+
+    pub(crate) fn to_json(self) -> String {
+        format!(
+            r#"{{"bookmarkEnabled":{},"punctuationBoundaryEnabled":{},"sentenceBoundaryEnabled":{},"wordBoundaryEnabled":{},"visemeEnabled":{},"sessionEndEnabled":false}}"#,
+            self.bookmark,
+            self.punctuation_boundary,
+            self.sentence_boundary,
+            self.word_boundary,
+            self.viseme
+        )
+    }
+}
+
+/// Builder for [`MetadataOptions`]
+#[derive(Debug, Default)]
+pub struct MetadataOptionsBuilder {
+    options: MetadataOptions,
+}
+
+impl MetadataOptionsBuilder {
+    /// Create a new builder with all metadata disabled
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Emit word boundary events
+    pub fn word_boundary(mut self, enabled: bool) -> Self {
+        self.options.word_boundary = enabled;
+        self
+    }
+
+    /// Emit punctuation boundary events
+    pub fn punctuation_boundary(mut self, enabled: bool) -> Self {
+        self.options.punctuation_boundary = enabled;
+        self
+    }
+
+    /// Emit sentence boundary events
+    pub fn sentence_boundary(mut self, enabled: bool) -> Self {
+        self.options.sentence_boundary = enabled;
+        self
+    }
+
+    /// Emit viseme events
+    pub fn viseme(mut self, enabled: bool) -> Self {
+        self.options.viseme = enabled;
+        self
+    }
+
+    /// Emit bookmark events
+    pub fn bookmark(mut self, enabled: bool) -> Self {
+        self.options.bookmark = enabled;
+        self
+    }
+
+    /// Build [`MetadataOptions`]
+    pub fn build(self) -> MetadataOptions {
+        self.options
+    }
+}
+
+/// A metadata event emitted by the websocket API during synthesis
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum SynthesisEvent {
+    WordBoundary(BoundaryEvent),
+    PunctuationBoundary(BoundaryEvent),
+    SentenceBoundary(BoundaryEvent),
+    Viseme(VisemeEvent),
+    Bookmark(BookmarkEvent),
+}
+
+impl SynthesisEvent {
+    /// The offset of this event in the synthesized audio
+    pub fn audio_offset(&self) -> Duration {
+        match self {
+            SynthesisEvent::WordBoundary(e)
+            | SynthesisEvent::PunctuationBoundary(e)
+            | SynthesisEvent::SentenceBoundary(e) => e.audio_offset,
+            SynthesisEvent::Viseme(e) => e.audio_offset,
+            SynthesisEvent::Bookmark(e) => e.audio_offset,
+        }
+    }
+}
+
+/// A word, punctuation or sentence boundary
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct BoundaryEvent {
+    /// The offset of the boundary in the synthesized audio
+    pub audio_offset: Duration,
+    /// The duration of the spoken text in the synthesized audio
+    pub duration: Duration,
+    /// The spoken text
+    pub text: String,
+}
+
+impl BoundaryEvent {
+    /// Create a boundary of the text spoken at `audio_offset` for `duration`.
+    pub fn new(audio_offset: Duration, duration: Duration, text: impl Into<String>) -> Self {
+        Self {
+            audio_offset,
+            duration,
+            text: text.into(),
+        }
+    }
+}
+
+/// A viseme, which can be used to animate the mouth of a character
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct VisemeEvent {
+    /// The offset of the viseme in the synthesized audio
+    pub audio_offset: Duration,
+    /// The viseme id
+    pub viseme_id: u32,
+    /// The animation data, only available for some viseme types
+    pub animation: Option<String>,
+}
+
+/// A bookmark that is reached during synthesis
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct BookmarkEvent {
+    /// The offset of the bookmark in the synthesized audio
+    pub audio_offset: Duration,
+    /// The name of the bookmark(the `mark` attribute)
+    pub name: String,
+}
+
+/// A piece of the output of a synthesis, either audio data or a metadata event
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum SynthesisChunk {
+    Audio(Vec<u8>),
+    Event(SynthesisEvent),
+}
+
+/// Synthesized audio and the metadata events that were emitted along with it
+#[derive(Debug, Clone, Default, PartialEq)]
+#[non_exhaustive]
+pub struct SynthesisOutput {
+    pub audio: Vec<u8>,
+    pub events: Vec<SynthesisEvent>,
+}
+
+/// Offsets and durations in the metadata are in ticks of 100 nanoseconds
+fn ticks_to_duration(ticks: u64) -> Duration {
+    Duration::from_nanos(ticks.saturating_mul(100))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RawMetadataBody {
+    metadata: Vec<RawMetadata>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RawMetadata {
+    #[serde(rename = "Type")]
+    kind: String,
+    data: RawMetadataData,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RawMetadataData {
+    offset: u64,
+    duration: Option<u64>,
+    #[serde(rename = "text")]
+    text: Option<RawBoundaryText>,
+    viseme_id: Option<u32>,
+    animation: Option<String>,
+    bookmark: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RawBoundaryText {
+    text: String,
+    boundary_type: Option<String>,
+}
+
+/// Parse the body of an `audio.metadata` message.
+///
+/// Metadata types that we don't know about(e.g. `SessionEnd`) are skipped.
+pub(crate) fn parse_metadata(body: &str) -> Result<Vec<SynthesisEvent>, serde_json::Error> {
+    let raw: RawMetadataBody = serde_json::from_str(body)?;
+    Ok(raw
+        .metadata
+        .into_iter()
+        .filter_map(|RawMetadata { kind, data }| {
+            let audio_offset = ticks_to_duration(data.offset);
+            Some(match kind.as_str() {
+                "WordBoundary" | "SentenceBoundary" => {
+                    let text = data.text?;
+                    let boundary = BoundaryEvent {
+                        audio_offset,
+                        duration: ticks_to_duration(data.duration.unwrap_or_default()),
+                        text: text.text,
+                    };
+                    match text.boundary_type.as_deref().unwrap_or(kind.as_str()) {
+                        "PunctuationBoundary" => SynthesisEvent::PunctuationBoundary(boundary),
+                        "SentenceBoundary" => SynthesisEvent::SentenceBoundary(boundary),
+                        _ => SynthesisEvent::WordBoundary(boundary),
+                    }
+                }
+                "Viseme" => SynthesisEvent::Viseme(VisemeEvent {
+                    audio_offset,
+                    viseme_id: data.viseme_id?,
+                    animation: data.animation.filter(|a| !a.is_empty()),
+                }),
+                "Bookmark" => SynthesisEvent::Bookmark(BookmarkEvent {
+                    audio_offset,
+                    name: data.bookmark?,
+                }),
+                _ => return None,
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boundaries() {
+        let events = parse_metadata(
+            r#"{"Metadata":[
+                {"Type":"WordBoundary","Data":{"Offset":1000000,"Duration":2500000,"text":{"Text":"Hello","Length":5,"BoundaryType":"WordBoundary"}}},
+                {"Type":"WordBoundary","Data":{"Offset":3500000,"Duration":500000,"text":{"Text":",","Length":1,"BoundaryType":"PunctuationBoundary"}}},
+                {"Type":"SentenceBoundary","Data":{"Offset":1000000,"Duration":9000000,"text":{"Text":"Hello, world!","Length":13,"BoundaryType":"SentenceBoundary"}}},
+                {"Type":"WordBoundary","Data":{"Offset":4000000,"text":{"Text":"world"}}}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            events,
+            [
+                SynthesisEvent::WordBoundary(BoundaryEvent::new(
+                    Duration::from_millis(100),
+                    Duration::from_millis(250),
+                    "Hello"
+                )),
+                SynthesisEvent::PunctuationBoundary(BoundaryEvent::new(
+                    Duration::from_millis(350),
+                    Duration::from_millis(50),
+                    ","
+                )),
+                SynthesisEvent::SentenceBoundary(BoundaryEvent::new(
+                    Duration::from_millis(100),
+                    Duration::from_millis(900),
+                    "Hello, world!"
+                )),
+                // The boundary type defaults to the type of the metadata
+                SynthesisEvent::WordBoundary(BoundaryEvent::new(
+                    Duration::from_millis(400),
+                    Duration::ZERO,
+                    "world"
+                )),
+            ]
+        );
+    }
+
+    #[test]
+    fn bookmarks_and_visemes() {
+        let events = parse_metadata(
+            r#"{"Metadata":[
+                {"Type":"Bookmark","Data":{"Offset":12340000,"Bookmark":"flower"}},
+                {"Type":"Viseme","Data":{"Offset":500000,"VisemeId":21,"Animation":""}},
+                {"Type":"Viseme","Data":{"Offset":600000,"VisemeId":0,"Animation":"{\"FrameIndex\":0}"}}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            events,
+            [
+                SynthesisEvent::Bookmark(BookmarkEvent {
+                    audio_offset: Duration::from_micros(1_234_000),
+                    name: "flower".to_string(),
+                }),
+                SynthesisEvent::Viseme(VisemeEvent {
+                    audio_offset: Duration::from_millis(50),
+                    viseme_id: 21,
+                    animation: None,
+                }),
+                SynthesisEvent::Viseme(VisemeEvent {
+                    audio_offset: Duration::from_millis(60),
+                    viseme_id: 0,
+                    animation: Some(r#"{"FrameIndex":0}"#.to_string()),
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_and_incomplete_metadata_is_skipped() {
+        let events = parse_metadata(
+            r#"{"Metadata":[
+                {"Type":"SessionEnd","Data":{"Offset":20000000}},
+                {"Type":"WordBoundary","Data":{"Offset":0}},
+                {"Type":"Viseme","Data":{"Offset":0}},
+                {"Type":"Bookmark","Data":{"Offset":0}}
+            ]}"#,
+        )
+        .unwrap();
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn malformed_metadata() {
+        for body in [
+            "",
+            "not json",
+            "{}",
+            r#"{"Metadata":{}}"#,
+            r#"{"Metadata":[{"Type":"WordBoundary"}]}"#,
+            r#"{"Metadata":[{"Type":"WordBoundary","Data":{"Offset":-1}}]}"#,
+            r#"{"Metadata":[{"Type":"Viseme","Data":{"Offset":0,"VisemeId":"one"}}]}"#,
+        ] {
+            assert!(parse_metadata(body).is_err(), "{body:?} should be rejected");
+        }
+    }
+}
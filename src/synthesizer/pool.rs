@@ -0,0 +1,211 @@
+use std::sync::{Arc, Mutex};
+
+use futures_util::{pin_mut, stream, Stream, StreamExt, TryStreamExt};
+use log::debug;
+use tokio::sync::{mpsc, Semaphore, SemaphorePermit};
+
+use super::{SynthesisOutput, SynthesizerConfig, WebsocketSynthesizer, WebsocketSynthesizerError};
+use crate::{interpolate_ssml, AudioFormat, TextOptions};
+
+/// A pool of [`WebsocketSynthesizer`]s that serves concurrent requests.
+///
+/// Every request borrows an idle connection of the pool, or opens a new one
+/// if fewer than [`WebsocketSynthesizerPool::size`] connections are open.
+/// Otherwise the request waits until a connection is returned to the pool.
+///
+/// The pool is cheap to clone and all clones share the same connections,
+/// so it can be shared across tasks, e.g. by the handlers of a web server.
+///
+/// ```no_run
+/// use aspeak::{synthesizer::SynthesizerConfig, AudioFormat, AuthOptionsBuilder, TextOptions};
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let auth = AuthOptionsBuilder::new(aspeak::get_websocket_endpoint_by_region("eastus"))
+///     .key("YOUR_AZURE_SUBSCRIPTION_KEY")
+///     .build();
+/// let pool = SynthesizerConfig::new(auth, AudioFormat::Riff16Khz16BitMonoPcm).websocket_pool(4);
+/// let handles = ["One", "Two", "Three"].map(|text| {
+///     let pool = pool.clone();
+///     tokio::spawn(async move { pool.synthesize_text(text, &TextOptions::default()).await })
+/// });
+/// for handle in handles {
+///     let audio = handle.await??;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct WebsocketSynthesizerPool {
+    inner: Arc<PoolInner>,
+}
+
+struct PoolInner {
+    config: SynthesizerConfig<'static>,
+    size: usize,
+    idle: Mutex<Vec<WebsocketSynthesizer>>,
+    /// Limits the number of connections that are in use or being opened
+    permits: Semaphore,
+}
+
+/// The number of audio chunks buffered by the streams of [`WebsocketSynthesizerPool::synthesize_ssml_stream`]
+const STREAM_BUFFER_SIZE: usize = 16;
+
+/// A connection borrowed from the pool.
+///
+/// It is only returned to the pool by [`PooledSynthesizer::release`]. If the request
+/// fails or is cancelled, the connection is dropped because it might be in an unknown state.
+struct PooledSynthesizer<'a> {
+    synthesizer: WebsocketSynthesizer,
+    pool: &'a PoolInner,
+    _permit: SemaphorePermit<'a>,
+}
+
+impl PooledSynthesizer<'_> {
+    fn release(self) {
+        self.pool.idle.lock().unwrap().push(self.synthesizer);
+    }
+}
+
+impl WebsocketSynthesizerPool {
+    pub(super) fn new(config: SynthesizerConfig<'_>, size: usize) -> Self {
+        let size = size.max(1);
+        Self {
+            inner: Arc::new(PoolInner {
+                config: config.into_owned(),
+                size,
+                idle: Mutex::new(Vec::with_capacity(size)),
+                permits: Semaphore::new(size),
+            }),
+        }
+    }
+
+    /// The maximum number of connections of the pool.
+    pub fn size(&self) -> usize {
+        self.inner.size
+    }
+
+    /// The audio format of the synthesized audio.
+    pub fn audio_format(&self) -> AudioFormat {
+        self.inner.config.audio_format
+    }
+
+    async fn acquire(&self) -> Result<PooledSynthesizer<'_>, WebsocketSynthesizerError> {
+        let permit = self
+            .inner
+            .permits
+            .acquire()
+            .await
+            .expect("the semaphore of the pool is never closed");
+        let idle = self.inner.idle.lock().unwrap().pop();
+        let synthesizer = match idle {
+            Some(synthesizer) => synthesizer,
+            None => {
+                debug!("Opening a new connection for the pool");
+                self.inner.config.clone().connect_websocket().await?
+            }
+        };
+        Ok(PooledSynthesizer {
+            synthesizer,
+            pool: &self.inner,
+            _permit: permit,
+        })
+    }
+
+    /// Synthesize the given SSML into audio([`Vec<u8>`]).
+    pub async fn synthesize_ssml(&self, ssml: &str) -> Result<Vec<u8>, WebsocketSynthesizerError> {
+        let mut connection = self.acquire().await?;
+        let audio = connection.synthesizer.synthesize_ssml(ssml).await?;
+        connection.release();
+        Ok(audio)
+    }
+
+    /// Synthesize the given SSML and return a stream of audio chunks as they arrive from the server.
+    ///
+    /// The synthesis runs in a background task that holds a connection of the pool
+    /// and returns it when the synthesis finishes.
+    /// If the stream is dropped early, the connection is closed instead because the rest of the audio is still on its way.
+    /// Errors that occur before any audio arrives are returned by this method rather than by the stream.
+    pub async fn synthesize_ssml_stream(
+        &self,
+        ssml: &str,
+    ) -> Result<
+        impl Stream<Item = Result<Vec<u8>, WebsocketSynthesizerError>> + Send + 'static,
+        WebsocketSynthesizerError,
+    > {
+        let (sender, mut receiver) = mpsc::channel(STREAM_BUFFER_SIZE);
+        let pool = self.clone();
+        let ssml = ssml.to_string();
+        tokio::spawn(async move {
+            if let Err(e) = pool.forward_audio(&ssml, &sender).await {
+                let _ = sender.send(Err(e)).await;
+            }
+        });
+        let first = receiver.recv().await.transpose()?;
+        let rest = stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|chunk| (chunk, receiver))
+        });
+        Ok(stream::iter(first.map(Ok)).chain(rest))
+    }
+
+    /// Synthesize the SSML with a connection of the pool and send the audio chunks to `sender`.
+    async fn forward_audio(
+        &self,
+        ssml: &str,
+        sender: &mpsc::Sender<Result<Vec<u8>, WebsocketSynthesizerError>>,
+    ) -> Result<(), WebsocketSynthesizerError> {
+        let mut connection = self.acquire().await?;
+        {
+            let stream = connection.synthesizer.synthesize_ssml_stream(ssml).await?;
+            pin_mut!(stream);
+            while let Some(chunk) = stream.try_next().await? {
+                if sender.send(Ok(chunk)).await.is_err() {
+                    debug!("The audio stream is dropped, closing the connection");
+                    return Ok(());
+                }
+            }
+        }
+        connection.release();
+        Ok(())
+    }
+
+    /// Synthesize the given SSML into audio and collect the metadata events emitted along with it.
+    ///
+    /// Use [`super::SynthesizerConfig::metadata_options_mut`] before creating the pool
+    /// to choose which kinds of events you want to receive.
+    pub async fn synthesize_ssml_with_metadata(
+        &self,
+        ssml: &str,
+    ) -> Result<SynthesisOutput, WebsocketSynthesizerError> {
+        let mut connection = self.acquire().await?;
+        let output = connection
+            .synthesizer
+            .synthesize_ssml_with_metadata(ssml)
+            .await?;
+        connection.release();
+        Ok(output)
+    }
+
+    /// Synthesize the given text into audio([`Vec<u8>`]).
+    /// This is a convenience method that interpolates the SSML for you.
+    pub async fn synthesize_text(
+        &self,
+        text: impl AsRef<str>,
+        options: &TextOptions<'_>,
+    ) -> Result<Vec<u8>, WebsocketSynthesizerError> {
+        debug!("Synthesizing text: {}", text.as_ref());
+        let ssml = interpolate_ssml(text, options)?;
+        self.synthesize_ssml(&ssml).await
+    }
+
+    /// Synthesize the given text into audio and collect the metadata events emitted along with it.
+    /// This is a convenience method that interpolates the SSML for you.
+    pub async fn synthesize_text_with_metadata(
+        &self,
+        text: impl AsRef<str>,
+        options: &TextOptions<'_>,
+    ) -> Result<SynthesisOutput, WebsocketSynthesizerError> {
+        debug!("Synthesizing text: {}", text.as_ref());
+        let ssml = interpolate_ssml(text, options)?;
+        self.synthesize_ssml_with_metadata(&ssml).await
+    }
+}
@@ -1,66 +1,132 @@
 use std::{
     error::Error,
     fmt::{self, Display, Formatter},
+    sync::Arc,
+    time::{Duration, SystemTime},
 };
 
 use bytes::Bytes;
-use hyper::header::{InvalidHeaderName, InvalidHeaderValue};
-use log::debug;
-use reqwest::{Client, StatusCode};
+use hyper::{
+    header::{InvalidHeaderName, InvalidHeaderValue, RETRY_AFTER},
+    HeaderMap, Request, StatusCode,
+};
+use log::{debug, warn};
 use strum::AsRefStr;
 
-use crate::{interpolate_ssml, SsmlError, TextOptions};
+use super::RetryPolicy;
+use crate::{
+    interpolate_ssml,
+    transport::{HttpTransport, TransportError, TransportErrorKind},
+    AudioFormat, CredentialError, CredentialProvider, SsmlError, TextOptions,
+};
 
 /// The synthesizer that uses the RESTful API.
 pub struct RestSynthesizer {
-    pub(super) client: Client,
+    pub(super) transport: Arc<dyn HttpTransport>,
+    /// Headers sent with every request
+    pub(super) headers: HeaderMap,
     pub(super) endpoint: String,
+    pub(super) audio_format: AudioFormat,
+    pub(super) retry_policy: RetryPolicy,
+    pub(super) credential_provider: Option<Arc<dyn CredentialProvider>>,
 }
 
 impl RestSynthesizer {
+    /// The audio format of the synthesized audio.
+    pub fn audio_format(&self) -> AudioFormat {
+        self.audio_format
+    }
+
+    /// The policy for retrying failed requests.
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+
+    /// The policy for retrying failed requests.
+    pub fn retry_policy_mut(&mut self) -> &mut RetryPolicy {
+        &mut self.retry_policy
+    }
+
     /// Synthesize the given SSML into audio([`Vec<u8>`]).
     pub async fn synthesize_ssml(&self, ssml: &str) -> Result<Vec<u8>, RestSynthesizerError> {
         Ok(self.synthesize_ssml_to_bytes(ssml).await?.to_vec())
     }
 
     /// Synthesize the given SSML into audio([`bytes::Bytes`]).
+    ///
+    /// Failed requests are retried according to the [`RetryPolicy`] of this synthesizer.
     pub async fn synthesize_ssml_to_bytes(
         &self,
         ssml: &str,
     ) -> Result<Bytes, RestSynthesizerError> {
+        let mut attempt = 1;
+        loop {
+            match self.send_request(ssml).await {
+                Ok(bytes) => return Ok(bytes),
+                Err((e, retry_after))
+                    if e.is_transient() && self.retry_policy.should_retry(attempt) =>
+                {
+                    let delay = self.retry_policy.delay(attempt, retry_after);
+                    warn!(
+                        "Attempt {attempt}/{} failed: {e}, retrying in {delay:?}",
+                        self.retry_policy.max_attempts()
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err((e, _)) => return Err(e),
+            }
+        }
+    }
+
+    /// Send a single request. The `Retry-After` delay is returned along with the error if present.
+    async fn send_request(
+        &self,
+        ssml: &str,
+    ) -> Result<Bytes, (RestSynthesizerError, Option<Duration>)> {
+        let mut request = Request::post(&self.endpoint)
+            .body(Bytes::from(ssml.to_string()))
+            .map_err(|e| (e.into(), None))?;
+        request.headers_mut().extend(self.headers.clone());
+        if let Some(provider) = &self.credential_provider {
+            for (name, value) in provider.headers().await.map_err(|e| (e.into(), None))? {
+                request.headers_mut().append(name, value);
+            }
+        }
         let res = self
-            .client
-            .post(&self.endpoint)
-            .body(ssml.to_string())
-            .send()
+            .transport
+            .send(request)
             .await
-            .map_err(|e| RestSynthesizerError {
-                kind: RestSynthesizerErrorKind::Connect,
-                source: Some(e.into()),
-            })?
-            .error_for_status()
-            .map_err(|e| {
-                use RestSynthesizerErrorKind::*;
-                let kind = match e.status() {
-                    Some(code) => match code {
-                        StatusCode::TOO_MANY_REQUESTS => TooManyRequests,
-                        StatusCode::UNAUTHORIZED => Unauthorized,
-                        StatusCode::BAD_REQUEST => InvalidRequest,
-                        StatusCode::UNSUPPORTED_MEDIA_TYPE => UnsupportedMediaType,
-                        _ => OtherHttp,
-                    },
-                    None => OtherHttp,
-                };
-                RestSynthesizerError {
-                    kind,
-                    source: Some(e.into()),
+            .map_err(|e| (e.into(), None))?;
+        let status = res.status();
+        if status.is_success() {
+            return Ok(res.into_body());
+        }
+        use RestSynthesizerErrorKind::*;
+        let kind = match status {
+            StatusCode::TOO_MANY_REQUESTS => TooManyRequests,
+            StatusCode::UNAUTHORIZED => {
+                // The credentials might have been revoked
+                if let Some(provider) = &self.credential_provider {
+                    provider.invalidate();
                 }
-            })?;
-        let bytes = res.bytes().await.map_err(|e| RestSynthesizerError {
-            kind: RestSynthesizerErrorKind::Connection,
-            source: Some(e.into()),
-        })?;
-        Ok(bytes)
+                Unauthorized
+            }
+            StatusCode::BAD_REQUEST => InvalidRequest,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE => UnsupportedMediaType,
+            StatusCode::SERVICE_UNAVAILABLE => ServiceUnavailable,
+            _ => OtherHttp,
+        };
+        Err((
+            RestSynthesizerError {
+                kind,
+                source: Some(anyhow::anyhow!(
+                    "HTTP status {status} for url ({})",
+                    self.endpoint
+                )),
+            },
+            parse_retry_after(res.headers()),
+        ))
     }
 
     /// This is a convenience method that interpolates the SSML for you.
@@ -86,6 +152,20 @@ impl RestSynthesizer {
     }
 }
 
+/// Parse the `Retry-After` header, which is either a number of seconds or an HTTP date.
+fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    // A date in the past means that we can retry immediately.
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
 /// Errors that can occur while using the RESTful API.
 #[derive(Debug)]
 #[non_exhaustive]
@@ -113,6 +193,17 @@ impl Display for RestSynthesizerError {
     }
 }
 
+impl RestSynthesizerError {
+    /// Returns `true` if the error is likely to go away when the request is retried.
+    fn is_transient(&self) -> bool {
+        use RestSynthesizerErrorKind::*;
+        matches!(
+            self.kind,
+            Connect | Connection | TooManyRequests | ServiceUnavailable
+        )
+    }
+}
+
 impl Error for RestSynthesizerError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         self.source.as_ref().map(|e| e.as_ref() as _)
@@ -140,12 +231,19 @@ pub enum RestSynthesizerErrorKind {
     UnsupportedMediaType,
     /// The server returned a 429 Too Many Requests response.
     TooManyRequests,
+    /// The server returned a 503 Service Unavailable response.
+    ServiceUnavailable,
     /// Other HTTP errors.
     OtherHttp,
     /// Connection errors.
     Connection,
     /// Errors when interpolating SSML.
     Ssml,
+    /// Failed to obtain credentials.
+    Credential,
+    /// The transport failed to handle the request for reasons other than the network,
+    /// e.g. an invalid proxy or a replayed exchange that does not match the request.
+    Transport,
 }
 
 macro_rules! impl_from_for_rest_synthesizer_error {
@@ -164,3 +262,49 @@ macro_rules! impl_from_for_rest_synthesizer_error {
 impl_from_for_rest_synthesizer_error!(InvalidHeaderValue, InvalidRequest);
 impl_from_for_rest_synthesizer_error!(InvalidHeaderName, InvalidRequest);
 impl_from_for_rest_synthesizer_error!(SsmlError, Ssml);
+impl_from_for_rest_synthesizer_error!(CredentialError, Credential);
+impl_from_for_rest_synthesizer_error!(hyper::http::Error, InvalidRequest);
+
+impl From<TransportError> for RestSynthesizerError {
+    fn from(e: TransportError) -> Self {
+        let kind = match e.kind {
+            TransportErrorKind::Connect => RestSynthesizerErrorKind::Connect,
+            TransportErrorKind::Connection => RestSynthesizerErrorKind::Connection,
+            TransportErrorKind::RequestConstruction => RestSynthesizerErrorKind::InvalidRequest,
+            _ => RestSynthesizerErrorKind::Transport,
+        };
+        Self {
+            kind,
+            source: Some(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transport_error(kind: TransportErrorKind) -> RestSynthesizerError {
+        TransportError { kind, source: None }.into()
+    }
+
+    #[test]
+    fn only_network_errors_are_transient() {
+        assert!(transport_error(TransportErrorKind::Connect).is_transient());
+        assert!(transport_error(TransportErrorKind::Connection).is_transient());
+        for kind in [
+            TransportErrorKind::BadUrl("socks6://localhost".into()),
+            TransportErrorKind::RequestConstruction,
+            TransportErrorKind::NoRecording,
+            TransportErrorKind::Mismatch,
+            TransportErrorKind::Io,
+            TransportErrorKind::InvalidFixture,
+        ] {
+            assert!(!transport_error(kind).is_transient());
+        }
+        assert_eq!(
+            transport_error(TransportErrorKind::Mismatch).kind,
+            RestSynthesizerErrorKind::Transport
+        );
+    }
+}
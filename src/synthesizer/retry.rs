@@ -0,0 +1,149 @@
+use std::time::Duration;
+
+/// Policy for retrying failed requests
+///
+/// The delay before the `n`-th retry is `base_delay * 2^(n-1)`, capped at `max_delay`.
+/// Up to `jitter` of the delay is randomly subtracted to avoid retrying in lockstep.
+/// A `Retry-After` header sent by the server takes precedence over the computed delay,
+/// but it is capped at `max_delay` as well.
+///
+/// The default policy makes only one attempt, i.e. it never retries.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts, including the first one
+    pub(crate) max_attempts: u32,
+    /// Delay before the first retry
+    pub(crate) base_delay: Duration,
+    /// Upper bound of the delay, including the one requested by `Retry-After`
+    pub(crate) max_delay: Duration,
+    /// Fraction of the delay that is randomized, between 0 and 1
+    pub(crate) jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            jitter: 0.2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that never retries
+    pub fn no_retry() -> Self {
+        Default::default()
+    }
+
+    /// Maximum number of attempts, including the first one
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// Delay before the first retry
+    pub fn base_delay(&self) -> Duration {
+        self.base_delay
+    }
+
+    /// Upper bound of the delay, including the one requested by `Retry-After`
+    pub fn max_delay(&self) -> Duration {
+        self.max_delay
+    }
+
+    /// Fraction of the delay that is randomized, between 0 and 1
+    pub fn jitter(&self) -> f64 {
+        self.jitter
+    }
+
+    /// Create a builder for [`RetryPolicy`]
+    pub fn builder() -> RetryPolicyBuilder {
+        RetryPolicyBuilder::new()
+    }
+
+    /// Returns `true` if another attempt should be made after `attempt` attempts failed
+    pub(crate) fn should_retry(&self, attempt: u32) -> bool {
+        attempt < self.max_attempts
+    }
+
+    /// The delay before the next attempt after `attempt` attempts failed
+    pub(crate) fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        if let Some(retry_after) = retry_after {
+            return retry_after.min(self.max_delay);
+        }
+        let exponential = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+        exponential.mul_f64(1.0 - self.jitter * rand::random::<f64>())
+    }
+}
+
+/// Builder for [`RetryPolicy`]
+#[derive(Debug, Default)]
+pub struct RetryPolicyBuilder {
+    policy: RetryPolicy,
+}
+
+impl RetryPolicyBuilder {
+    /// Create a new builder with the default policy, which never retries
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Maximum number of attempts, including the first one. `0` is treated as `1`.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.policy.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Delay before the first retry, default to 500ms
+    pub fn base_delay(mut self, base_delay: Duration) -> Self {
+        self.policy.base_delay = base_delay;
+        self
+    }
+
+    /// Upper bound of the delay, including the one requested by `Retry-After`, default to 30s
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.policy.max_delay = max_delay;
+        self
+    }
+
+    /// Fraction of the delay that is randomized, default to 0.2. It is clamped between 0 and 1.
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.policy.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Build [`RetryPolicy`]
+    pub fn build(self) -> RetryPolicy {
+        self.policy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_is_capped() {
+        let policy = RetryPolicy::builder()
+            .max_attempts(10)
+            .base_delay(Duration::from_secs(1))
+            .max_delay(Duration::from_secs(5))
+            .jitter(0.0)
+            .build();
+        assert_eq!(policy.delay(1, None), Duration::from_secs(1));
+        assert_eq!(policy.delay(3, None), Duration::from_secs(4));
+        assert_eq!(policy.delay(4, None), Duration::from_secs(5));
+        assert_eq!(
+            policy.delay(1, Some(Duration::from_secs(2))),
+            Duration::from_secs(2)
+        );
+        assert_eq!(
+            policy.delay(1, Some(Duration::from_secs(3600))),
+            Duration::from_secs(5)
+        );
+    }
+}
@@ -7,10 +7,14 @@ use async_trait::async_trait;
 use log::debug;
 use strum::AsRefStr;
 
-use crate::{interpolate_ssml, SsmlError, TextOptions};
+use crate::{
+    interpolate_ssml, join_audio, AudioFormat, AudioJoinError, SsmlError, TextOptions, TextSplitter,
+};
 
 #[async_trait]
 pub trait UnifiedSynthesizer: Send {
+    /// The audio format of the synthesized audio.
+    fn audio_format(&self) -> AudioFormat;
     /// Synthesize the given SSML into audio([`Vec<u8>`]).
     async fn process_ssml(&mut self, ssml: &str) -> Result<Vec<u8>, UnifiedSynthesizerError>;
     /// This is a convenience method that interpolates the SSML for you.
@@ -23,6 +27,36 @@ pub trait UnifiedSynthesizer: Send {
         let ssml = interpolate_ssml(text, options)?;
         self.process_ssml(&ssml).await
     }
+    /// Split long text into chunks with the given [`TextSplitter`], synthesize every chunk
+    /// in a separate request and join the results into a single audio file.
+    ///
+    /// This works around the limits on the request size and audio length of the Azure TTS API.
+    async fn process_text_chunked(
+        &mut self,
+        text: &str,
+        options: &TextOptions<'_>,
+        splitter: &TextSplitter,
+    ) -> Result<Vec<u8>, UnifiedSynthesizerError> {
+        let chunks = splitter.split(text);
+        let mut clips = Vec::with_capacity(chunks.len());
+        for (i, chunk) in chunks.iter().enumerate() {
+            debug!("Synthesizing chunk {}/{}", i + 1, chunks.len());
+            clips.push(self.process_text(chunk, options).await?);
+        }
+        Ok(join_audio(self.audio_format(), &clips)?)
+    }
+    /// Synthesize every SSML document in a separate request and join the results into a single audio file.
+    async fn process_ssml_chunked(
+        &mut self,
+        ssml_chunks: &[String],
+    ) -> Result<Vec<u8>, UnifiedSynthesizerError> {
+        let mut clips = Vec::with_capacity(ssml_chunks.len());
+        for (i, ssml) in ssml_chunks.iter().enumerate() {
+            debug!("Synthesizing chunk {}/{}", i + 1, ssml_chunks.len());
+            clips.push(self.process_ssml(ssml).await?);
+        }
+        Ok(join_audio(self.audio_format(), &clips)?)
+    }
 }
 
 /// Errors that can occur when creating and using a [`UnifiedSynthesizer`].
@@ -43,6 +77,7 @@ impl Display for UnifiedSynthesizerError {
                 f,
                 "an invalid request is constructed or 400 status reported by the server"
             ),
+            AudioJoin => write!(f, "failed to join the audio of multiple chunks"),
             _ => write!(f, "{} error", self.kind.as_ref()),
         }
     }
@@ -78,6 +113,12 @@ pub enum UnifiedSynthesizerErrorKind {
     InvalidMessage,
     /// Errors that occur while processing SSML.
     Ssml,
+    /// Failed to join the audio of multiple chunks.
+    AudioJoin,
+    /// Failed to obtain credentials.
+    Credential,
+    /// The transport failed to handle the request for reasons other than the network.
+    Transport,
 }
 
 macro_rules! impl_from_for_unified_synthesizer_error {
@@ -94,6 +135,7 @@ macro_rules! impl_from_for_unified_synthesizer_error {
 }
 
 impl_from_for_unified_synthesizer_error!(SsmlError, Ssml);
+impl_from_for_unified_synthesizer_error!(AudioJoinError, AudioJoin);
 
 #[cfg(feature = "rest-synthesizer")]
 impl From<super::RestSynthesizerError> for UnifiedSynthesizerError {
@@ -111,6 +153,7 @@ impl From<super::RestSynthesizerError> for UnifiedSynthesizerError {
             },
             RestKind::Unauthorized
             | RestKind::TooManyRequests
+            | RestKind::ServiceUnavailable
             | RestKind::UnsupportedMediaType
             | RestKind::OtherHttp => Self {
                 kind: Http,
@@ -124,6 +167,14 @@ impl From<super::RestSynthesizerError> for UnifiedSynthesizerError {
                 kind: Ssml,
                 source: Some(value.into()),
             },
+            RestKind::Credential => Self {
+                kind: Credential,
+                source: Some(value.into()),
+            },
+            RestKind::Transport => Self {
+                kind: Transport,
+                source: Some(value.into()),
+            },
         }
     }
 }
@@ -158,13 +209,32 @@ impl From<super::WebsocketSynthesizerError> for UnifiedSynthesizerError {
                 kind: Ssml,
                 source: Some(value.into()),
             },
+            WsKind::Credential => Self {
+                kind: Credential,
+                source: Some(value.into()),
+            },
         }
     }
 }
 
+#[async_trait]
+impl<S: UnifiedSynthesizer + ?Sized> UnifiedSynthesizer for Box<S> {
+    fn audio_format(&self) -> AudioFormat {
+        (**self).audio_format()
+    }
+
+    async fn process_ssml(&mut self, ssml: &str) -> Result<Vec<u8>, UnifiedSynthesizerError> {
+        (**self).process_ssml(ssml).await
+    }
+}
+
 #[cfg(feature = "rest-synthesizer")]
 #[async_trait]
 impl UnifiedSynthesizer for super::RestSynthesizer {
+    fn audio_format(&self) -> AudioFormat {
+        self.audio_format()
+    }
+
     async fn process_ssml(&mut self, ssml: &str) -> Result<Vec<u8>, UnifiedSynthesizerError> {
         Ok(self.synthesize_ssml(ssml).await?)
     }
@@ -173,6 +243,22 @@ impl UnifiedSynthesizer for super::RestSynthesizer {
 #[cfg(feature = "websocket-synthesizer")]
 #[async_trait]
 impl UnifiedSynthesizer for super::WebsocketSynthesizer {
+    fn audio_format(&self) -> AudioFormat {
+        self.audio_format()
+    }
+
+    async fn process_ssml(&mut self, ssml: &str) -> Result<Vec<u8>, UnifiedSynthesizerError> {
+        Ok(self.synthesize_ssml(ssml).await?)
+    }
+}
+
+#[cfg(feature = "websocket-synthesizer")]
+#[async_trait]
+impl UnifiedSynthesizer for super::WebsocketSynthesizerPool {
+    fn audio_format(&self) -> AudioFormat {
+        self.audio_format()
+    }
+
     async fn process_ssml(&mut self, ssml: &str) -> Result<Vec<u8>, UnifiedSynthesizerError> {
         Ok(self.synthesize_ssml(ssml).await?)
     }
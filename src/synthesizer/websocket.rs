@@ -1,79 +1,238 @@
+use std::collections::VecDeque;
 use std::error::Error;
 use std::fmt::{self, Display, Formatter};
 
 use crate::errors::ConnectError;
 use crate::msg;
-use crate::net::WsStream;
+use crate::synthesizer::metadata::{
+    parse_metadata, MetadataOptions, SynthesisChunk, SynthesisOutput,
+};
+use crate::synthesizer::SynthesizerConfig;
+use crate::transport::{BoxWebsocketStream, TransportError};
 use crate::{interpolate_ssml, msg::WebSocketMessage, AudioFormat, TextOptions};
 use chrono::Utc;
-use futures_util::{SinkExt, StreamExt};
+use futures_util::{
+    pin_mut, stream::try_unfold, FutureExt, SinkExt, Stream, StreamExt, TryStreamExt,
+};
 use hyper::header::InvalidHeaderValue;
 use log::{debug, info, warn};
 
 use strum::AsRefStr;
-use tokio_tungstenite::tungstenite::protocol::Message;
+use tokio_tungstenite::tungstenite::protocol::{CloseFrame, Message};
 use uuid::Uuid;
 
 /// The main struct for interacting with the Azure Speech Service.
+///
+/// If the server closes the connection, e.g. after the idle timeout,
+/// the synthesizer transparently reconnects before the next request.
 pub struct WebsocketSynthesizer {
-    pub(super) audio_format: AudioFormat,
-    pub(super) stream: WsStream,
+    pub(super) config: SynthesizerConfig<'static>,
+    pub(super) stream: BoxWebsocketStream,
+    /// Whether the connection is known to be closed
+    pub(super) closed: bool,
 }
 
 impl WebsocketSynthesizer {
+    /// The audio format of the synthesized audio.
+    pub fn audio_format(&self) -> AudioFormat {
+        self.config.audio_format
+    }
+
+    /// Options that control which metadata events are emitted by the server.
+    pub fn metadata_options(&self) -> &MetadataOptions {
+        &self.config.metadata_options
+    }
+
+    /// Options that control which metadata events are emitted by the server.
+    pub fn metadata_options_mut(&mut self) -> &mut MetadataOptions {
+        &mut self.config.metadata_options
+    }
+
+    /// Close the current connection and connect to the server again.
+    ///
+    /// Failed attempts are retried according to the [`super::RetryPolicy`] of the config
+    /// this synthesizer is created from.
+    /// You don't need to call this method manually because
+    /// the synthesizer reconnects automatically when the connection is closed.
+    pub async fn reconnect(&mut self) -> Result<(), WebsocketSynthesizerError> {
+        if !self.closed {
+            // The old connection is abandoned anyway
+            let _ = self.stream.close().await;
+        }
+        self.closed = true;
+        let retry_policy = self.config.retry_policy;
+        let mut attempt = 1;
+        loop {
+            match self.config.open_websocket().await {
+                Ok(stream) => {
+                    info!("Successfully reconnected to the server");
+                    self.stream = stream;
+                    self.closed = false;
+                    return Ok(());
+                }
+                Err(e) if retry_policy.should_retry(attempt) => {
+                    let delay = retry_policy.delay(attempt, None);
+                    warn!(
+                        "Reconnection attempt {attempt}/{} failed: {e}, retrying in {delay:?}",
+                        retry_policy.max_attempts()
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Reconnect if the server has closed the connection.
+    async fn ensure_connected(&mut self) -> Result<(), WebsocketSynthesizerError> {
+        // The server might have closed the connection while we were idle.
+        // Check the messages that have already arrived without waiting for new ones.
+        while !self.closed {
+            match self.stream.next().now_or_never() {
+                None => break,
+                Some(Some(Ok(Message::Close(frame)))) => {
+                    debug!("The server closed the connection: {frame:?}");
+                    self.closed = true;
+                }
+                Some(None) => self.closed = true,
+                Some(Some(Err(e))) => {
+                    debug!("The connection is broken: {e}");
+                    self.closed = true;
+                }
+                Some(Some(Ok(msg))) => debug!("Discarding a message received while idle: {msg:?}"),
+            }
+        }
+        if self.closed {
+            info!("The connection is closed, reconnecting");
+            self.reconnect().await?;
+        }
+        Ok(())
+    }
+
     /// Synthesize the given SSML into audio([`Vec<u8>`]).
     pub async fn synthesize_ssml(
         &mut self,
         ssml: &str,
     ) -> Result<Vec<u8>, WebsocketSynthesizerError> {
-        let uuid = Uuid::new_v4();
-        let request_id = uuid.as_simple();
-        let now = Utc::now();
-        let synthesis_context = format!(
-            r#"{{"synthesis":{{"audio":{{"metadataOptions":{{"sentenceBoundaryEnabled":false,"wordBoundaryEnabled":false,"sessionEndEnabled":false}},"outputFormat":"{}"}}}}}}"#,
-            Into::<&str>::into(self.audio_format)
-        );
-        self.stream.send(Message::Text(format!(
-            "Path: synthesis.context\r\nX-RequestId: {request_id}\r\nX-Timestamp: {now:?}Content-Type: application/json\r\n\r\n{synthesis_context}", 
-            request_id = &request_id)),
-        ).await?;
-        info!("Before sending the SSML to the server");
-        self.stream.send(Message::Text(format!(
-            "Path: ssml\r\nX-RequestId: {request_id}\r\nX-Timestamp: {now:?}\r\nContent-Type: application/ssml+xml\r\n\r\n{ssml}"
-        ))).await?;
+        let stream = self.synthesize_ssml_stream(ssml).await?;
+        pin_mut!(stream);
         let mut buffer = Vec::new();
-        while let Some(raw_msg) = self.stream.next().await.transpose()? {
-            let msg = WebSocketMessage::try_from(&raw_msg)?;
-            match msg {
-                WebSocketMessage::TurnStart | WebSocketMessage::Response { body: _ } => continue,
-                WebSocketMessage::Audio { data } => {
-                    buffer.extend_from_slice(data);
-                }
-                WebSocketMessage::TurnEnd => {
-                    break;
-                }
-                WebSocketMessage::Close(frame) => {
-                    return Err(frame.map_or_else(
-                        || {
-                            WebsocketSynthesizerError::connection_closed(
-                                "Unknown".to_string(),
-                                "The server closed the connection without a reason".to_string(),
-                            )
-                        },
-                        |fr| {
-                            WebsocketSynthesizerError::connection_closed(
-                                fr.code.to_string(),
-                                fr.reason.to_string(),
-                            )
-                        },
-                    ));
-                }
-                msg => warn!("Received a message that is not handled: {:?}", msg),
-            }
+        while let Some(chunk) = stream.try_next().await? {
+            buffer.extend_from_slice(&chunk);
         }
         Ok(buffer)
     }
 
+    /// Synthesize the given SSML into audio and collect the metadata events emitted along with it.
+    ///
+    /// Use [`WebsocketSynthesizer::metadata_options_mut`] or [`super::SynthesizerConfig::metadata_options_mut`]
+    /// to choose which kinds of events you want to receive.
+    pub async fn synthesize_ssml_with_metadata(
+        &mut self,
+        ssml: &str,
+    ) -> Result<SynthesisOutput, WebsocketSynthesizerError> {
+        let stream = self.synthesize_ssml_stream_with_metadata(ssml).await?;
+        pin_mut!(stream);
+        let mut output = SynthesisOutput::default();
+        while let Some(chunk) = stream.try_next().await? {
+            match chunk {
+                SynthesisChunk::Audio(data) => output.audio.extend_from_slice(&data),
+                SynthesisChunk::Event(event) => output.events.push(event),
+            }
+        }
+        Ok(output)
+    }
+
+    /// Synthesize the given SSML and return a stream of audio chunks as they arrive from the server.
+    ///
+    /// The stream ends when the server finishes the synthesis.
+    /// Note that the stream should be consumed to the end before issuing the next request,
+    /// otherwise the remaining audio of this request would be mixed into the next one.
+    pub async fn synthesize_ssml_stream(
+        &mut self,
+        ssml: &str,
+    ) -> Result<
+        impl Stream<Item = Result<Vec<u8>, WebsocketSynthesizerError>> + '_,
+        WebsocketSynthesizerError,
+    > {
+        Ok(self
+            .synthesize_ssml_stream_with_metadata(ssml)
+            .await?
+            .try_filter_map(|chunk| async move {
+                Ok(match chunk {
+                    SynthesisChunk::Audio(data) => Some(data),
+                    _ => None,
+                })
+            }))
+    }
+
+    /// Synthesize the given SSML and return a stream of audio chunks and metadata events as they arrive from the server.
+    ///
+    /// The same caveats of [`WebsocketSynthesizer::synthesize_ssml_stream`] apply.
+    pub async fn synthesize_ssml_stream_with_metadata(
+        &mut self,
+        ssml: &str,
+    ) -> Result<
+        impl Stream<Item = Result<SynthesisChunk, WebsocketSynthesizerError>> + '_,
+        WebsocketSynthesizerError,
+    > {
+        self.ensure_connected().await?;
+        if let Err(e) = self.send_request(ssml).await {
+            warn!("Failed to send the request: {e}, reconnecting");
+            self.reconnect().await?;
+            self.send_request(ssml).await?;
+        }
+        Ok(try_unfold(
+            (&mut self.stream, &mut self.closed, VecDeque::new()),
+            |(stream, closed, mut pending_events)| async move {
+                if let Some(event) = pending_events.pop_front() {
+                    return Ok(Some((
+                        SynthesisChunk::Event(event),
+                        (stream, closed, pending_events),
+                    )));
+                }
+                while let Some(raw_msg) = stream.next().await {
+                    let raw_msg = raw_msg.map_err(|e| {
+                        *closed = true;
+                        WebsocketSynthesizerError::from(e)
+                    })?;
+                    let msg = WebSocketMessage::try_from(&raw_msg)?;
+                    match msg {
+                        WebSocketMessage::TurnStart | WebSocketMessage::Response { body: _ } => {
+                            continue
+                        }
+                        WebSocketMessage::Audio { data } => {
+                            return Ok(Some((
+                                SynthesisChunk::Audio(data.to_vec()),
+                                (stream, closed, pending_events),
+                            )));
+                        }
+                        WebSocketMessage::AudioMetadata { body } => {
+                            pending_events.extend(parse_metadata(body)?);
+                            if let Some(event) = pending_events.pop_front() {
+                                return Ok(Some((
+                                    SynthesisChunk::Event(event),
+                                    (stream, closed, pending_events),
+                                )));
+                            }
+                        }
+                        WebSocketMessage::TurnEnd => {
+                            return Ok(None);
+                        }
+                        WebSocketMessage::Close(frame) => {
+                            *closed = true;
+                            return Err(WebsocketSynthesizerError::from_close_frame(frame));
+                        }
+                        msg => warn!("Received a message that is not handled: {:?}", msg),
+                    }
+                }
+                *closed = true;
+                Ok(None)
+            },
+        ))
+    }
+
     /// Synthesize the given text into audio([`Vec<u8>`]).
     /// This is a convenience method that interpolates the SSML for you.
     pub async fn synthesize_text(
@@ -85,6 +244,53 @@ impl WebsocketSynthesizer {
         let ssml = interpolate_ssml(text, options)?;
         self.synthesize_ssml(&ssml).await
     }
+
+    /// Synthesize the given text and return a stream of audio chunks as they arrive from the server.
+    /// This is a convenience method that interpolates the SSML for you.
+    pub async fn synthesize_text_stream(
+        &mut self,
+        text: impl AsRef<str>,
+        options: &TextOptions<'_>,
+    ) -> Result<
+        impl Stream<Item = Result<Vec<u8>, WebsocketSynthesizerError>> + '_,
+        WebsocketSynthesizerError,
+    > {
+        debug!("Synthesizing text: {}", text.as_ref());
+        let ssml = interpolate_ssml(text, options)?;
+        self.synthesize_ssml_stream(&ssml).await
+    }
+
+    /// Synthesize the given text into audio and collect the metadata events emitted along with it.
+    /// This is a convenience method that interpolates the SSML for you.
+    pub async fn synthesize_text_with_metadata(
+        &mut self,
+        text: impl AsRef<str>,
+        options: &TextOptions<'_>,
+    ) -> Result<SynthesisOutput, WebsocketSynthesizerError> {
+        debug!("Synthesizing text: {}", text.as_ref());
+        let ssml = interpolate_ssml(text, options)?;
+        self.synthesize_ssml_with_metadata(&ssml).await
+    }
+
+    async fn send_request(&mut self, ssml: &str) -> Result<(), WebsocketSynthesizerError> {
+        let uuid = Uuid::new_v4();
+        let request_id = uuid.as_simple();
+        let now = Utc::now();
+        let synthesis_context = format!(
+            r#"{{"synthesis":{{"audio":{{"metadataOptions":{},"outputFormat":"{}"}}}}}}"#,
+            self.config.metadata_options.to_json(),
+            Into::<&str>::into(self.config.audio_format)
+        );
+        self.stream.send(Message::Text(format!(
+            "Path: synthesis.context\r\nX-RequestId: {request_id}\r\nX-Timestamp: {now:?}Content-Type: application/json\r\n\r\n{synthesis_context}", 
+            request_id = &request_id)),
+        ).await?;
+        info!("Before sending the SSML to the server");
+        self.stream.send(Message::Text(format!(
+            "Path: ssml\r\nX-RequestId: {request_id}\r\nX-Timestamp: {now:?}\r\nContent-Type: application/ssml+xml\r\n\r\n{ssml}"
+        ))).await?;
+        Ok(())
+    }
 }
 
 /// Errors that can occur when creating and using a [`WebsocketSynthesizer`].
@@ -102,6 +308,18 @@ impl WebsocketSynthesizerError {
             source: None,
         }
     }
+
+    fn from_close_frame(frame: Option<&CloseFrame>) -> Self {
+        frame.map_or_else(
+            || {
+                Self::connection_closed(
+                    "Unknown".to_string(),
+                    "The server closed the connection without a reason".to_string(),
+                )
+            },
+            |fr| Self::connection_closed(fr.code.to_string(), fr.reason.to_string()),
+        )
+    }
 }
 
 impl Display for WebsocketSynthesizerError {
@@ -151,6 +369,8 @@ pub enum WebsocketSynthesizerErrorKind {
     InvalidMessage,
     /// Errors that occur when interpolating SSML.
     Ssml,
+    /// Failed to obtain credentials.
+    Credential,
 }
 
 macro_rules! impl_from_for_ws_synthesizer_error {
@@ -169,8 +389,11 @@ macro_rules! impl_from_for_ws_synthesizer_error {
 impl_from_for_ws_synthesizer_error!(InvalidHeaderValue, InvalidRequest);
 impl_from_for_ws_synthesizer_error!(url::ParseError, InvalidRequest);
 impl_from_for_ws_synthesizer_error!(ConnectError, Connect);
+impl_from_for_ws_synthesizer_error!(TransportError, Connect);
 impl_from_for_ws_synthesizer_error!(tokio_tungstenite::tungstenite::Error, Websocket);
 impl_from_for_ws_synthesizer_error!(crate::ssml::SsmlError, Ssml);
+impl_from_for_ws_synthesizer_error!(serde_json::Error, InvalidMessage);
+impl_from_for_ws_synthesizer_error!(crate::CredentialError, Credential);
 
 impl From<msg::ParseError> for WebsocketSynthesizerError {
     fn from(e: msg::ParseError) -> Self {
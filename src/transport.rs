@@ -0,0 +1,246 @@
+//! The transport layer underneath the synthesizers.
+//!
+//! By default, [`crate::synthesizer::RestSynthesizer`] and [`crate::synthesizer::WebsocketSynthesizer`]
+//! talk to the service over the network with a [`NetworkTransport`].
+//! Set [`crate::synthesizer::SynthesizerConfig::http_transport_mut`] or
+//! [`crate::synthesizer::SynthesizerConfig::websocket_transport_mut`] to use another transport,
+//! e.g. the `Recorder` and `Replayer` that are available with the `record-replay` feature.
+
+use std::{
+    error::Error,
+    fmt::{self, Debug, Display, Formatter},
+};
+
+use async_trait::async_trait;
+use strum::AsRefStr;
+
+#[cfg(feature = "record-replay")]
+mod record;
+
+#[cfg(feature = "record-replay")]
+pub use record::*;
+
+#[cfg(feature = "rest-synthesizer")]
+use bytes::Bytes;
+#[cfg(feature = "websocket-synthesizer")]
+use futures_util::{Sink, Stream};
+#[cfg(feature = "websocket-synthesizer")]
+use tokio_tungstenite::tungstenite::{self, handshake::client::Request, Message};
+
+#[cfg(feature = "rest-synthesizer")]
+use crate::utils::ClientBuilderExt;
+
+/// A transport that sends the HTTP requests of [`crate::synthesizer::RestSynthesizer`].
+#[cfg(feature = "rest-synthesizer")]
+#[async_trait]
+pub trait HttpTransport: Debug + Send + Sync {
+    /// Send the request and return the response, regardless of its status code.
+    async fn send(
+        &self,
+        request: hyper::Request<Bytes>,
+    ) -> Result<hyper::Response<Bytes>, TransportError>;
+}
+
+/// A websocket connection returned by a [`WebsocketTransport`].
+///
+/// It is implemented for every type that is both a [`Stream`] of and a [`Sink`] for websocket messages.
+#[cfg(feature = "websocket-synthesizer")]
+pub trait WebsocketStream:
+    Stream<Item = Result<Message, tungstenite::Error>>
+    + Sink<Message, Error = tungstenite::Error>
+    + Send
+    + Unpin
+{
+}
+
+#[cfg(feature = "websocket-synthesizer")]
+impl<T> WebsocketStream for T where
+    T: Stream<Item = Result<Message, tungstenite::Error>>
+        + Sink<Message, Error = tungstenite::Error>
+        + Send
+        + Unpin
+{
+}
+
+/// A boxed [`WebsocketStream`]
+#[cfg(feature = "websocket-synthesizer")]
+pub type BoxWebsocketStream = Box<dyn WebsocketStream>;
+
+/// A transport that opens the websocket connections of [`crate::synthesizer::WebsocketSynthesizer`].
+#[cfg(feature = "websocket-synthesizer")]
+#[async_trait]
+pub trait WebsocketTransport: Debug + Send + Sync {
+    /// Perform the websocket handshake with the given request and return the connection.
+    async fn connect(&self, request: Request) -> Result<BoxWebsocketStream, TransportError>;
+}
+
+/// The default transport, which talks to the service over the network, optionally through a proxy.
+#[derive(Debug, Clone)]
+pub struct NetworkTransport {
+    #[cfg(feature = "rest-synthesizer")]
+    client: reqwest::Client,
+    #[cfg_attr(not(feature = "websocket-synthesizer"), allow(unused))]
+    proxy: Option<reqwest::Url>,
+}
+
+impl NetworkTransport {
+    /// Create a new [`NetworkTransport`] that connects through the given proxy.
+    ///
+    /// `socks5://`, `http://` and `https://` proxies are supported.
+    pub fn new(proxy: Option<&str>) -> Result<Self, TransportError> {
+        let proxy = proxy
+            .map(|proxy| {
+                reqwest::Url::parse(proxy).map_err(|e| TransportError {
+                    kind: TransportErrorKind::BadUrl(proxy.to_string()),
+                    source: Some(e.into()),
+                })
+            })
+            .transpose()?;
+        if let Some(scheme) = proxy.as_ref().map(|proxy| proxy.scheme()) {
+            if !matches!(scheme, "socks5" | "http" | "https") {
+                return Err(TransportError {
+                    kind: TransportErrorKind::UnsupportedScheme(scheme.to_string()),
+                    source: None,
+                });
+            }
+        }
+        Ok(Self {
+            #[cfg(feature = "rest-synthesizer")]
+            client: reqwest::Client::builder()
+                .user_agent("aspeak")
+                .optional_proxy(
+                    proxy
+                        .as_ref()
+                        .map(|proxy| reqwest::Proxy::all(proxy.as_str()))
+                        .transpose()
+                        .map_err(|e| TransportError {
+                            kind: TransportErrorKind::Connect,
+                            source: Some(e.into()),
+                        })?,
+                )
+                .build()
+                .map_err(|e| TransportError {
+                    kind: TransportErrorKind::Connect,
+                    source: Some(e.into()),
+                })?,
+            proxy,
+        })
+    }
+}
+
+#[cfg(feature = "rest-synthesizer")]
+#[async_trait]
+impl HttpTransport for NetworkTransport {
+    async fn send(
+        &self,
+        request: hyper::Request<Bytes>,
+    ) -> Result<hyper::Response<Bytes>, TransportError> {
+        let request = reqwest::Request::try_from(request).map_err(|e| TransportError {
+            kind: TransportErrorKind::RequestConstruction,
+            source: Some(e.into()),
+        })?;
+        let response = self
+            .client
+            .execute(request)
+            .await
+            .map_err(|e| TransportError {
+                kind: TransportErrorKind::Connect,
+                source: Some(e.into()),
+            })?;
+        let mut builder = hyper::Response::builder().status(response.status());
+        if let Some(headers) = builder.headers_mut() {
+            *headers = response.headers().clone();
+        }
+        let body = response.bytes().await.map_err(|e| TransportError {
+            kind: TransportErrorKind::Connection,
+            source: Some(e.into()),
+        })?;
+        Ok(builder.body(body).unwrap())
+    }
+}
+
+#[cfg(feature = "websocket-synthesizer")]
+#[async_trait]
+impl WebsocketTransport for NetworkTransport {
+    async fn connect(&self, request: Request) -> Result<BoxWebsocketStream, TransportError> {
+        use crate::net;
+
+        let stream = match self.proxy.as_ref() {
+            Some(proxy) if proxy.scheme() == "socks5" => {
+                net::connect_via_socks5_proxy(request, proxy).await?
+            }
+            Some(proxy) => net::connect_via_http_proxy(request, proxy).await?,
+            None => net::connect_directly(request).await?,
+        };
+        Ok(Box::new(stream))
+    }
+}
+
+/// Errors that can occur in a transport.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct TransportError {
+    pub kind: TransportErrorKind,
+    pub(crate) source: Option<anyhow::Error>,
+}
+
+impl Display for TransportError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        use TransportErrorKind::*;
+        write!(f, "transport error: ")?;
+        match &self.kind {
+            BadUrl(url) => write!(f, "bad url: {url}"),
+            UnsupportedScheme(scheme) => write!(f, "unsupported proxy scheme: {scheme}"),
+            NoRecording => write!(f, "no recorded exchange is left to replay"),
+            Mismatch => write!(f, "the request does not match the recorded exchange"),
+            _ => write!(f, "{} error", self.kind.as_ref()),
+        }
+    }
+}
+
+impl Error for TransportError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_ref().map(|e| e.as_ref() as _)
+    }
+}
+
+#[cfg(feature = "python")]
+impl From<TransportError> for pyo3::PyErr {
+    fn from(value: TransportError) -> Self {
+        pyo3::exceptions::PyOSError::new_err(format!("{:?}", color_eyre::Report::from(value)))
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, AsRefStr)]
+#[non_exhaustive]
+#[strum(serialize_all = "title_case")]
+pub enum TransportErrorKind {
+    /// The url of the proxy is invalid.
+    BadUrl(String),
+    /// The scheme of the proxy is not supported.
+    UnsupportedScheme(String),
+    /// The request can't be sent by this transport.
+    RequestConstruction,
+    /// Failed to connect to the endpoint.
+    Connect,
+    /// Connection errors.
+    Connection,
+    /// All recorded exchanges have been replayed.
+    NoRecording,
+    /// The request does not match the recorded exchange.
+    Mismatch,
+    /// Failed to read or write a fixture file.
+    Io,
+    /// The fixture file is invalid.
+    InvalidFixture,
+}
+
+#[cfg(feature = "websocket-synthesizer")]
+impl From<crate::errors::ConnectError> for TransportError {
+    fn from(e: crate::errors::ConnectError) -> Self {
+        Self {
+            kind: TransportErrorKind::Connect,
+            source: Some(e.into()),
+        }
+    }
+}
@@ -0,0 +1,609 @@
+//! Recording and replaying the exchanges with the service.
+//!
+//! Wrap a [`NetworkTransport`] in a [`Recorder`] to record the real exchanges into a [`Fixture`],
+//! save it to a file and replay it later with a [`Replayer`], without any network:
+//!
+//! ```no_run
+//! use std::sync::Arc;
+//! use aspeak::{
+//!     synthesizer::SynthesizerConfig,
+//!     transport::{NetworkTransport, Recorder, Replayer},
+//!     get_rest_endpoint_by_region, AudioFormat, AuthOptionsBuilder,
+//! };
+//!
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! let ssml = r#"<speak version="1.0" xmlns="http://www.w3.org/2001/10/synthesis" xml:lang="en-US"><voice name="en-US-JennyNeural">Hello, world!</voice></speak>"#;
+//! let auth = AuthOptionsBuilder::new(get_rest_endpoint_by_region("eastus"))
+//!     .key("YOUR_AZURE_SUBSCRIPTION_KEY")
+//!     .build();
+//! let mut config = SynthesizerConfig::new(auth, AudioFormat::Riff16Khz16BitMonoPcm);
+//!
+//! // Record once
+//! let recorder = Arc::new(Recorder::new(NetworkTransport::new(None)?));
+//! *config.http_transport_mut() = Some(recorder.clone());
+//! let recorded = config.rest_synthesizer()?.synthesize_ssml(ssml).await?;
+//! recorder.save("tests/fixtures/hello.json")?;
+//!
+//! // Replay in tests
+//! *config.http_transport_mut() = Some(Arc::new(Replayer::load("tests/fixtures/hello.json")?));
+//! let replayed = config.rest_synthesizer()?.synthesize_ssml(ssml).await?;
+//! assert_eq!(recorded, replayed);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Credentials are never recorded:
+//! request headers are omitted and the `Authorization` query parameter is stripped from urls.
+//! Exchanges are replayed in the order they are recorded.
+
+use std::{
+    collections::VecDeque,
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use super::{NetworkTransport, TransportError, TransportErrorKind};
+
+#[cfg(feature = "rest-synthesizer")]
+use super::HttpTransport;
+#[cfg(feature = "rest-synthesizer")]
+use bytes::Bytes;
+
+#[cfg(feature = "websocket-synthesizer")]
+use super::{BoxWebsocketStream, WebsocketTransport};
+#[cfg(feature = "websocket-synthesizer")]
+use futures_util::{Sink, Stream};
+#[cfg(feature = "websocket-synthesizer")]
+use std::{
+    borrow::Cow,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+#[cfg(feature = "websocket-synthesizer")]
+use tokio_tungstenite::tungstenite::{
+    self,
+    handshake::client::Request,
+    protocol::{frame::coding::CloseCode, CloseFrame},
+    Message,
+};
+
+/// Recorded exchanges with the service, which can be saved to and loaded from a JSON file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct Fixture {
+    /// HTTP exchanges, in the order they are made
+    #[serde(default)]
+    pub http: Vec<HttpExchange>,
+    /// Websocket connections, in the order they are opened
+    #[serde(default)]
+    pub websocket: Vec<WebsocketSession>,
+}
+
+impl Fixture {
+    /// Load a fixture from a JSON file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, TransportError> {
+        let content = fs::read_to_string(path).map_err(|e| TransportError {
+            kind: TransportErrorKind::Io,
+            source: Some(e.into()),
+        })?;
+        serde_json::from_str(&content).map_err(|e| TransportError {
+            kind: TransportErrorKind::InvalidFixture,
+            source: Some(e.into()),
+        })
+    }
+
+    /// Save the fixture to a JSON file
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), TransportError> {
+        let content = serde_json::to_string_pretty(self).map_err(|e| TransportError {
+            kind: TransportErrorKind::InvalidFixture,
+            source: Some(e.into()),
+        })?;
+        fs::write(path, content).map_err(|e| TransportError {
+            kind: TransportErrorKind::Io,
+            source: Some(e.into()),
+        })
+    }
+}
+
+/// A recorded HTTP request and its response
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct HttpExchange {
+    pub method: String,
+    /// The url of the request, without credentials
+    pub url: String,
+    pub request_body: String,
+    pub status: u16,
+    pub response_headers: Vec<(String, String)>,
+    #[serde(with = "base64_bytes")]
+    pub response_body: Vec<u8>,
+}
+
+/// The frames exchanged over a recorded websocket connection
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct WebsocketSession {
+    /// The url of the handshake request, without credentials
+    pub url: String,
+    pub frames: Vec<RecordedFrame>,
+}
+
+/// A frame of a recorded websocket connection
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "direction", rename_all = "snake_case")]
+pub enum RecordedFrame {
+    /// A message sent by the client
+    Sent { message: RecordedMessage },
+    /// A message received from the server
+    Received { message: RecordedMessage },
+    /// The server ended the connection
+    End,
+}
+
+/// A recorded websocket message
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RecordedMessage {
+    Text {
+        text: String,
+    },
+    /// A binary message. The text header of the message is stored separately to keep fixtures readable.
+    /// It is `None` if the message doesn't start with a header.
+    Binary {
+        header: Option<String>,
+        #[serde(with = "base64_bytes")]
+        data: Vec<u8>,
+    },
+    Ping {
+        #[serde(with = "base64_bytes")]
+        data: Vec<u8>,
+    },
+    Pong {
+        #[serde(with = "base64_bytes")]
+        data: Vec<u8>,
+    },
+    Close {
+        code: Option<u16>,
+        reason: String,
+    },
+}
+
+#[cfg(feature = "websocket-synthesizer")]
+impl RecordedMessage {
+    /// The value of the `Path` header of a text message
+    fn path(&self) -> Option<&str> {
+        match self {
+            RecordedMessage::Text { text } => text
+                .split("\r\n\r\n")
+                .next()?
+                .split("\r\n")
+                .find_map(|header| header.strip_prefix("Path:"))
+                .map(str::trim),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "websocket-synthesizer")]
+impl From<&Message> for RecordedMessage {
+    fn from(message: &Message) -> Self {
+        match message {
+            Message::Text(text) => RecordedMessage::Text { text: text.clone() },
+            Message::Binary(data) => {
+                let header = data
+                    .get(..2)
+                    .map(|len| u16::from_be_bytes([len[0], len[1]]) as usize)
+                    .and_then(|len| data.get(2..2 + len))
+                    .and_then(|header| std::str::from_utf8(header).ok());
+                match header {
+                    Some(header) => RecordedMessage::Binary {
+                        header: Some(header.to_string()),
+                        data: data[2 + header.len()..].to_vec(),
+                    },
+                    None => RecordedMessage::Binary {
+                        header: None,
+                        data: data.clone(),
+                    },
+                }
+            }
+            Message::Ping(data) => RecordedMessage::Ping { data: data.clone() },
+            Message::Pong(data) => RecordedMessage::Pong { data: data.clone() },
+            Message::Close(frame) => RecordedMessage::Close {
+                code: frame.as_ref().map(|frame| frame.code.into()),
+                reason: frame
+                    .as_ref()
+                    .map(|frame| frame.reason.to_string())
+                    .unwrap_or_default(),
+            },
+            Message::Frame(frame) => RecordedMessage::Binary {
+                header: None,
+                data: frame.payload().clone(),
+            },
+        }
+    }
+}
+
+#[cfg(feature = "websocket-synthesizer")]
+impl From<RecordedMessage> for Message {
+    fn from(message: RecordedMessage) -> Self {
+        match message {
+            RecordedMessage::Text { text } => Message::Text(text),
+            RecordedMessage::Binary {
+                header: Some(header),
+                data,
+            } => {
+                let mut buffer = Vec::with_capacity(2 + header.len() + data.len());
+                buffer.extend_from_slice(&(header.len() as u16).to_be_bytes());
+                buffer.extend_from_slice(header.as_bytes());
+                buffer.extend_from_slice(&data);
+                Message::Binary(buffer)
+            }
+            RecordedMessage::Binary { header: None, data } => Message::Binary(data),
+            RecordedMessage::Ping { data } => Message::Ping(data),
+            RecordedMessage::Pong { data } => Message::Pong(data),
+            RecordedMessage::Close { code, reason } => {
+                Message::Close(code.map(|code| CloseFrame {
+                    code: CloseCode::from(code),
+                    reason: Cow::Owned(reason),
+                }))
+            }
+        }
+    }
+}
+
+/// Remove credentials from the query of the url
+fn redact_url(url: &str) -> String {
+    let Ok(mut url) = url::Url::parse(url) else {
+        return url.to_string();
+    };
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(name, _)| name != "Authorization")
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect();
+    if pairs.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+    url.to_string()
+}
+
+/// A transport that records the exchanges made through the inner transport into a [`Fixture`].
+#[derive(Debug)]
+pub struct Recorder<T = NetworkTransport> {
+    inner: T,
+    fixture: Arc<Mutex<Fixture>>,
+}
+
+impl<T> Recorder<T> {
+    /// Record the exchanges made through `inner`
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            fixture: Default::default(),
+        }
+    }
+
+    /// The exchanges recorded so far
+    pub fn fixture(&self) -> Fixture {
+        self.fixture.lock().unwrap().clone()
+    }
+
+    /// Save the exchanges recorded so far to a JSON file
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), TransportError> {
+        self.fixture().save(path)
+    }
+}
+
+#[cfg(feature = "rest-synthesizer")]
+#[async_trait]
+impl<T: HttpTransport> HttpTransport for Recorder<T> {
+    async fn send(
+        &self,
+        request: hyper::Request<Bytes>,
+    ) -> Result<hyper::Response<Bytes>, TransportError> {
+        let method = request.method().to_string();
+        let url = redact_url(&request.uri().to_string());
+        let request_body = String::from_utf8_lossy(request.body()).into_owned();
+        let response = self.inner.send(request).await?;
+        debug!("Recording HTTP exchange with {url}");
+        self.fixture.lock().unwrap().http.push(HttpExchange {
+            method,
+            url,
+            request_body,
+            status: response.status().as_u16(),
+            response_headers: response
+                .headers()
+                .iter()
+                .map(|(name, value)| {
+                    (
+                        name.to_string(),
+                        String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                    )
+                })
+                .collect(),
+            response_body: response.body().to_vec(),
+        });
+        Ok(response)
+    }
+}
+
+#[cfg(feature = "websocket-synthesizer")]
+#[async_trait]
+impl<T: WebsocketTransport> WebsocketTransport for Recorder<T> {
+    async fn connect(&self, request: Request) -> Result<BoxWebsocketStream, TransportError> {
+        let url = redact_url(&request.uri().to_string());
+        let inner = self.inner.connect(request).await?;
+        debug!("Recording websocket connection to {url}");
+        let session = {
+            let mut fixture = self.fixture.lock().unwrap();
+            fixture.websocket.push(WebsocketSession {
+                url,
+                frames: Vec::new(),
+            });
+            fixture.websocket.len() - 1
+        };
+        Ok(Box::new(RecordingStream {
+            inner,
+            session,
+            fixture: self.fixture.clone(),
+        }))
+    }
+}
+
+#[cfg(feature = "websocket-synthesizer")]
+struct RecordingStream {
+    inner: BoxWebsocketStream,
+    session: usize,
+    fixture: Arc<Mutex<Fixture>>,
+}
+
+#[cfg(feature = "websocket-synthesizer")]
+impl RecordingStream {
+    fn record(&self, frame: RecordedFrame) {
+        self.fixture.lock().unwrap().websocket[self.session]
+            .frames
+            .push(frame);
+    }
+}
+
+#[cfg(feature = "websocket-synthesizer")]
+impl Stream for RecordingStream {
+    type Item = Result<Message, tungstenite::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = Pin::new(&mut self.inner).poll_next(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(message))) => self.record(RecordedFrame::Received {
+                message: message.into(),
+            }),
+            Poll::Ready(None) => self.record(RecordedFrame::End),
+            _ => {}
+        }
+        poll
+    }
+}
+
+#[cfg(feature = "websocket-synthesizer")]
+impl Sink<Message> for RecordingStream {
+    type Error = tungstenite::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Message) -> Result<(), Self::Error> {
+        self.record(RecordedFrame::Sent {
+            message: (&item).into(),
+        });
+        Pin::new(&mut self.inner).start_send(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+/// A transport that replays the exchanges of a [`Fixture`] without any network.
+///
+/// HTTP requests must match the method and path of the recorded requests.
+/// Sent websocket messages must match the `Path` header of the recorded messages,
+/// and each received message is replayed once the messages sent before it have been sent.
+#[derive(Debug)]
+pub struct Replayer {
+    #[cfg(feature = "rest-synthesizer")]
+    http: Mutex<VecDeque<HttpExchange>>,
+    #[cfg(feature = "websocket-synthesizer")]
+    websocket: Mutex<VecDeque<WebsocketSession>>,
+}
+
+impl Replayer {
+    /// Replay the exchanges of the fixture
+    pub fn new(fixture: Fixture) -> Self {
+        Self {
+            #[cfg(feature = "rest-synthesizer")]
+            http: Mutex::new(fixture.http.into()),
+            #[cfg(feature = "websocket-synthesizer")]
+            websocket: Mutex::new(fixture.websocket.into()),
+        }
+    }
+
+    /// Replay the exchanges of the fixture saved in a JSON file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, TransportError> {
+        Ok(Self::new(Fixture::load(path)?))
+    }
+}
+
+#[cfg(feature = "rest-synthesizer")]
+#[async_trait]
+impl HttpTransport for Replayer {
+    async fn send(
+        &self,
+        request: hyper::Request<Bytes>,
+    ) -> Result<hyper::Response<Bytes>, TransportError> {
+        let exchange = self
+            .http
+            .lock()
+            .unwrap()
+            .pop_front()
+            .ok_or(TransportError {
+                kind: TransportErrorKind::NoRecording,
+                source: None,
+            })?;
+        let recorded_path = exchange
+            .url
+            .parse::<hyper::Uri>()
+            .ok()
+            .map(|uri| uri.path().to_string());
+        if exchange.method != request.method().as_str()
+            || recorded_path.as_deref() != Some(request.uri().path())
+        {
+            return Err(TransportError {
+                kind: TransportErrorKind::Mismatch,
+                source: Some(anyhow::anyhow!(
+                    "expected {} {}, got {} {}",
+                    exchange.method,
+                    exchange.url,
+                    request.method(),
+                    request.uri()
+                )),
+            });
+        }
+        debug!("Replaying HTTP exchange with {}", exchange.url);
+        let mut response = hyper::Response::builder().status(exchange.status);
+        for (name, value) in &exchange.response_headers {
+            response = response.header(name, value);
+        }
+        response
+            .body(exchange.response_body.into())
+            .map_err(|e| TransportError {
+                kind: TransportErrorKind::InvalidFixture,
+                source: Some(e.into()),
+            })
+    }
+}
+
+#[cfg(feature = "websocket-synthesizer")]
+#[async_trait]
+impl WebsocketTransport for Replayer {
+    async fn connect(&self, request: Request) -> Result<BoxWebsocketStream, TransportError> {
+        let session = self
+            .websocket
+            .lock()
+            .unwrap()
+            .pop_front()
+            .ok_or(TransportError {
+                kind: TransportErrorKind::NoRecording,
+                source: None,
+            })?;
+        debug!(
+            "Replaying websocket connection to {} for {}",
+            session.url,
+            redact_url(&request.uri().to_string())
+        );
+        Ok(Box::new(ReplayStream {
+            frames: session.frames.into(),
+            waker: None,
+        }))
+    }
+}
+
+#[cfg(feature = "websocket-synthesizer")]
+struct ReplayStream {
+    frames: VecDeque<RecordedFrame>,
+    /// Woken when the client sends the message that the next received message is waiting for
+    waker: Option<Waker>,
+}
+
+#[cfg(feature = "websocket-synthesizer")]
+fn replay_error(reason: String) -> tungstenite::Error {
+    tungstenite::Error::Io(std::io::Error::new(std::io::ErrorKind::Other, reason))
+}
+
+#[cfg(feature = "websocket-synthesizer")]
+impl Stream for ReplayStream {
+    type Item = Result<Message, tungstenite::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.frames.front() {
+            Some(RecordedFrame::Received { .. }) => match self.frames.pop_front() {
+                Some(RecordedFrame::Received { message }) => Poll::Ready(Some(Ok(message.into()))),
+                _ => unreachable!(),
+            },
+            Some(RecordedFrame::End) => Poll::Ready(None),
+            // Either the client has to send something first,
+            // or the connection stays idle like a real connection does.
+            Some(RecordedFrame::Sent { .. }) | None => {
+                self.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(feature = "websocket-synthesizer")]
+impl Sink<Message> for ReplayStream {
+    type Error = tungstenite::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Message) -> Result<(), Self::Error> {
+        let sent = RecordedMessage::from(&item);
+        match self.frames.front() {
+            Some(RecordedFrame::Sent { message }) if message.path() == sent.path() => {
+                self.frames.pop_front();
+                if let Some(waker) = self.waker.take() {
+                    waker.wake();
+                }
+                Ok(())
+            }
+            Some(RecordedFrame::Sent { message }) => Err(replay_error(format!(
+                "expected to send a message with path {:?}, got {:?}",
+                message.path(),
+                sent.path()
+            ))),
+            Some(RecordedFrame::End) => Err(tungstenite::Error::AlreadyClosed),
+            _ => Err(replay_error(format!(
+                "unexpected message with path {:?}, no more messages are recorded to be sent at this point",
+                sent.path()
+            ))),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+mod base64_bytes {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<u8>, D::Error> {
+        STANDARD
+            .decode(String::deserialize(deserializer)?)
+            .map_err(D::Error::custom)
+    }
+}
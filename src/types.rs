@@ -1,7 +1,7 @@
 use std::borrow::Cow;
 
 use serde::Deserialize;
-use strum::IntoStaticStr;
+use strum::{EnumString, IntoStaticStr};
 
 use crate::get_default_voice_by_locale;
 
@@ -13,7 +13,7 @@ use crate::get_default_voice_by_locale;
     derive(clap::ValueEnum),
     clap(rename_all = "verbatim")
 )]
-#[derive(Debug, Clone, Copy, IntoStaticStr, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, IntoStaticStr, EnumString, Deserialize)]
 pub enum Role {
     Girl,
     Boy,
@@ -65,6 +65,14 @@ impl<'a> RichSsmlOptions<'a> {
     pub fn builder() -> RichSsmlOptionsBuilder<'a> {
         RichSsmlOptionsBuilder::new()
     }
+    /// Convert into a [`RichSsmlOptions`] that owns all its data
+    pub fn into_owned(self) -> RichSsmlOptions<'static> {
+        RichSsmlOptions {
+            style: self.style.map(|s| Cow::Owned(s.into_owned())),
+            role: self.role,
+            style_degree: self.style_degree,
+        }
+    }
 }
 
 /// Builder for [`RichSsmlOptions`]
@@ -183,7 +191,7 @@ impl<'a> TextOptions<'a> {
     }
 
     /// Rich SSML options
-    pub fn rich_ssml_options(&self) -> &Option<RichSsmlOptions> {
+    pub fn rich_ssml_options(&self) -> &Option<RichSsmlOptions<'_>> {
         &self.rich_ssml_options
     }
 
@@ -196,6 +204,19 @@ impl<'a> TextOptions<'a> {
     pub fn builder() -> TextOptionsBuilder<'a> {
         TextOptionsBuilder::new()
     }
+
+    /// Convert into a [`TextOptions`] that owns all its data
+    pub fn into_owned(self) -> TextOptions<'static> {
+        fn owned(cow: Cow<'_, str>) -> Cow<'static, str> {
+            Cow::Owned(cow.into_owned())
+        }
+        TextOptions {
+            voice: owned(self.voice),
+            pitch: self.pitch.map(owned),
+            rate: self.rate.map(owned),
+            rich_ssml_options: self.rich_ssml_options.map(RichSsmlOptions::into_owned),
+        }
+    }
 }
 
 /// Builder for [`TextOptions`]
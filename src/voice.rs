@@ -6,7 +6,7 @@ use std::{
 
 use crate::{
     constants::{ORIGIN, TRIAL_VOICE_LIST_URL},
-    AudioFormat,
+    AudioFormat, CredentialProvider,
 };
 use colored::Colorize;
 use hyper::{header::InvalidHeaderValue, http::HeaderValue};
@@ -59,6 +59,8 @@ pub enum VoiceListAPIAuth<'a> {
     SubscriptionKey(&'a str),
     /// Auth token
     AuthToken(&'a str),
+    /// Credentials given by a [`CredentialProvider`]
+    Provider(&'a dyn CredentialProvider),
 }
 
 impl Voice {
@@ -107,10 +109,18 @@ impl Voice {
                     HeaderValue::from_str(token).map_err(request_error)?,
                 );
             }
+            Some(VoiceListAPIAuth::Provider(provider)) => {
+                for (name, value) in provider.headers().await.map_err(|e| VoiceListAPIError {
+                    kind: VoiceListAPIErrorKind::Credential,
+                    source: Some(e.into()),
+                })? {
+                    request = request.header(name, value);
+                }
+            }
             None => {}
         }
-        if additional_headers.is_some() {
-            request = request.headers(additional_headers.unwrap());
+        if let Some(additional_headers) = additional_headers {
+            request = request.headers(additional_headers);
         } else if Some(url.as_ref()) == TRIAL_VOICE_LIST_URL {
             // Trial endpoint
             request = request.header("Origin", HeaderValue::from_str(ORIGIN).unwrap());
@@ -311,4 +321,6 @@ pub enum VoiceListAPIErrorKind {
     Parse,
     /// A response was received from the voice list API, but it is not successful
     Response,
+    /// Failed to obtain credentials
+    Credential,
 }
@@ -0,0 +1,300 @@
+use std::time::Duration;
+
+use aspeak::{
+    mock::{MockFault, MockRequestKind, MockServer},
+    synthesizer::{
+        RestSynthesizerErrorKind, RetryPolicy, SynthesizerConfig, WebsocketSynthesizerErrorKind,
+    },
+    voice::{VoiceListAPIAuth, VoiceListAPIEndpoint, VoiceListAPIErrorKind},
+    AudioFormat, AuthOptionsBuilder, TokenIssuer, Voice,
+};
+
+const KEY: &str = "mock-key";
+const SSML: &str = r#"<speak version="1.0" xmlns="http://www.w3.org/2001/10/synthesis" xml:lang="en-US"><voice name="en-US-JennyNeural">Hello, world!</voice></speak>"#;
+
+fn config(endpoint: String) -> SynthesizerConfig<'static> {
+    let auth = AuthOptionsBuilder::new(endpoint).key(KEY).build();
+    SynthesizerConfig::new(auth, AudioFormat::Riff24Khz16BitMonoPcm)
+}
+
+async fn server() -> MockServer {
+    MockServer::builder()
+        .audio(vec![42; 10000])
+        .key(KEY)
+        .start()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn rest() {
+    let server = server().await;
+    let syn = config(server.rest_endpoint()).rest_synthesizer().unwrap();
+    assert_eq!(syn.synthesize_ssml(SSML).await.unwrap(), server.audio());
+    let requests = server.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].kind, MockRequestKind::Rest);
+    assert_eq!(requests[0].ssml.as_deref(), Some(SSML));
+    assert_eq!(
+        requests[0].output_format.as_deref(),
+        Some("riff-24khz-16bit-mono-pcm")
+    );
+}
+
+#[tokio::test]
+async fn websocket() {
+    let server = server().await;
+    let mut syn = config(server.websocket_endpoint())
+        .connect_websocket()
+        .await
+        .unwrap();
+    // The audio is sent in several chunks
+    assert_eq!(syn.synthesize_ssml(SSML).await.unwrap(), server.audio());
+    assert_eq!(syn.synthesize_ssml(SSML).await.unwrap(), server.audio());
+    let requests = server.requests();
+    assert_eq!(requests.len(), 2);
+    assert!(requests.iter().all(|r| r.kind == MockRequestKind::Websocket
+        && r.ssml.as_deref() == Some(SSML)
+        && r.output_format.as_deref() == Some("riff-24khz-16bit-mono-pcm")));
+}
+
+#[tokio::test]
+async fn voice_list() {
+    let server = server().await;
+    let endpoint = server.voice_list_endpoint();
+    let voices = Voice::request_available_voices(
+        VoiceListAPIEndpoint::Url(&endpoint),
+        Some(VoiceListAPIAuth::SubscriptionKey(KEY)),
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        voices.iter().map(Voice::short_name).collect::<Vec<_>>(),
+        ["en-US-JennyNeural", "zh-CN-XiaoxiaoNeural"]
+    );
+    assert_eq!(server.requests()[0].kind, MockRequestKind::VoiceList);
+}
+
+#[tokio::test]
+async fn issue_token() {
+    let server = server().await;
+    let issuer = TokenIssuer::new(server.issue_token_endpoint(), KEY);
+    let token = issuer.token().await.unwrap();
+    assert_eq!(server.requests()[0].kind, MockRequestKind::IssueToken);
+    assert!(TokenIssuer::new(server.issue_token_endpoint(), "wrong key")
+        .token()
+        .await
+        .is_err());
+
+    // Websocket clients can send the token in the `Authorization` query parameter
+    let auth = AuthOptionsBuilder::new(server.websocket_endpoint())
+        .token(format!("Bearer {token}"))
+        .build();
+    let mut syn = SynthesizerConfig::new(auth, AudioFormat::Riff24Khz16BitMonoPcm)
+        .connect_websocket()
+        .await
+        .unwrap();
+    assert_eq!(syn.synthesize_ssml(SSML).await.unwrap(), server.audio());
+}
+
+#[tokio::test]
+async fn token_issuer() {
+    let server = server().await;
+    let issued_tokens = || {
+        server
+            .requests()
+            .iter()
+            .filter(|r| r.kind == MockRequestKind::IssueToken)
+            .count()
+    };
+    let issuer = TokenIssuer::new(server.issue_token_endpoint(), KEY);
+    // Concurrent callers share a single token
+    let tokens = futures::future::join_all((0..8).map(|_| issuer.token())).await;
+    let token = tokens[0].as_ref().unwrap();
+    assert!(tokens.iter().all(|t| t.as_ref().ok() == Some(token)));
+    assert_eq!(issued_tokens(), 1);
+
+    let auth = AuthOptionsBuilder::new(server.rest_endpoint())
+        .token_issuer(issuer)
+        .build();
+    let syn = SynthesizerConfig::new(auth, AudioFormat::Riff24Khz16BitMonoPcm)
+        .rest_synthesizer()
+        .unwrap();
+    assert_eq!(syn.synthesize_ssml(SSML).await.unwrap(), server.audio());
+    assert_eq!(issued_tokens(), 1);
+
+    let auth = AuthOptionsBuilder::new(server.websocket_endpoint())
+        .token_issuer(TokenIssuer::new(server.issue_token_endpoint(), KEY))
+        .build();
+    let mut syn = SynthesizerConfig::new(auth, AudioFormat::Riff24Khz16BitMonoPcm)
+        .connect_websocket()
+        .await
+        .unwrap();
+    assert_eq!(syn.synthesize_ssml(SSML).await.unwrap(), server.audio());
+    assert_eq!(issued_tokens(), 2);
+    // The key is never sent to the synthesis endpoints
+    assert!(server
+        .requests()
+        .iter()
+        .filter(|r| r.kind != MockRequestKind::IssueToken)
+        .all(|r| !r.headers.contains_key("Ocp-Apim-Subscription-Key")));
+
+    // Tokens are issued through the proxy of the auth options
+    let auth = AuthOptionsBuilder::new(server.rest_endpoint())
+        .token_issuer(TokenIssuer::new(server.issue_token_endpoint(), KEY))
+        .proxy("http://127.0.0.1:1")
+        .build();
+    let error = SynthesizerConfig::new(auth, AudioFormat::Riff24Khz16BitMonoPcm)
+        .rest_synthesizer()
+        .unwrap()
+        .synthesize_ssml(SSML)
+        .await
+        .unwrap_err();
+    assert_eq!(error.kind, RestSynthesizerErrorKind::Credential);
+    assert_eq!(issued_tokens(), 2);
+}
+
+#[tokio::test]
+async fn wrong_key_is_rejected() {
+    let server = server().await;
+    let auth = AuthOptionsBuilder::new(server.rest_endpoint())
+        .key("wrong key")
+        .build();
+    let syn = SynthesizerConfig::new(auth, AudioFormat::Riff24Khz16BitMonoPcm)
+        .rest_synthesizer()
+        .unwrap();
+    assert_eq!(
+        syn.synthesize_ssml(SSML).await.unwrap_err().kind,
+        RestSynthesizerErrorKind::Unauthorized
+    );
+    assert!(server.requests().is_empty());
+}
+
+#[tokio::test]
+async fn unauthorized_fault() {
+    let server = server().await;
+    let syn = config(server.rest_endpoint()).rest_synthesizer().unwrap();
+    server.inject_fault(MockFault::Unauthorized);
+    assert_eq!(
+        syn.synthesize_ssml(SSML).await.unwrap_err().kind,
+        RestSynthesizerErrorKind::Unauthorized
+    );
+    // Faults are consumed by a single request
+    assert_eq!(syn.synthesize_ssml(SSML).await.unwrap(), server.audio());
+}
+
+#[tokio::test]
+async fn too_many_requests_fault() {
+    let server = server().await;
+    let syn = config(server.rest_endpoint()).rest_synthesizer().unwrap();
+    server.inject_fault(MockFault::TooManyRequests { retry_after: None });
+    assert_eq!(
+        syn.synthesize_ssml(SSML).await.unwrap_err().kind,
+        RestSynthesizerErrorKind::TooManyRequests
+    );
+
+    let mut config = config(server.rest_endpoint());
+    *config.retry_policy_mut() = RetryPolicy::builder()
+        .max_attempts(2)
+        .base_delay(Duration::from_secs(60))
+        .build();
+    let syn = config.rest_synthesizer().unwrap();
+    // `Retry-After` takes precedence over the base delay
+    server.inject_fault(MockFault::TooManyRequests {
+        retry_after: Some(0),
+    });
+    assert_eq!(syn.synthesize_ssml(SSML).await.unwrap(), server.audio());
+}
+
+#[tokio::test]
+async fn service_unavailable_fault() {
+    let server = server().await;
+    let syn = config(server.rest_endpoint()).rest_synthesizer().unwrap();
+    server.inject_fault(MockFault::ServiceUnavailable);
+    assert_eq!(
+        syn.synthesize_ssml(SSML).await.unwrap_err().kind,
+        RestSynthesizerErrorKind::ServiceUnavailable
+    );
+
+    let endpoint = server.voice_list_endpoint();
+    server.inject_fault(MockFault::ServiceUnavailable);
+    let error = Voice::request_available_voices(
+        VoiceListAPIEndpoint::Url(&endpoint),
+        Some(VoiceListAPIAuth::SubscriptionKey(KEY)),
+        None,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(error.kind, VoiceListAPIErrorKind::Response);
+}
+
+#[tokio::test]
+async fn http_fault_fails_websocket_handshake() {
+    let server = server().await;
+    server.inject_fault(MockFault::Unauthorized);
+    let Err(error) = config(server.websocket_endpoint())
+        .connect_websocket()
+        .await
+    else {
+        panic!("the handshake should fail");
+    };
+    assert_eq!(error.kind, WebsocketSynthesizerErrorKind::Connect);
+}
+
+#[tokio::test]
+async fn close_fault() {
+    let server = server().await;
+    let mut syn = config(server.websocket_endpoint())
+        .connect_websocket()
+        .await
+        .unwrap();
+    server.inject_fault(MockFault::Close {
+        code: 1011,
+        reason: "mock failure".to_string(),
+    });
+    // Close faults are not consumed by HTTP requests
+    let rest = config(server.rest_endpoint()).rest_synthesizer().unwrap();
+    assert_eq!(rest.synthesize_ssml(SSML).await.unwrap(), server.audio());
+    assert_eq!(
+        syn.synthesize_ssml(SSML).await.unwrap_err().kind,
+        WebsocketSynthesizerErrorKind::WebsocketConnectionClosed {
+            code: "1011".to_string(),
+            reason: "mock failure".to_string()
+        }
+    );
+    // The synthesizer reconnects for the next request
+    assert_eq!(syn.synthesize_ssml(SSML).await.unwrap(), server.audio());
+}
+
+#[tokio::test]
+async fn websocket_pool_stream() {
+    use futures::TryStreamExt;
+
+    let server = server().await;
+    let pool = config(server.websocket_endpoint()).websocket_pool(1);
+    let chunks = pool
+        .synthesize_ssml_stream(SSML)
+        .await
+        .unwrap()
+        .try_collect::<Vec<_>>()
+        .await
+        .unwrap();
+    assert!(chunks.len() > 1);
+    assert_eq!(chunks.concat(), server.audio());
+    // The connection is returned to the pool when the stream is exhausted
+    assert_eq!(pool.synthesize_ssml(SSML).await.unwrap(), server.audio());
+    let handshakes = server
+        .requests()
+        .iter()
+        .map(|r| r.headers.get("Sec-WebSocket-Key").cloned())
+        .collect::<std::collections::HashSet<_>>();
+    assert_eq!(handshakes.len(), 1);
+
+    // Errors before the first chunk are returned by the method
+    server.inject_fault(MockFault::Close {
+        code: 1011,
+        reason: "mock failure".to_string(),
+    });
+    assert!(pool.synthesize_ssml_stream(SSML).await.is_err());
+}